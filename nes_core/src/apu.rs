@@ -0,0 +1,460 @@
+mod blip;
+pub mod dmc;
+mod envelope;
+mod filter;
+mod length_counter;
+pub mod mixer;
+pub mod noise;
+pub mod pulse;
+pub mod recorder;
+pub mod ring_buffer;
+pub mod triangle;
+
+use dmc::Dmc;
+use mixer::{ChannelOutputs, Mixer, MixerState, DEFAULT_SAMPLE_RATE};
+use recorder::Recorder;
+use ring_buffer::RingBuffer;
+use noise::Noise;
+use pulse::Pulse;
+use triangle::Triangle;
+use std::path::Path;
+use crate::savestate::{impl_save_state, SaveState, StateReader, StateWriter};
+
+// フレームカウンターの各ステップ(CPUサイクル, NTSC)
+const FOUR_STEP_SEQUENCE: [u32; 4] = [7457, 14913, 22371, 29829];
+const FIVE_STEP_SEQUENCE: [u32; 4] = [7457, 14913, 22371, 37281];
+const FOUR_STEP_PERIOD: u32 = 29830;
+const FIVE_STEP_PERIOD: u32 = 37282;
+// 4ステップモードでIRQフラグが立つサイクル。最後のサイクルで次のフレームに入る。
+const FRAME_IRQ_CYCLES: [u32; 3] = [29828, 29829, 29830];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FrameMode {
+    FourStep,
+    FiveStep,
+}
+
+impl SaveState for FrameMode {
+    fn save(&self, w: &mut StateWriter) {
+        (*self == FrameMode::FiveStep).save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut five_step = false;
+        five_step.load(r)?;
+        *self = if five_step { FrameMode::FiveStep } else { FrameMode::FourStep };
+        Ok(())
+    }
+}
+
+pub struct Apu {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    frame_mode: FrameMode,
+    frame_cycle: u32,
+    frame_irq_inhibit: bool,
+    frame_irq: bool,
+    // $4017への書き込みは3-4サイクル遅れて反映される。(書き込んだ値, 残りサイクル)
+    frame_counter_write: Option<(u8, u8)>,
+    last_frame_counter_value: u8,
+    mixer: Mixer,
+    // カートリッジの拡張音源の出力(ミックス済みの振幅)
+    expansion_output: f32,
+    cycle: u64,
+}
+
+impl_save_state!(Apu {
+    pulse1,
+    pulse2,
+    triangle,
+    noise,
+    dmc,
+    frame_mode,
+    frame_cycle,
+    frame_irq_inhibit,
+    frame_irq,
+    frame_counter_write,
+    last_frame_counter_value,
+    expansion_output,
+    cycle,
+});
+
+impl Apu {
+    // 電源投入時は$4017に$00を書き込んだ状態から始まる。
+    pub fn new() -> Self {
+        let mut apu = Apu {
+            pulse1: Pulse::new(1),
+            pulse2: Pulse::new(2),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            frame_mode: FrameMode::FourStep,
+            frame_cycle: 0,
+            frame_irq_inhibit: false,
+            frame_irq: false,
+            frame_counter_write: None,
+            last_frame_counter_value: 0,
+            mixer: Mixer::new(DEFAULT_SAMPLE_RATE),
+            expansion_output: 0.0,
+            cycle: 0,
+        };
+        apu.write_frame_counter(0x00);
+        apu
+    }
+
+    // リセット時は全チャンネルが無効になり、$4017は最後に書き込まれた値で再設定される。
+    pub fn reset(&mut self) {
+        self.write_status(0x00);
+        self.frame_irq = false;
+        self.write_frame_counter(self.last_frame_counter_value);
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000 => self.pulse1.write_control(data),
+            0x4001 => self.pulse1.write_sweep(data),
+            0x4002 => self.pulse1.write_timer_low(data),
+            0x4003 => self.pulse1.write_timer_high(data),
+            0x4004 => self.pulse2.write_control(data),
+            0x4005 => self.pulse2.write_sweep(data),
+            0x4006 => self.pulse2.write_timer_low(data),
+            0x4007 => self.pulse2.write_timer_high(data),
+            0x4008 => self.triangle.write_linear_counter(data),
+            0x400A => self.triangle.write_timer_low(data),
+            0x400B => self.triangle.write_timer_high(data),
+            0x400C => self.noise.write_control(data),
+            0x400E => self.noise.write_period(data),
+            0x400F => self.noise.write_length(data),
+            0x4010 => self.dmc.write_control(data),
+            0x4011 => self.dmc.write_output_level(data),
+            0x4012 => self.dmc.write_sample_address(data),
+            0x4013 => self.dmc.write_sample_length(data),
+            0x4015 => self.write_status(data),
+            0x4017 => self.write_frame_counter(data),
+            _ => {}
+        }
+    }
+
+    fn write_status(&mut self, data: u8) {
+        self.pulse1.length_counter.set_enabled(data & 0b0001 != 0);
+        self.pulse2.length_counter.set_enabled(data & 0b0010 != 0);
+        self.triangle.length_counter.set_enabled(data & 0b0100 != 0);
+        self.noise.length_counter.set_enabled(data & 0b1000 != 0);
+        self.dmc.set_enabled(data & 0b1_0000 != 0);
+    }
+
+    // $4015の読み出し。各チャンネルの長さカウンター(DMCは残りバイト数)が0でなければビットが立つ。
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+        if self.pulse1.length_counter.is_active() {
+            status |= 0b0001;
+        }
+        if self.pulse2.length_counter.is_active() {
+            status |= 0b0010;
+        }
+        if self.triangle.length_counter.is_active() {
+            status |= 0b0100;
+        }
+        if self.noise.length_counter.is_active() {
+            status |= 0b1000;
+        }
+        if self.dmc.is_active() {
+            status |= 0b0001_0000;
+        }
+        if self.frame_irq {
+            status |= 0b0100_0000;
+        }
+        if self.dmc.irq() {
+            status |= 0b1000_0000;
+        }
+        // 読むとフレームIRQフラグが下りる。
+        self.frame_irq = false;
+        status
+    }
+
+    // IRQ禁止フラグは即座に反映されるが、モード変更とシーケンサーのリセットは
+    // APUサイクルの途中なら3サイクル後、APUサイクルの間なら4サイクル後に起きる。
+    fn write_frame_counter(&mut self, data: u8) {
+        self.last_frame_counter_value = data;
+        self.frame_irq_inhibit = data & 0b0100_0000 != 0;
+        if self.frame_irq_inhibit {
+            self.frame_irq = false;
+        }
+        let delay = if self.cycle % 2 == 1 { 4 } else { 3 };
+        self.frame_counter_write = Some((data, delay));
+    }
+
+    fn apply_frame_counter_write(&mut self, data: u8) {
+        self.frame_mode = if data & 0b1000_0000 != 0 {
+            FrameMode::FiveStep
+        } else {
+            FrameMode::FourStep
+        };
+        self.frame_cycle = 0;
+        // 5ステップモードに切り替えた時は即座に1/4, 1/2フレームのクロックが入る。
+        if self.frame_mode == FrameMode::FiveStep {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    fn clock_frame_counter(&mut self) {
+        if let Some((data, delay)) = self.frame_counter_write {
+            if delay <= 1 {
+                self.frame_counter_write = None;
+                self.apply_frame_counter_write(data);
+                return;
+            }
+            self.frame_counter_write = Some((data, delay - 1));
+        }
+
+        self.frame_cycle += 1;
+        let (sequence, period) = match self.frame_mode {
+            FrameMode::FourStep => (&FOUR_STEP_SEQUENCE, FOUR_STEP_PERIOD),
+            FrameMode::FiveStep => (&FIVE_STEP_SEQUENCE, FIVE_STEP_PERIOD),
+        };
+        if let Some(step) = sequence.iter().position(|&c| c == self.frame_cycle) {
+            self.clock_quarter_frame();
+            if step == 1 || step == 3 {
+                self.clock_half_frame();
+            }
+        }
+        if self.frame_mode == FrameMode::FourStep
+            && !self.frame_irq_inhibit
+            && FRAME_IRQ_CYCLES.contains(&self.frame_cycle)
+        {
+            self.frame_irq = true;
+        }
+        if self.frame_cycle >= period {
+            self.frame_cycle = 0;
+        }
+    }
+
+    // CPUの1サイクルごとに呼ばれる。
+    pub fn clock(&mut self) {
+        self.clock_frame_counter();
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.cycle % 2 == 1 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        let outputs = ChannelOutputs {
+            pulse1: self.pulse1.output(),
+            pulse2: self.pulse2.output(),
+            triangle: self.triangle.output(),
+            noise: self.noise.output(),
+            dmc: self.dmc.output(),
+            expansion: self.expansion_output,
+        };
+        self.mixer.clock(&outputs);
+        self.cycle += 1;
+    }
+
+    // ホストのサンプルレートに変換済みのサンプル
+    pub fn samples(&mut self) -> &mut RingBuffer {
+        self.mixer.samples()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.mixer.sample_rate()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.mixer.set_sample_rate(sample_rate);
+    }
+
+    pub fn adjust_sample_rate(&mut self, factor: f64) {
+        self.mixer.adjust_rate(factor);
+    }
+
+    pub fn set_expansion_output(&mut self, amplitude: f32) {
+        self.expansion_output = amplitude;
+    }
+
+    // 以降の出力をWAVに書き出す。stemsならチャンネル別のファイルも書く。
+    pub fn start_recording(&mut self, path: &Path, stems: bool) -> Result<(), String> {
+        let recorder = Recorder::new(path, self.mixer.sample_rate(), stems)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        self.mixer.start_recording(recorder);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<(), String> {
+        match self.mixer.stop_recording() {
+            Some(recorder) => {
+                let path = recorder.path().display().to_string();
+                recorder
+                    .finish()
+                    .map_err(|e| format!("Failed to write {}: {}", path, e))
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.mixer.is_recording()
+    }
+
+    // 後で巻き戻す区間の音を録音やホストの出力に残さないよう、ミキサーを控えておく。
+    pub fn suspend_output(&mut self) -> MixerState {
+        self.mixer.suspend()
+    }
+
+    pub fn resume_output(&mut self, state: MixerState) {
+        self.mixer.resume(state);
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq()
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_length_counter_status() {
+        let mut apu = Apu::new();
+        // 無効のチャンネルには長さがロードされない。
+        apu.write_register(0x4003, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0);
+
+        apu.write_register(0x4015, 0b0000_1111);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.write_register(0x400F, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0b1001);
+
+        apu.write_register(0x4015, 0b0000_0001);
+        assert_eq!(apu.read_status(), 0b0001);
+    }
+
+    fn clock_n(apu: &mut Apu, cycles: u32) {
+        for _ in 0..cycles {
+            apu.clock();
+        }
+    }
+
+    #[test]
+    fn test_length_counter_clocked_by_frame_counter() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        // 長さ2(インデックス3)
+        apu.write_register(0x4003, 0b0001_1000);
+
+        // 電源投入時の$4017書き込みが3サイクル後に反映される。
+        clock_n(&mut apu, 3 + 14913);
+        assert_eq!(apu.read_status(), 0b0001);
+        clock_n(&mut apu, 29829 - 14913);
+        assert_eq!(apu.read_status() & 0b0001_1111, 0);
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu = Apu::new();
+        clock_n(&mut apu, 3 + 29827);
+        assert!(!apu.irq());
+        clock_n(&mut apu, 1);
+        assert!(apu.irq());
+
+        // $4015を読むとフラグが下りる。ただし最後のサイクルまでは立ち続ける。
+        assert_eq!(apu.read_status() & 0b0100_0000, 0b0100_0000);
+        assert!(!apu.irq());
+        clock_n(&mut apu, 2);
+        assert!(apu.irq());
+        apu.read_status();
+        clock_n(&mut apu, 1);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_frame_irq_inhibit() {
+        let mut apu = Apu::new();
+        clock_n(&mut apu, 3 + 29828);
+        assert!(apu.irq());
+        apu.write_register(0x4017, 0b0100_0000);
+        assert!(!apu.irq());
+        clock_n(&mut apu, 29830 * 2);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_five_step_mode_has_no_irq() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4017, 0b1000_0000);
+        clock_n(&mut apu, 37282 * 2);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_frame_counter_write_delay() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4003, 0b0001_1000);
+        clock_n(&mut apu, 3);
+
+        // 奇数サイクルでの書き込みは4サイクル後に反映される。
+        apu.write_register(0x4017, 0b1000_0000);
+        clock_n(&mut apu, 3);
+        assert_eq!(apu.frame_mode, FrameMode::FourStep);
+        clock_n(&mut apu, 1);
+        assert_eq!(apu.frame_mode, FrameMode::FiveStep);
+        assert_eq!(apu.read_status(), 0b0001);
+
+        // 偶数サイクルでの書き込みは3サイクル後
+        clock_n(&mut apu, 1);
+        apu.write_register(0x4017, 0b1000_0000);
+        clock_n(&mut apu, 3);
+        assert_eq!(apu.read_status(), 0);
+    }
+
+    #[test]
+    fn test_reset_silences_channels() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4003, 0b0001_1000);
+        apu.write_register(0x4017, 0b1100_0000);
+        apu.reset();
+
+        assert_eq!(apu.read_status(), 0);
+        assert!(apu.frame_irq_inhibit);
+    }
+
+    #[test]
+    fn test_five_step_mode_clocks_immediately() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        // 長さ2
+        apu.write_register(0x4003, 0b0001_1000);
+        apu.write_register(0x4017, 0b1000_0000);
+        clock_n(&mut apu, 3);
+        apu.write_register(0x4017, 0b1000_0000);
+        clock_n(&mut apu, 4);
+        assert_eq!(apu.read_status(), 0);
+    }
+}
@@ -0,0 +1,136 @@
+use std::f64::consts::PI;
+
+// 帯域制限したステップを合成するバッファ(blip_buf方式)。
+// 振幅の変化(デルタ)を窓関数付きsincのインパルスとして出力サンプル列に足し込み、
+// 読み出す時に積分してステップに戻す。CPUクロックのまま間引くとエイリアスがひどい。
+const KERNEL_WIDTH: usize = 16;
+const PHASES: usize = 64;
+// ナイキスト周波数に対するカットオフの割合
+const CUTOFF: f64 = 0.9;
+
+#[derive(Clone)]
+pub struct BlipBuffer {
+    // 1クロックあたりの出力サンプル数
+    ratio: f64,
+    // 現在のフレームの開始位置(buf先頭からのサンプル数、小数部あり)
+    offset: f64,
+    buf: Vec<f32>,
+    integrator: f32,
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        BlipBuffer {
+            ratio: sample_rate / clock_rate,
+            offset: 0.0,
+            buf: vec![0.0; KERNEL_WIDTH],
+            integrator: 0.0,
+            kernel: build_kernel(),
+        }
+    }
+
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.ratio = sample_rate / clock_rate;
+    }
+
+    // フレーム開始からtimeクロック目で出力がdelta変化した。
+    pub fn add_delta(&mut self, time: u32, delta: f32) {
+        let pos = self.offset + time as f64 * self.ratio;
+        let index = pos as usize;
+        let phase = ((pos - index as f64) * PHASES as f64) as usize;
+        if self.buf.len() < index + KERNEL_WIDTH {
+            self.buf.resize(index + KERNEL_WIDTH, 0.0);
+        }
+        for (out, k) in self.buf[index..].iter_mut().zip(self.kernel[phase].iter()) {
+            *out += delta * k;
+        }
+    }
+
+    // timeクロック分でフレームを閉じ、その分のサンプルを読み出せるようにする。
+    pub fn end_frame(&mut self, time: u32) {
+        self.offset += time as f64 * self.ratio;
+        let needed = self.offset as usize + KERNEL_WIDTH;
+        if self.buf.len() < needed {
+            self.buf.resize(needed, 0.0);
+        }
+    }
+
+    pub fn samples_avail(&self) -> usize {
+        self.offset as usize
+    }
+
+    // 読み出せるサンプルを全てoutに追加する。
+    pub fn read_samples(&mut self, out: &mut Vec<f32>) {
+        let avail = self.samples_avail();
+        for &delta in &self.buf[..avail] {
+            self.integrator += delta;
+            out.push(self.integrator);
+        }
+        self.buf.drain(..avail);
+        self.offset -= avail as f64;
+    }
+}
+
+// 各位相ごとに、合計が1になるよう正規化したインパルス応答を作る。
+fn build_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+    let half = (KERNEL_WIDTH / 2) as f64;
+    (0..PHASES)
+        .map(|phase| {
+            let frac = phase as f64 / PHASES as f64;
+            let mut taps = [0.0f64; KERNEL_WIDTH];
+            for (k, tap) in taps.iter_mut().enumerate() {
+                let x = k as f64 - half - frac;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * CUTOFF * x).sin() / (PI * CUTOFF * x)
+                };
+                // Blackman窓
+                let w = 2.0 * PI * (x / KERNEL_WIDTH as f64 + 0.5);
+                let window = 0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+                *tap = sinc * window.max(0.0);
+            }
+            let sum: f64 = taps.iter().sum();
+            let mut kernel = [0.0f32; KERNEL_WIDTH];
+            for (out, tap) in kernel.iter_mut().zip(taps.iter()) {
+                *out = (tap / sum) as f32;
+            }
+            kernel
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_step_settles_to_amplitude() {
+        let mut blip = BlipBuffer::new(1_789_773.0, 44_100.0);
+        blip.add_delta(100, 1.0);
+        blip.end_frame(29780);
+
+        let mut out = Vec::new();
+        blip.read_samples(&mut out);
+
+        // 29780 * 44100 / 1789773 = 733.7
+        assert_eq!(out.len(), 733);
+        assert!(out[0].abs() < 1e-6);
+        assert!((out[out.len() - 1] - 1.0).abs() < 1e-4);
+        // 帯域制限したステップのリンギング(ギブス現象)は1割程度に収まる。
+        assert!(out.iter().all(|&s| (-0.15..1.15).contains(&s)));
+    }
+
+    #[test]
+    fn test_fraction_carries_over_frames() {
+        let mut blip = BlipBuffer::new(1_789_773.0, 48_000.0);
+        let mut out = Vec::new();
+        for _ in 0..60 {
+            blip.end_frame(29830);
+            blip.read_samples(&mut out);
+        }
+        // 60 * 29830 * 48000 / 1789773 = 48000.8
+        assert_eq!(out.len(), 48000);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::savestate::impl_save_state;
+
+// NTSCのサンプル周期(CPUサイクル)
+const RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+// デルタ変調チャンネル($4010-$4013)
+pub struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+    output_level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    irq: bool,
+}
+
+impl_save_state!(Dmc {
+    irq_enabled,
+    looping,
+    timer_period,
+    timer,
+    output_level,
+    sample_address,
+    sample_length,
+    current_address,
+    bytes_remaining,
+    sample_buffer,
+    shift_register,
+    bits_remaining,
+    silence,
+    irq,
+});
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            timer_period: RATE_TABLE[0],
+            timer: RATE_TABLE[0],
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+}
+
+impl Dmc {
+    pub fn write_control(&mut self, data: u8) {
+        self.irq_enabled = data & 0b1000_0000 != 0;
+        self.looping = data & 0b0100_0000 != 0;
+        self.timer_period = RATE_TABLE[(data & 0b1111) as usize];
+        if !self.irq_enabled {
+            self.irq = false;
+        }
+    }
+
+    pub fn write_output_level(&mut self, data: u8) {
+        self.output_level = data & 0b0111_1111;
+    }
+
+    // サンプルは$C000 + A * 64から
+    pub fn write_sample_address(&mut self, data: u8) {
+        self.sample_address = 0xC000 | (data as u16) << 6;
+    }
+
+    // 長さはL * 16 + 1バイト
+    pub fn write_sample_length(&mut self, data: u8) {
+        self.sample_length = (data as u16) << 4 | 1;
+    }
+
+    // $4015のbit4。無効にすると残りバイト数が0になり、有効にした時に残りが無ければ再生し直す。
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    // サンプルバッファが空で残りがあれば、DMAで読み込むアドレスを返す。
+    pub fn dma_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    // DMAで読み込んだバイトをサンプルバッファに入れる。
+    pub fn fill_sample_buffer(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        self.current_address = if self.current_address == 0xFFFF {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // CPUサイクルごとに呼ばれる。
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift_register = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample_fetch_and_irq() {
+        let mut dmc = Dmc::default();
+        dmc.write_control(0b1000_0000);
+        dmc.write_sample_address(0xFF);
+        dmc.write_sample_length(0x00);
+        dmc.set_enabled(true);
+
+        assert_eq!(dmc.dma_request(), Some(0xFFC0));
+        dmc.fill_sample_buffer(0xFF);
+        assert_eq!(dmc.dma_request(), None);
+        assert!(!dmc.is_active());
+        assert!(dmc.irq());
+    }
+
+    #[test]
+    fn test_address_wraps_to_8000() {
+        let mut dmc = Dmc {
+            current_address: 0xFFFF,
+            bytes_remaining: 2,
+            ..Dmc::default()
+        };
+        dmc.fill_sample_buffer(0);
+        assert_eq!(dmc.current_address, 0x8000);
+    }
+
+    #[test]
+    fn test_looping_sample_restarts() {
+        let mut dmc = Dmc::default();
+        dmc.write_control(0b1100_0000);
+        dmc.write_sample_length(0x00);
+        dmc.set_enabled(true);
+        dmc.fill_sample_buffer(0);
+        assert!(dmc.is_active());
+        assert!(!dmc.irq());
+    }
+
+    #[test]
+    fn test_output_follows_sample_bits() {
+        let mut dmc = Dmc::default();
+        dmc.write_output_level(64);
+        dmc.write_control(0x0F);
+        dmc.write_sample_length(0x00);
+        dmc.set_enabled(true);
+        dmc.fill_sample_buffer(0b0000_0011);
+
+        // 最初の8bitは無音。タイマーは初期値の428から数え始める。
+        for _ in 0..429 + 7 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 64);
+        for _ in 0..2 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 68);
+        for _ in 0..54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 66);
+    }
+}
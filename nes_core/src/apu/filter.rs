@@ -0,0 +1,71 @@
+use std::f32::consts::PI;
+
+// NES本体の出力段にある1次フィルター。(ハイパス90Hz, ハイパス440Hz, ローパス14kHz)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+#[derive(Clone)]
+pub struct Filter {
+    kind: FilterKind,
+    cutoff: f32,
+    alpha: f32,
+    prev_input: f32,
+    prev_output: f32,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, cutoff: f32, sample_rate: f32) -> Self {
+        let mut filter = Filter {
+            kind,
+            cutoff,
+            alpha: 0.0,
+            prev_input: 0.0,
+            prev_output: 0.0,
+        };
+        filter.set_sample_rate(sample_rate);
+        filter
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        let rc = 1.0 / (2.0 * PI * self.cutoff);
+        let dt = 1.0 / sample_rate;
+        self.alpha = match self.kind {
+            FilterKind::HighPass => rc / (rc + dt),
+            FilterKind::LowPass => dt / (rc + dt),
+        };
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            FilterKind::HighPass => self.alpha * (self.prev_output + input - self.prev_input),
+            FilterKind::LowPass => self.prev_output + self.alpha * (input - self.prev_output),
+        };
+        self.prev_input = input;
+        self.prev_output = output;
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_high_pass_removes_dc() {
+        let mut filter = Filter::new(FilterKind::HighPass, 90.0, 44_100.0);
+        let mut output = 0.0;
+        for _ in 0..44_100 {
+            output = filter.process(0.5);
+        }
+        assert!(output.abs() < 1e-3);
+
+        let mut filter = Filter::new(FilterKind::LowPass, 14_000.0, 44_100.0);
+        for _ in 0..100 {
+            output = filter.process(0.5);
+        }
+        assert!((output - 0.5).abs() < 1e-3);
+    }
+}
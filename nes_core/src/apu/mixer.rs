@@ -0,0 +1,305 @@
+use super::blip::BlipBuffer;
+use super::filter::{Filter, FilterKind};
+use super::recorder::Recorder;
+use super::ring_buffer::RingBuffer;
+
+// NTSCのCPUクロック(Hz)
+pub const CPU_CLOCK_RATE: f64 = 1_789_773.0;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+// この間隔(CPUサイクル)ごとにblipバッファからサンプルを取り出す。
+const MIX_FRAME_CLOCKS: u32 = 4096;
+// リングバッファには0.5秒分まで溜める。
+const RING_BUFFER_SECONDS: f32 = 0.5;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+    Expansion,
+}
+
+pub const CHANNELS: [Channel; 6] = [
+    Channel::Pulse1,
+    Channel::Pulse2,
+    Channel::Triangle,
+    Channel::Noise,
+    Channel::Dmc,
+    Channel::Expansion,
+];
+
+impl Channel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::Dmc => "dmc",
+            Channel::Expansion => "expansion",
+        }
+    }
+}
+
+// ある瞬間の各チャンネルの出力
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ChannelOutputs {
+    pub pulse1: u8,
+    pub pulse2: u8,
+    pub triangle: u8,
+    pub noise: u8,
+    pub dmc: u8,
+    // 拡張音源はミックス済みの振幅で渡す。
+    pub expansion: f32,
+}
+
+impl ChannelOutputs {
+    // 指定したチャンネル以外を無音にする。(チャンネル別の録音用)
+    pub fn solo(&self, channel: Channel) -> ChannelOutputs {
+        let mut solo = ChannelOutputs::default();
+        match channel {
+            Channel::Pulse1 => solo.pulse1 = self.pulse1,
+            Channel::Pulse2 => solo.pulse2 = self.pulse2,
+            Channel::Triangle => solo.triangle = self.triangle,
+            Channel::Noise => solo.noise = self.noise,
+            Channel::Dmc => solo.dmc = self.dmc,
+            Channel::Expansion => solo.expansion = self.expansion,
+        }
+        solo
+    }
+}
+
+// チャンネル出力の非線形ミキシング。(nesdev wikiの近似式から作ったテーブル)
+struct MixTables {
+    pulse: [f32; 31],
+    tnd: [f32; 203],
+}
+
+impl MixTables {
+    fn new() -> Self {
+        let mut pulse = [0.0; 31];
+        for (n, entry) in pulse.iter_mut().enumerate().skip(1) {
+            *entry = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        let mut tnd = [0.0; 203];
+        for (n, entry) in tnd.iter_mut().enumerate().skip(1) {
+            *entry = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        MixTables { pulse, tnd }
+    }
+
+    fn mix(&self, outputs: &ChannelOutputs) -> f32 {
+        let pulse = self.pulse[(outputs.pulse1 + outputs.pulse2) as usize];
+        let tnd = self.tnd
+            [3 * outputs.triangle as usize + 2 * outputs.noise as usize + outputs.dmc as usize];
+        pulse + tnd + outputs.expansion
+    }
+}
+
+// 振幅の変化をblipバッファに入れ、ホストのレートで出力フィルターを通す。
+#[derive(Clone)]
+pub struct Resampler {
+    sample_rate: u32,
+    blip: BlipBuffer,
+    filters: [Filter; 3],
+    last_amplitude: f32,
+    scratch: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate as f32;
+        Resampler {
+            sample_rate,
+            blip: BlipBuffer::new(CPU_CLOCK_RATE, sample_rate as f64),
+            filters: [
+                Filter::new(FilterKind::HighPass, 90.0, rate),
+                Filter::new(FilterKind::HighPass, 440.0, rate),
+                Filter::new(FilterKind::LowPass, 14_000.0, rate),
+            ],
+            last_amplitude: 0.0,
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn adjust_rate(&mut self, factor: f64) {
+        self.blip.set_rates(CPU_CLOCK_RATE, self.sample_rate as f64 * factor);
+    }
+
+    // フレーム開始からtimeクロック目の振幅
+    pub fn update(&mut self, time: u32, amplitude: f32) {
+        if amplitude != self.last_amplitude {
+            self.blip.add_delta(time, amplitude - self.last_amplitude);
+            self.last_amplitude = amplitude;
+        }
+    }
+
+    // フレームを閉じ、フィルター済みのサンプルを返す。
+    pub fn end_frame(&mut self, time: u32) -> &[f32] {
+        self.blip.end_frame(time);
+        self.scratch.clear();
+        self.blip.read_samples(&mut self.scratch);
+        for sample in self.scratch.iter_mut() {
+            *sample = self.filters.iter_mut().fold(*sample, |s, f| f.process(s));
+        }
+        &self.scratch
+    }
+}
+
+// 先行実行のように後で巻き戻す区間の前に控えておくミキサーの状態。録音はこの間外しておく。
+pub struct MixerState {
+    resampler: Resampler,
+    ring: RingBuffer,
+    recorder: Option<Recorder>,
+    time: u32,
+}
+
+pub struct Mixer {
+    tables: MixTables,
+    sample_rate: u32,
+    resampler: Resampler,
+    ring: RingBuffer,
+    recorder: Option<Recorder>,
+    time: u32,
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32) -> Self {
+        Mixer {
+            tables: MixTables::new(),
+            sample_rate,
+            resampler: Resampler::new(sample_rate),
+            ring: RingBuffer::new((sample_rate as f32 * RING_BUFFER_SECONDS) as usize),
+            recorder: None,
+            time: 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        *self = Mixer::new(sample_rate);
+    }
+
+    // 動的レート制御用。factor倍のサンプルレートで出力する。(フィルターは元のレートのまま)
+    pub fn adjust_rate(&mut self, factor: f64) {
+        self.resampler.adjust_rate(factor);
+    }
+
+    // 0.0〜約1.0の振幅を返す。
+    pub fn mix(&self, outputs: &ChannelOutputs) -> f32 {
+        self.tables.mix(outputs)
+    }
+
+    // CPUの1サイクルごとに、その時点の各チャンネルの出力を渡す。
+    pub fn clock(&mut self, outputs: &ChannelOutputs) {
+        self.resampler.update(self.time, self.tables.mix(outputs));
+        if let Some(recorder) = &mut self.recorder {
+            for (channel, stem) in recorder.stems_mut() {
+                stem.update(self.time, self.tables.mix(&outputs.solo(*channel)));
+            }
+        }
+        self.time += 1;
+        if self.time >= MIX_FRAME_CLOCKS {
+            self.end_frame();
+        }
+    }
+
+    fn end_frame(&mut self) {
+        let samples = self.resampler.end_frame(self.time);
+        for &sample in samples {
+            self.ring.push(sample);
+        }
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.write(samples, self.time) {
+                eprintln!("Recording stopped: {}", e);
+                self.recorder = None;
+            }
+        }
+        self.time = 0;
+    }
+
+    pub fn samples(&mut self) -> &mut RingBuffer {
+        &mut self.ring
+    }
+
+    pub fn start_recording(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recorder> {
+        self.recorder.take()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    // resumeまでの出力はWAVに書かず、resumeでリサンプラーとリングバッファをこの時点に戻す。
+    pub fn suspend(&mut self) -> MixerState {
+        MixerState {
+            resampler: self.resampler.clone(),
+            ring: self.ring.clone(),
+            recorder: self.recorder.take(),
+            time: self.time,
+        }
+    }
+
+    pub fn resume(&mut self, state: MixerState) {
+        self.resampler = state.resampler;
+        self.ring = state.ring;
+        self.recorder = state.recorder;
+        self.time = state.time;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn outputs(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> ChannelOutputs {
+        ChannelOutputs {
+            pulse1,
+            pulse2,
+            triangle,
+            noise,
+            dmc,
+            expansion: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_mix_tables() {
+        let mixer = Mixer::new(DEFAULT_SAMPLE_RATE);
+        assert_eq!(mixer.mix(&outputs(0, 0, 0, 0, 0)), 0.0);
+        assert!((mixer.mix(&outputs(15, 15, 0, 0, 0)) - 0.2584).abs() < 1e-3);
+        assert!((mixer.mix(&outputs(0, 0, 15, 15, 127)) - 0.7417).abs() < 1e-3);
+        // 非線形なので2チャンネル分は1チャンネルの2倍より小さい。
+        assert!(mixer.mix(&outputs(15, 15, 0, 0, 0)) < 2.0 * mixer.mix(&outputs(15, 0, 0, 0, 0)));
+    }
+
+    #[test]
+    fn test_square_wave_is_resampled() {
+        let mut mixer = Mixer::new(48_000);
+        // 約1kHzの矩形波を0.25秒分
+        for cycle in 0..CPU_CLOCK_RATE as u32 / 4 {
+            let high = (cycle / 895) % 2 == 0;
+            mixer.clock(&outputs(if high { 10 } else { 0 }, 0, 0, 0, 0));
+        }
+
+        let ring = mixer.samples();
+        assert!((11_500..=12_000).contains(&ring.len()));
+        let mut out = vec![0.0; ring.len()];
+        ring.read(&mut out);
+        // ハイパスでDCが抜け、0を中心に振れる。
+        let tail = &out[out.len() - 4800..];
+        let mean: f32 = tail.iter().sum::<f32>() / tail.len() as f32;
+        assert!(mean.abs() < 0.01);
+        assert!(tail.iter().any(|&s| s > 0.05));
+        assert!(tail.iter().any(|&s| s < -0.05));
+    }
+}
@@ -0,0 +1,113 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::savestate::impl_save_state;
+
+// NTSCのノイズ周期(CPUサイクル)
+const PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+// ノイズチャンネル($400C-$400F)
+pub struct Noise {
+    // 短周期モードではbit6、通常はbit1をフィードバックに使う。
+    short_mode: bool,
+    shift_register: u16,
+    timer_period: u16,
+    timer: u16,
+    envelope: Envelope,
+    pub(super) length_counter: LengthCounter,
+}
+
+impl_save_state!(Noise {
+    short_mode,
+    shift_register,
+    timer_period,
+    timer,
+    envelope,
+    length_counter,
+});
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise {
+            short_mode: false,
+            shift_register: 1,
+            timer_period: PERIOD_TABLE[0],
+            timer: 0,
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+        }
+    }
+}
+
+impl Noise {
+    pub fn write_control(&mut self, data: u8) {
+        self.length_counter.set_halt(data & 0b0010_0000 != 0);
+        self.envelope.write_control(data);
+    }
+
+    pub fn write_period(&mut self, data: u8) {
+        self.short_mode = data & 0b1000_0000 != 0;
+        self.timer_period = PERIOD_TABLE[(data & 0b1111) as usize];
+    }
+
+    pub fn write_length(&mut self, data: u8) {
+        self.length_counter.load(data >> 3);
+        self.envelope.restart();
+    }
+
+    // 周期表がCPUサイクル単位なので、CPUサイクルごとに呼ぶ。
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 1 != 0 || !self.length_counter.is_active() {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn period_of(noise: &mut Noise) -> usize {
+        let start = noise.shift_register;
+        let mut steps = 0;
+        loop {
+            noise.timer = 0;
+            noise.clock_timer();
+            steps += 1;
+            if noise.shift_register == start {
+                return steps;
+            }
+        }
+    }
+
+    #[test]
+    fn test_lfsr_period() {
+        let mut noise = Noise::default();
+        assert_eq!(period_of(&mut noise), 32767);
+
+        let mut noise = Noise::default();
+        noise.write_period(0b1000_0000);
+        assert_eq!(period_of(&mut noise), 93);
+    }
+}
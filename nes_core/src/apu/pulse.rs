@@ -0,0 +1,201 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::savestate::impl_save_state;
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// 矩形波チャンネル($4000-$4003, $4004-$4007)
+pub struct Pulse {
+    // スイープで減算する時、パルス1は1の補数、パルス2は2の補数になる。
+    ones_complement: bool,
+    // MMC5の矩形波にはスイープユニットがなく、周期による消音も起きない。
+    has_sweep: bool,
+    duty: u8,
+    sequence: u8,
+    timer_period: u16,
+    timer: u16,
+    envelope: Envelope,
+    pub(super) length_counter: LengthCounter,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl_save_state!(Pulse {
+    duty,
+    sequence,
+    timer_period,
+    timer,
+    envelope,
+    length_counter,
+    sweep_enabled,
+    sweep_period,
+    sweep_negate,
+    sweep_shift,
+    sweep_divider,
+    sweep_reload,
+});
+
+impl Pulse {
+    pub fn new(channel: u8) -> Self {
+        Pulse {
+            ones_complement: channel == 1,
+            has_sweep: true,
+            duty: 0,
+            sequence: 0,
+            timer_period: 0,
+            timer: 0,
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    pub fn without_sweep() -> Self {
+        Pulse {
+            has_sweep: false,
+            ..Pulse::new(2)
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
+    pub fn write_control(&mut self, data: u8) {
+        self.duty = data >> 6;
+        self.length_counter.set_halt(data & 0b0010_0000 != 0);
+        self.envelope.write_control(data);
+    }
+
+    pub fn write_sweep(&mut self, data: u8) {
+        self.sweep_enabled = data & 0b1000_0000 != 0;
+        self.sweep_period = (data >> 4) & 0b111;
+        self.sweep_negate = data & 0b0000_1000 != 0;
+        self.sweep_shift = data & 0b111;
+        self.sweep_reload = true;
+    }
+
+    pub fn write_timer_low(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0xFF00) | data as u16;
+    }
+
+    pub fn write_timer_high(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x00FF) | ((data & 0b111) as u16) << 8;
+        self.length_counter.load(data >> 3);
+        self.sequence = 0;
+        self.envelope.restart();
+    }
+
+    // タイマーはAPUサイクル(CPU2サイクル)ごとに進む。
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence = (self.sequence + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.is_muted() {
+            self.timer_period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if self.sweep_negate {
+            let change = if self.ones_complement { change + 1 } else { change };
+            self.timer_period.saturating_sub(change)
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    // スイープが無効でも、周期が範囲外になる場合は消音される。
+    fn is_muted(&self) -> bool {
+        if !self.has_sweep {
+            return false;
+        }
+        self.timer_period < 8 || self.sweep_target() > 0x7FF
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.is_active()
+            || self.is_muted()
+            || DUTY_TABLE[self.duty as usize][self.sequence as usize] == 0
+        {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sweep_negate() {
+        let mut pulse1 = Pulse::new(1);
+        let mut pulse2 = Pulse::new(2);
+        for pulse in [&mut pulse1, &mut pulse2] {
+            pulse.write_timer_low(0x00);
+            pulse.write_timer_high(0x01);
+            // 有効, 周期0, 減算, シフト1
+            pulse.write_sweep(0b1000_1001);
+        }
+
+        assert_eq!(pulse1.sweep_target(), 0x100 - 0x80 - 1);
+        assert_eq!(pulse2.sweep_target(), 0x100 - 0x80);
+
+        pulse1.clock_half_frame();
+        assert_eq!(pulse1.timer_period, 0x7F);
+    }
+
+    #[test]
+    fn test_muted_by_sweep_target() {
+        let mut pulse = Pulse::new(2);
+        pulse.length_counter.set_enabled(true);
+        pulse.write_control(0b1001_1111);
+        pulse.write_timer_low(0x00);
+        pulse.write_timer_high(0x06);
+        pulse.write_sweep(0b0000_0010);
+        pulse.sequence = 1;
+        assert_eq!(pulse.output(), 15);
+
+        // スイープが無効でも、加算結果が$7FFを超える場合は消音
+        pulse.write_sweep(0b0000_0001);
+        assert_eq!(pulse.output(), 0);
+    }
+}
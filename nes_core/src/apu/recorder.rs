@@ -0,0 +1,128 @@
+use super::mixer::{Channel, Resampler, CHANNELS};
+use crate::wav::WavWriter;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+type Writer = WavWriter<BufWriter<File>>;
+
+struct Stem {
+    channel: Channel,
+    resampler: Resampler,
+    writer: Writer,
+}
+
+// ミックスした出力をWAVに書き出す。stemsを指定するとチャンネル別のファイルも書く。
+pub struct Recorder {
+    path: PathBuf,
+    writer: Writer,
+    stems: Vec<Stem>,
+}
+
+impl Recorder {
+    pub fn new(path: &Path, sample_rate: u32, stems: bool) -> io::Result<Self> {
+        let mut recorder = Recorder {
+            path: path.to_path_buf(),
+            writer: WavWriter::create(path, sample_rate)?,
+            stems: Vec::new(),
+        };
+        if stems {
+            for channel in CHANNELS {
+                recorder.stems.push(Stem {
+                    channel,
+                    resampler: Resampler::new(sample_rate),
+                    writer: WavWriter::create(&stem_path(path, channel), sample_rate)?,
+                });
+            }
+        }
+        Ok(recorder)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(super) fn stems_mut(&mut self) -> impl Iterator<Item = (&Channel, &mut Resampler)> {
+        self.stems.iter_mut().map(|stem| (&stem.channel, &mut stem.resampler))
+    }
+
+    // ミックス済みのサンプルを書き、チャンネル別の方もtimeクロックでフレームを閉じて書く。
+    pub(super) fn write(&mut self, samples: &[f32], time: u32) -> io::Result<()> {
+        self.writer.write_samples(samples)?;
+        for stem in self.stems.iter_mut() {
+            let samples = stem.resampler.end_frame(time);
+            stem.writer.write_samples(samples)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.writer.finish()?;
+        for stem in self.stems {
+            stem.writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// foo.wav -> foo.pulse1.wav
+pub fn stem_path(path: &Path, channel: Channel) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}.wav", stem, channel.name()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apu::Apu;
+
+    #[test]
+    fn test_stem_path() {
+        assert_eq!(
+            stem_path(Path::new("out/song.wav"), Channel::Triangle),
+            PathBuf::from("out/song.triangle.wav")
+        );
+    }
+
+    #[test]
+    fn test_record_stems() {
+        let dir = std::env::temp_dir().join(format!("nes_record_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("song.wav");
+
+        let mut apu = Apu::new();
+        apu.start_recording(&path, true).unwrap();
+        // 矩形波1を鳴らす。(デューティ50%, 固定音量15)
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1011_1111);
+        apu.write_register(0x4002, 0xFD);
+        apu.write_register(0x4003, 0x00);
+        for _ in 0..29830 * 6 {
+            apu.clock();
+        }
+        apu.stop_recording().unwrap();
+
+        let main = std::fs::read(&path).unwrap();
+        let pulse1 = std::fs::read(stem_path(&path, Channel::Pulse1)).unwrap();
+        let noise = std::fs::read(stem_path(&path, Channel::Noise)).unwrap();
+        assert!(main.len() > 44 + 2 * 4000);
+        assert_eq!(main.len(), pulse1.len());
+        assert_eq!(main.len(), noise.len());
+        // ノイズは鳴らしていない。
+        assert!(noise[44..].iter().all(|&b| b == 0));
+        // 三角波の出力は止まっていても0ではないが、その分のDCがハイパスで抜けた後は
+        // ミックスと矩形波1のチャンネルがほぼ一致する。
+        let samples = |wav: &[u8]| -> Vec<i16> {
+            wav[44..].chunks(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect()
+        };
+        let (main, pulse1) = (samples(&main), samples(&pulse1));
+        let tail = main.len() / 2;
+        assert!(main[tail..].iter().any(|&s| s > 1000));
+        assert!(main[tail..]
+            .iter()
+            .zip(&pulse1[tail..])
+            .all(|(a, b)| (a - b).abs() < 100));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
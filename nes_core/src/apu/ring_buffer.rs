@@ -0,0 +1,80 @@
+// 固定長のサンプルのリングバッファ。溢れた時は古いサンプルから捨てる。
+#[derive(Clone)]
+pub struct RingBuffer {
+    data: Vec<f32>,
+    read: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        RingBuffer {
+            data: vec![0.0; capacity],
+            read: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        let capacity = self.capacity();
+        let write = (self.read + self.len) % capacity;
+        self.data[write] = sample;
+        if self.len == capacity {
+            self.read = (self.read + 1) % capacity;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    // outに読み出したサンプル数を返す。
+    pub fn read(&mut self, out: &mut [f32]) -> usize {
+        let count = out.len().min(self.len);
+        for sample in out[..count].iter_mut() {
+            *sample = self.data[self.read];
+            self.read = (self.read + 1) % self.capacity();
+        }
+        self.len -= count;
+        count
+    }
+
+    pub fn clear(&mut self) {
+        self.read = 0;
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wrap_and_overflow() {
+        let mut ring = RingBuffer::new(4);
+        for i in 0..6 {
+            ring.push(i as f32);
+        }
+        assert_eq!(ring.len(), 4);
+
+        let mut out = [0.0; 3];
+        assert_eq!(ring.read(&mut out), 3);
+        assert_eq!(out, [2.0, 3.0, 4.0]);
+
+        ring.push(6.0);
+        let mut out = [0.0; 4];
+        assert_eq!(ring.read(&mut out), 2);
+        assert_eq!(out[..2], [5.0, 6.0]);
+        assert!(ring.is_empty());
+    }
+}
@@ -0,0 +1,61 @@
+use crate::joypad::JoypadButton;
+
+pub const DEFAULT_TURBO_RATE: u32 = 2;
+
+// 連射。押している間、rateフレーム押してrateフレーム離すのを繰り返す。
+// 実時間ではなくフレームで数えるので、ムービーの再生やスロー実行でも同じ入力になる。
+pub struct Turbo {
+    rate: u32,
+    // ボタン(bit)ごとの押し続けているフレーム数
+    held_frames: [u32; 8],
+}
+
+impl Default for Turbo {
+    fn default() -> Self {
+        Turbo::new(DEFAULT_TURBO_RATE)
+    }
+}
+
+impl Turbo {
+    pub fn new(rate: u32) -> Self {
+        Turbo {
+            rate: rate.max(1),
+            held_frames: [0; 8],
+        }
+    }
+
+    // 1フレームに1回呼ぶ。heldは連射ボタンとして押されているボタン
+    pub fn next_frame(&mut self, held: JoypadButton) -> JoypadButton {
+        let mut pressed = JoypadButton::empty();
+        for (bit, frames) in self.held_frames.iter_mut().enumerate() {
+            let button = JoypadButton::from_bits_truncate(1 << bit);
+            if !held.contains(button) {
+                *frames = 0;
+                continue;
+            }
+            if (*frames / self.rate).is_multiple_of(2) {
+                pressed |= button;
+            }
+            *frames += 1;
+        }
+        pressed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_turbo_rate() {
+        let mut turbo = Turbo::new(2);
+        let frames: Vec<bool> = (0..8)
+            .map(|_| turbo.next_frame(JoypadButton::A).contains(JoypadButton::A))
+            .collect();
+        assert_eq!(frames, [true, true, false, false, true, true, false, false]);
+
+        // 離すと次に押した時は押された状態から始まる。
+        turbo.next_frame(JoypadButton::empty());
+        assert_eq!(turbo.next_frame(JoypadButton::A | JoypadButton::B), JoypadButton::A | JoypadButton::B);
+    }
+}
@@ -0,0 +1,239 @@
+use nes_core::cartridge::Rom;
+use nes_core::movie::{apply_movie_frame, movie_rng, power_cycle, Movie, MovieFrame, MovieSession};
+use nes_core::ports::FourPlayerMode;
+use nes_core::snake::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use nes_core::{fds, loader, png, romdb};
+use nes_core::{Nes, CPU};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// SDLを使わずにROMを実行し、結果をファイルに書き出す。テストや非同期の調査に使う。
+// 起動引数: nes-headless <rom> [--frames <n>] [--until <addr>=<value>] [--movie <fm2>] [--seed <n>]
+//                        [--png <file>] [--wav <file>] [--ram <file>] [--hash-log <file>]
+//                        [--patch <ips/bps/ups>] [--entry <zip内のファイル名>] [--fds-bios <disksys.rom>]
+//                        [--rom-db <nes20db.xml>] [--snake]
+// --untilは16進数で、そのアドレスの値が一致したフレームで止まる。見られるのはRAMとカートリッジの
+// メモリだけ。--framesを省略するとムービーの最後まで、ムービーもなければ600フレーム実行する。
+// --snakeはsnakeのデモ用に乱数($FE)とキー($FF)を書き込む。PPUがないので、--pngで書き出すのは
+// snakeのデバッグ画面(RAMの$0200-$05FFを32x32で描いたもの)で、snake以外のROMでは意味のある画面にならない。
+// ハッシュログは1行1フレームで"フレーム 画面のCRC32 RAMのCRC32"(--snakeなしでは画面の列がない)。
+const DEFAULT_FRAMES: usize = 600;
+
+struct Options {
+    rom_path: PathBuf,
+    patch_path: Option<PathBuf>,
+    zip_entry: Option<String>,
+    fds_bios: PathBuf,
+    rom_db: Option<PathBuf>,
+    frames: Option<usize>,
+    until: Option<(u16, u8)>,
+    movie_path: Option<PathBuf>,
+    seed: u64,
+    png_path: Option<PathBuf>,
+    wav_path: Option<PathBuf>,
+    ram_path: Option<PathBuf>,
+    hash_log_path: Option<PathBuf>,
+    snake: bool,
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    let text = text.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(text, 16).ok()
+}
+
+// "0010=FF"のような停止条件。読むと状態が変わるレジスター($2000-$5FFF)は指定できない。
+fn parse_until(text: &str) -> Result<(u16, u8), String> {
+    let error = || format!("--until requires <addr>=<value> in hex: {}", text);
+    let (addr, value) = text.split_once('=').ok_or_else(error)?;
+    let addr = parse_hex(addr).ok_or_else(error)?;
+    let value = parse_hex(value).filter(|&value| value <= 0xFF).ok_or_else(error)?;
+    if (0x2000..=0x5FFF).contains(&addr) {
+        return Err(format!("--until can only watch RAM ($0000-$1FFF) and cartridge memory ($6000-$FFFF): {}", text));
+    }
+    Ok((addr, value as u8))
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} requires a number: {}", arg, value))
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: PathBuf::new(),
+        patch_path: None,
+        zip_entry: None,
+        fds_bios: PathBuf::from("disksys.rom"),
+        rom_db: None,
+        frames: None,
+        until: None,
+        movie_path: None,
+        seed: 0,
+        png_path: None,
+        wav_path: None,
+        ram_path: None,
+        hash_log_path: None,
+        snake: false,
+    };
+    let mut rom_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            rom_path = Some(PathBuf::from(arg));
+            continue;
+        }
+        if arg == "--snake" {
+            options.snake = true;
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("{} requires a value", arg))?;
+        match arg.as_str() {
+            "--frames" => options.frames = Some(parse_number(&arg, &value)?),
+            "--until" => options.until = Some(parse_until(&value)?),
+            "--movie" => options.movie_path = Some(PathBuf::from(value)),
+            "--seed" => options.seed = parse_number(&arg, &value)?,
+            "--png" => options.png_path = Some(PathBuf::from(value)),
+            "--wav" => options.wav_path = Some(PathBuf::from(value)),
+            "--ram" => options.ram_path = Some(PathBuf::from(value)),
+            "--hash-log" => options.hash_log_path = Some(PathBuf::from(value)),
+            "--patch" => options.patch_path = Some(PathBuf::from(value)),
+            "--entry" => options.zip_entry = Some(value),
+            "--fds-bios" => options.fds_bios = PathBuf::from(value),
+            "--rom-db" => options.rom_db = Some(PathBuf::from(value)),
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+    options.rom_path = rom_path.ok_or("Usage: nes-headless <rom> [options]")?;
+    Ok(options)
+}
+
+// 戻り値は本体とROMのMD5(ムービーとセーブステートの照合用)
+fn load_nes(options: &Options) -> Result<(Nes, [u8; 16]), String> {
+    if let Some(path) = &options.rom_db {
+        romdb::load_database(path)?;
+    }
+    let bytes = loader::load_rom(&options.rom_path, options.patch_path.as_deref(), options.zip_entry.as_deref())?;
+    let rom_md5 = Rom::new(&bytes)?.hash.md5;
+    let mut nes = if fds::is_disk_image(&bytes) {
+        let bios = std::fs::read(&options.fds_bios)
+            .map_err(|e| format!("Failed to read FDS BIOS {}: {}", options.fds_bios.display(), e))?;
+        Nes::load_disk(&bytes, bios)?
+    } else {
+        Nes::load_rom(&bytes)?
+    };
+    nes.set_snake(options.snake);
+    Ok((nes, rom_md5))
+}
+
+// 引数やROMの誤りはパニックせず、メッセージを出して終了する。
+fn exit_with_error(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1)
+}
+
+fn write_error(path: &Path, e: std::io::Error) -> String {
+    format!("Failed to write {}: {}", path.display(), e)
+}
+
+// ムービーはフロントエンドと同じく電源を入れた状態か、ムービーのセーブステートから始める。
+fn start_movie(cpu: &mut CPU, options: &Options, rom_md5: [u8; 16]) -> Result<Option<MovieSession>, String> {
+    let Some(path) = &options.movie_path else {
+        return Ok(None);
+    };
+    let movie = Movie::load(path)?;
+    if movie.rom_checksum != rom_md5 {
+        eprintln!("Warning: movie was recorded with a different ROM ({})", movie.rom_filename);
+    }
+    if movie.four_score {
+        cpu.bus.ports.set_four_player(FourPlayerMode::FourScore);
+    }
+    match &movie.savestate {
+        Some(state) => {
+            cpu.load_state(rom_md5, state).map_err(|e| format!("Failed to load the movie's save state: {}", e))?;
+        }
+        None => power_cycle(cpu),
+    }
+    Ok(Some(MovieSession::play(movie, path.clone(), true)))
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|e| exit_with_error(e));
+    if let Err(e) = run(&options) {
+        exit_with_error(e);
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let (mut nes, rom_md5) = load_nes(options)?;
+
+    if let Some(path) = &options.wav_path {
+        nes.cpu_mut().bus.apu.start_recording(path, false)?;
+    }
+    let mut hash_log = match &options.hash_log_path {
+        Some(path) => Some((BufWriter::new(File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?), path)),
+        None => None,
+    };
+
+    nes.set_rng(StdRng::seed_from_u64(options.seed));
+    let mut movie = start_movie(nes.cpu_mut(), options, rom_md5)?;
+    if movie.is_some() {
+        apply_movie_frame(nes.cpu_mut(), &mut movie, MovieFrame::default());
+        nes.resync_frame();
+    }
+    if let Some(session) = &movie {
+        nes.set_rng(movie_rng(session));
+    }
+    let frames = options.frames.unwrap_or(match &movie {
+        Some(session) => session.movie.frames.len(),
+        None => DEFAULT_FRAMES,
+    });
+
+    // フロントエンドと同じく、フレームの境目で入力を反映する。
+    let mut frame = 0;
+    while frame < frames {
+        let running = nes.step_frame();
+        frame += 1;
+
+        if let Some((log, path)) = &mut hash_log {
+            let ram_crc = crc32fast::hash(nes.cpu().bus.ram());
+            if nes.is_snake() {
+                writeln!(log, "{} {:08x} {:08x}", frame, crc32fast::hash(nes.snake_screen()), ram_crc).map_err(|e| write_error(path, e))?;
+            } else {
+                writeln!(log, "{} {:08x}", frame, ram_crc).map_err(|e| write_error(path, e))?;
+            }
+        }
+        if !running {
+            println!("Stopped at BRK");
+            break;
+        }
+        if let Some((addr, value)) = options.until {
+            if nes.cpu().bus.peek(addr) == Some(value) {
+                println!("Condition ${:04X}={:02X} met", addr, value);
+                break;
+            }
+        }
+
+        apply_movie_frame(nes.cpu_mut(), &mut movie, MovieFrame::default());
+        if let Some(session) = &movie {
+            nes.set_rng(movie_rng(session));
+        }
+    }
+    println!("Ran {} frames", frame);
+
+    if let Some((mut log, path)) = hash_log {
+        log.flush().map_err(|e| write_error(path, e))?;
+    }
+    if options.wav_path.is_some() {
+        nes.cpu_mut().bus.apu.stop_recording()?;
+    }
+    if let Some(path) = &options.png_path {
+        png::save_png(path, SCREEN_WIDTH, SCREEN_HEIGHT, nes.snake_screen())
+            .map_err(|e| write_error(path, e))?;
+    }
+    if let Some(path) = &options.ram_path {
+        std::fs::write(path, nes.cpu().bus.ram()).map_err(|e| write_error(path, e))?;
+    }
+    Ok(())
+}
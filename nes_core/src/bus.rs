@@ -0,0 +1,356 @@
+use crate::Mem;
+use crate::cartridge::Rom;
+use crate::apu::Apu;
+use crate::expansion::ExpansionLevels;
+use crate::ports::ControllerPorts;
+use crate::mapper::{self, Mapper};
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+const RAM:u16 = 0x0000;
+const RAM_MIRRORS_END:u16 = 0x1FFF;
+const PPU_REGISTERS:u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END:u16  = 0x3FFF;
+const APU_REGISTERS:u16 = 0x4000;
+const APU_REGISTERS_END:u16 = 0x4013;
+const APU_STATUS:u16 = 0x4015;
+const JOYPAD1:u16 = 0x4016;
+// 書き込みはAPUのフレームカウンター、読み込みは2コン
+const JOYPAD2:u16 = 0x4017;
+const APU_FRAME_COUNTER:u16 = 0x4017;
+const EXPANSION:u16 = 0x4020;
+const EXPANSION_END:u16 = 0x5FFF;
+const PRG_RAM:u16 = 0x6000;
+const PRG_RAM_END:u16 = 0x7FFF;
+// DMCのDMAでCPUが止まるサイクル数
+const DMC_DMA_CYCLES:u32 = 4;
+
+
+pub struct Bus {
+    cpu_vram: [u8; 2048],
+    pub apu: Apu,
+    pub ports: ControllerPorts,
+    mapper: Box<dyn Mapper>,
+    prg_ram_dirty: bool,
+    cycles: u64,
+    // 実行中の命令で最後に読み込んだアドレス(最後のアクセスが書き込みならNone)
+    last_read: Option<u16>,
+    // 最後にデータバスに乗った値。何もつながっていないアドレス(PPUができるまでのPPUのレジスターを含む)はこれを読む。
+    data_bus: u8,
+    // カートリッジの拡張音源の音量
+    expansion_level: f32,
+}
+
+impl Bus {
+    pub fn new(rom: Rom) -> Result<Self, String> {
+        let mapper = mapper::new_mapper(rom)?;
+        let expansion_level = mapper.expansion_chip().map_or(0.0, |chip| ExpansionLevels::default().get(chip));
+        Ok(Bus {
+            cpu_vram:[0;2048],
+            apu: Apu::new(),
+            ports: ControllerPorts::new(),
+            mapper,
+            prg_ram_dirty: false,
+            cycles: 0,
+            last_read: None,
+            data_bus: 0,
+            expansion_level,
+        })
+    }
+
+    pub fn set_expansion_levels(&mut self, levels: &ExpansionLevels) {
+        if let Some(chip) = self.mapper.expansion_chip() {
+            self.expansion_level = levels.get(chip);
+        }
+    }
+
+    pub fn battery_ram(&self) -> Option<&[u8]> {
+        self.mapper.battery_ram()
+    }
+
+    pub fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.mapper.battery_ram_mut()
+    }
+
+    pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
+        self.mapper.as_mut()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // CPUが命令を実行するたびに呼ばれる。
+    // CPUは命令単位でしか動かないので、DMCのDMAは命令の途中ではなく命令の終わった後に行い、
+    // その分のサイクルだけCPUを止める。
+    pub fn tick(&mut self, cycles: u8) {
+        let mut remaining = cycles as u32;
+        while remaining > 0 {
+            remaining -= 1;
+            self.cycles += 1;
+            self.apu.clock();
+            self.mapper.clock(1);
+            self.apu.set_expansion_output(self.mapper.audio_output() * self.expansion_level);
+
+            if let Some(addr) = self.apu.dmc.dma_request() {
+                self.dmc_dma(addr);
+                remaining += DMC_DMA_CYCLES;
+            }
+        }
+        self.last_read = None;
+    }
+
+    // DMAで止められたCPUは、止まっている間に直前の読み込みを繰り返す。命令の最後のアクセスが
+    // コントローラーの読み込みなら、シフトレジスタがもう1回進んでボタンを1つ読み飛ばす。
+    // ($2007の二重読み込みも同じ仕組みだが、PPUがないので影響はない)
+    fn dmc_dma(&mut self, addr: u16) {
+        if let Some(last_read @ (JOYPAD1 | JOYPAD2)) = self.last_read {
+            self.mem_read(last_read);
+        }
+        let data = self.mem_read(addr);
+        self.apu.dmc.fill_sample_buffer(data);
+        self.last_read = None;
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.mapper.irq() || self.apu.irq()
+    }
+
+    // 前回の呼び出し以降にPRG-RAMへの書き込みがあったかどうか。
+    pub fn take_prg_ram_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.prg_ram_dirty, false)
+    }
+
+    // リセットボタン。APUは$4015が0になり、$4017には最後に書いた値が再度書かれる。
+    pub fn reset(&mut self) {
+        self.apu.reset();
+    }
+
+    // CPUの2KBのRAM
+    pub fn ram(&self) -> &[u8] {
+        &self.cpu_vram
+    }
+
+    // 副作用なしにメモリを読む。読むと状態が変わるレジスター(PPU, APU, コントローラー, 拡張端子)はNone。
+    pub fn peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            RAM..=RAM_MIRRORS_END => Some(self.cpu_vram[(addr & 0b00000111_11111111) as usize]),
+            PRG_RAM..=PRG_RAM_END => Some(self.mapper.read_prg_ram(addr)),
+            0x8000..=0xFFFF => Some(self.mapper.read_prg(addr)),
+            _ => None,
+        }
+    }
+
+    pub fn clear_ram(&mut self) {
+        self.cpu_vram = [0; 2048];
+    }
+}
+
+// PPUのレジスター、VRAM、OAM、パレットの区画。PPUができるまでは版0の空の区画を書き、読む時は中身を使わない。
+const PPU_SECTIONS: [&[u8; 4]; 4] = [b"PREG", b"VRAM", b"OAM ", b"PALT"];
+
+// CPUのRAM、APU、マッパー、コントローラーとPPUの区画を保存する。
+impl SaveState for Bus {
+    fn save(&self, w: &mut StateWriter) {
+        self.cpu_vram.save(w);
+        self.cycles.save(w);
+        self.data_bus.save(w);
+        self.apu.save(w);
+        self.mapper.save(w);
+        self.ports.save(w);
+        for tag in PPU_SECTIONS {
+            savestate::write_section(w, tag, 0, &[]);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let battery_ram = self.battery_ram().map(|ram| ram.to_vec());
+        self.cpu_vram.load(r)?;
+        self.cycles.load(r)?;
+        self.data_bus.load(r)?;
+        self.apu.load(r)?;
+        self.mapper.load(r)?;
+        self.ports.load(r)?;
+        for tag in PPU_SECTIONS {
+            savestate::read_section(r, tag)?;
+        }
+        // バッテリーバックアップのRAMが変わったら.savに書き直す。
+        if self.battery_ram() != battery_ram.as_deref() {
+            self.prg_ram_dirty = true;
+        }
+        self.last_read = None;
+        Ok(())
+    }
+}
+
+impl Mem for Bus {
+
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.last_read = Some(addr);
+        let data = match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b00000111_11111111;
+                self.cpu_vram[mirror_down_addr as usize]
+            }
+            // PPUはまだないので、レジスターはオープンバスとして読む。
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => self.data_bus,
+            APU_STATUS => self.apu.read_status(),
+            JOYPAD1 => self.ports.read(0, self.cycles),
+            JOYPAD2 => self.ports.read(1, self.cycles),
+            EXPANSION..=EXPANSION_END => self.mapper.read_expansion(addr),
+            PRG_RAM..=PRG_RAM_END => self.mapper.read_prg_ram(addr),
+            0x8000..=0xFFFF => self.mapper.cpu_read_prg(addr),
+
+            _ => self.data_bus,
+        };
+        self.data_bus = data;
+        data
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.last_read = None;
+        self.data_bus = data;
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b11111111111;
+                self.cpu_vram[mirror_down_addr as usize] = data;
+            }
+            // PPUはまだないので、レジスターへの書き込みは無視する。
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {}
+            APU_REGISTERS..=APU_REGISTERS_END | APU_STATUS | APU_FRAME_COUNTER => {
+                self.apu.write_register(addr, data);
+            }
+            JOYPAD1 => self.ports.write(data),
+            EXPANSION..=EXPANSION_END => self.mapper.write_expansion(addr, data),
+            PRG_RAM..=PRG_RAM_END => {
+                self.mapper.write_prg_ram(addr, data);
+                self.prg_ram_dirty = true;
+            }
+            0x8000..=0xFFFF => self.mapper.write_prg(addr, data),
+
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_bus() -> Bus {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[0x4000] = 0xAA;
+        raw.extend(prg_rom);
+        Bus::new(Rom::new(&raw).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_dmc_dma_stalls_cpu() {
+        let mut bus = test_bus();
+        bus.mem_write(0x4012, 0x00);
+        bus.mem_write(0x4013, 0x01);
+        bus.mem_write(0x4015, 0b0001_0000);
+
+        bus.tick(2);
+        // 1サイクル目でサンプルを読み込み、DMA分だけサイクルが延びる。
+        assert_eq!(bus.cycles(), 2 + DMC_DMA_CYCLES as u64);
+        // バッファが空になるまで次のDMAは起きない。
+        assert_eq!(bus.apu.dmc.dma_request(), None);
+        bus.tick(2);
+        assert_eq!(bus.cycles(), 4 + DMC_DMA_CYCLES as u64);
+    }
+
+    // $4016を読んだ命令の直後にDMAが起きると、Bのビットが読み飛ばされてSelectが返る。
+    #[test]
+    fn test_dmc_dma_rereads_joypad() {
+        for dma in [false, true] {
+            let mut bus = test_bus();
+            bus.ports.joypad_mut(0).unwrap().set_buttons(crate::joypad::JoypadButton::A | crate::joypad::JoypadButton::B);
+            bus.mem_write(JOYPAD1, 1);
+            bus.mem_write(JOYPAD1, 0);
+            bus.mem_write(0x4012, 0x00);
+            bus.mem_write(0x4013, 0x01);
+            if dma {
+                bus.mem_write(0x4015, 0b0001_0000);
+            }
+
+            assert_eq!(bus.mem_read(JOYPAD1) & 1, 1);
+            bus.tick(4);
+            assert_eq!(bus.cycles() > 4, dma);
+            assert_eq!(bus.mem_read(JOYPAD1) & 1, if dma { 0 } else { 1 });
+        }
+    }
+
+    // 直前のアクセスが書き込みなら繰り返す読み込みはない。
+    #[test]
+    fn test_dmc_dma_after_write() {
+        let mut bus = test_bus();
+        bus.ports.joypad_mut(0).unwrap().set_buttons(crate::joypad::JoypadButton::A | crate::joypad::JoypadButton::B);
+        bus.mem_write(JOYPAD1, 1);
+        bus.mem_write(JOYPAD1, 0);
+        bus.mem_write(0x4012, 0x00);
+        bus.mem_write(0x4013, 0x01);
+        assert_eq!(bus.mem_read(JOYPAD1) & 1, 1);
+        bus.mem_write(0x4015, 0b0001_0000);
+        bus.tick(4);
+        assert_eq!(bus.mem_read(JOYPAD1) & 1, 1);
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut bus = test_bus();
+        bus.mem_write(0x0010, 0x42);
+        assert_eq!(bus.peek(0x0810), Some(0x42));
+        assert_eq!(bus.peek(0xC000), Some(0xAA));
+        assert_eq!(bus.peek(0x2002), None);
+        assert_eq!(bus.peek(JOYPAD1), None);
+
+        // コントローラーの読み出し位置は進まない。
+        bus.ports.joypad_mut(0).unwrap().set_buttons(crate::joypad::JoypadButton::A);
+        bus.mem_write(JOYPAD1, 1);
+        bus.mem_write(JOYPAD1, 0);
+        bus.peek(JOYPAD1);
+        assert_eq!(bus.mem_read(JOYPAD1) & 1, 1);
+    }
+
+    // PPUのレジスターは書き込みを無視し、最後にデータバスに乗った値を読む。
+    #[test]
+    fn test_ppu_open_bus() {
+        let mut bus = test_bus();
+        bus.mem_write(0x2000, 0x80);
+        assert_eq!(bus.mem_read(0x2002), 0x80);
+        bus.mem_write(0x0010, 0x42);
+        assert_eq!(bus.mem_read(0x0010), 0x42);
+        assert_eq!(bus.mem_read(0x3FFF), 0x42);
+        assert_eq!(bus.mem_read(0xC000), 0xAA);
+        assert_eq!(bus.mem_read(0x2007), 0xAA);
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut bus = test_bus();
+        bus.mem_write(0x0010, 0x42);
+        bus.mem_write(0x4015, 0b0001);
+        bus.mem_write(0x4003, 0x08);
+        bus.ports.joypad_mut(0).unwrap().set_buttons(crate::joypad::JoypadButton::A);
+        bus.mem_write(JOYPAD1, 1);
+        bus.mem_write(JOYPAD1, 0);
+        bus.tick(100);
+        let mut w = StateWriter::new();
+        bus.save(&mut w);
+        let state = w.into_bytes();
+
+        let mut loaded = test_bus();
+        loaded.load(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(loaded.mem_read(0x0010), 0x42);
+        assert_eq!(loaded.cycles(), bus.cycles());
+        assert_eq!(loaded.mem_read(APU_STATUS) & 1, 1);
+        // ボタンを読み出す位置も戻る。
+        assert_eq!(loaded.mem_read(JOYPAD1) & 1, 1);
+        assert_eq!(loaded.mem_read(JOYPAD1) & 1, 0);
+
+        let mut four_score = test_bus();
+        four_score.ports.set_four_player(crate::ports::FourPlayerMode::FourScore);
+        assert!(four_score.load(&mut StateReader::new(&state)).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use crate::romdb::{self, RomHash};
+use crate::unif;
+use crate::fds;
+use crate::nsf::{self, NsfInfo};
+
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+const PRG_ROM_PAGE_SIZE: usize = 16384;
+const CHR_ROM_PAGE_SIZE: usize = 8192;
+// パッチやアーカイブに書かれた大きさを信用しすぎないための上限。(NES 2.0で実際に使われる最大よりも大きい)
+pub const MAX_ROM_SIZE: usize = 32 << 20;
+const PRG_RAM_PAGE_SIZE: usize = 8192;
+
+#[derive(Debug , PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum Mirroring {
+    VERTICAL,
+    HORIZONTAL,
+    FOUR_SCREEN,
+    SINGLE_SCREEN_LOWER,
+    SINGLE_SCREEN_UPPER,
+}
+
+#[derive(Debug , PartialEq, Clone, Copy)]
+pub enum TvSystem {
+    NTSC,
+    PAL,
+    DUAL,
+}
+
+#[derive(Clone)]
+pub struct Rom {
+    pub prg_rom :Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mapper: u8,
+    pub screen_mirroring: Mirroring,
+    pub battery: bool,
+    pub prg_ram_size: usize,
+    pub tv_system: TvSystem,
+    pub title: Option<String>,
+    pub hash: RomHash,
+    // FDSのディスクイメージ(各面65500バイト)。BIOSはprg_romに入れる。
+    pub disk_sides: Vec<Vec<u8>>,
+    // NSF/NSFeの曲情報。データ本体はprg_romに入れる。
+    pub nsf: Option<NsfInfo>,
+}
+
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, String> {
+        if raw.starts_with(&unif::UNIF_TAG) {
+            return unif::parse(raw);
+        }
+        if fds::is_disk_image(raw) {
+            return fds::parse_image(raw);
+        }
+        if nsf::is_nsf(raw) {
+            return nsf::parse(raw);
+        }
+        if raw.len() < HEADER_SIZE || raw[0..4] != NES_TAG {
+            return Err("File is not in iNES file format".to_string());
+        }
+
+        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+
+        let ines_ver = (raw[7] >> 2) & 0b11;
+        if ines_ver != 0 {
+            return Err("NES2.0 format is no supported".to_string());
+        }
+
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertival_mirroring = raw[6] & 0b1 != 0;
+        let screen_mirroring = match (four_screen, vertival_mirroring) {
+            (true, _ ) => Mirroring::FOUR_SCREEN,
+            (false, true) => Mirroring::VERTICAL,
+            (false,false) => Mirroring::HORIZONTAL,
+        };
+
+        let tv_system = if raw[9] & 0b1 != 0 { TvSystem::PAL } else { TvSystem::NTSC };
+
+        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+
+        let skip_trainer = raw[6] & 0b100 != 0;
+        let battery = raw[6] & 0b10 != 0;
+
+        // byte 8が0の場合も互換性のため8KBとして扱う。
+        let prg_ram_size = match raw[8] {
+            0 => PRG_RAM_PAGE_SIZE,
+            n => n as usize * PRG_RAM_PAGE_SIZE,
+        };
+
+        let prg_rom_start = HEADER_SIZE + if skip_trainer {512} else {0};
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+
+        // 途中で切れたファイルはヘッダーの大きさに足りないので、パニックせずにエラーにする。
+        let truncated = || format!("ROM is truncated: header needs {} bytes but the file has {}", chr_rom_start + chr_rom_size, raw.len());
+        let prg_rom = raw.get(prg_rom_start .. chr_rom_start).ok_or_else(truncated)?.to_vec();
+        let chr_rom = raw.get(chr_rom_start .. (chr_rom_start + chr_rom_size)).ok_or_else(truncated)?.to_vec();
+        let hash = RomHash::new(&prg_rom, &chr_rom);
+
+        let mut rom = Rom {
+            prg_rom,
+            chr_rom,
+            mapper,
+            screen_mirroring,
+            battery,
+            prg_ram_size,
+            tv_system,
+            title: None,
+            hash,
+            disk_sides: Vec::new(),
+            nsf: None,
+        };
+        rom.apply_database();
+        Ok(rom)
+    }
+
+    // ヘッダーが間違っているダンプが多いので、データベースに登録されていればそちらを優先する。
+    pub(crate) fn apply_database(&mut self) {
+        let game = match romdb::lookup(&self.hash) {
+            Some(game) => game,
+            None => return,
+        };
+
+        if let Some(mapper) = game.mapper.filter(|&m| m <= 0xFF) {
+            self.mapper = mapper as u8;
+        }
+        if let Some(mirroring) = game.mirroring {
+            self.screen_mirroring = mirroring;
+        }
+        if let Some(battery) = game.battery {
+            self.battery = battery;
+        }
+        if let Some(size) = game.prg_ram_size {
+            self.prg_ram_size = size;
+        }
+        self.title = Some(game.title.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct TestRom {
+        header: Vec<u8>,
+        trainer:Option<Vec<u8>>,
+        pgp_rom:Vec<u8>,
+        chr_rom:Vec<u8>,
+    }
+    //Romと同サイズのベクターを確保して値を格納。
+    fn create_rom(rom: TestRom) -> Vec<u8> {
+
+        let mut  result = Vec::with_capacity(
+            rom.header.len() 
+            + rom.trainer.as_ref().map_or(0, |t| t.len())
+            + rom.pgp_rom.len()
+            + rom.chr_rom.len(),
+        );
+
+        result.extend(&rom.header);
+        if let Some(t) = rom.trainer {
+            result.extend(t);
+        }
+
+        result.extend(&rom.pgp_rom);
+        result.extend(&rom.chr_rom);
+
+        result
+    }
+
+    #[test]
+    fn test() {
+        let test_rom = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00,],
+            trainer:None,
+            pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert_eq!(rom.chr_rom, vec!(2; CHR_ROM_PAGE_SIZE));
+        assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
+        assert!(!rom.battery);
+        assert_eq!(rom.prg_ram_size, PRG_RAM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_battery_flag() {
+        let test_rom = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x33, 00, 0x02, 00, 00, 00, 00, 00, 00, 00,],
+            trainer:None,
+            pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert!(rom.battery);
+        assert_eq!(rom.prg_ram_size, 2 * PRG_RAM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_truncated_rom() {
+        let test_rom = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00,],
+            trainer:None,
+            pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        for len in [0, 4, 15, 16, 16 + PRG_ROM_PAGE_SIZE, test_rom.len() - 1] {
+            assert!(Rom::new(&test_rom[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_database_overrides_header() {
+        let mut raw = std::fs::read("../snake.nes").unwrap();
+        //わざと壊したヘッダー(mapper 1, vertical, battery)
+        raw[6] = 0b0001_0011;
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.screen_mirroring, Mirroring::HORIZONTAL);
+        assert!(!rom.battery);
+        assert_eq!(rom.title, Some("Snake".to_string()));
+    }
+}
+
@@ -0,0 +1,99 @@
+pub mod fds;
+pub mod mmc5;
+pub mod n163;
+pub mod sunsoft5b;
+pub mod vrc6;
+pub mod vrc7;
+
+// カートリッジ側の拡張音源。ファミコン本体で内蔵音源とミックスされる。
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExpansionChip {
+    Vrc6,
+    Vrc7,
+    N163,
+    Sunsoft5b,
+    Mmc5,
+    Fds,
+}
+
+pub const EXPANSION_CHIPS: [ExpansionChip; 6] = [
+    ExpansionChip::Vrc6,
+    ExpansionChip::Vrc7,
+    ExpansionChip::N163,
+    ExpansionChip::Sunsoft5b,
+    ExpansionChip::Mmc5,
+    ExpansionChip::Fds,
+];
+
+impl ExpansionChip {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExpansionChip::Vrc6 => "vrc6",
+            ExpansionChip::Vrc7 => "vrc7",
+            ExpansionChip::N163 => "n163",
+            ExpansionChip::Sunsoft5b => "5b",
+            ExpansionChip::Mmc5 => "mmc5",
+            ExpansionChip::Fds => "fds",
+        }
+    }
+}
+
+// 内蔵音源に対する各拡張音源の音量。1.0で実機に近いバランスになる。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpansionLevels {
+    levels: [f32; 6],
+}
+
+impl Default for ExpansionLevels {
+    fn default() -> Self {
+        ExpansionLevels { levels: [1.0; 6] }
+    }
+}
+
+impl ExpansionLevels {
+    pub fn get(&self, chip: ExpansionChip) -> f32 {
+        self.levels[chip as usize]
+    }
+
+    pub fn set(&mut self, chip: ExpansionChip, level: f32) {
+        self.levels[chip as usize] = level;
+    }
+
+    // "vrc6=0.8" の形式の指定を反映する。
+    pub fn parse_setting(&mut self, setting: &str) -> Result<(), String> {
+        let (name, level) = setting
+            .split_once('=')
+            .ok_or_else(|| format!("Expected <chip>=<level>: {}", setting))?;
+        let chip = EXPANSION_CHIPS
+            .iter()
+            .find(|chip| chip.name() == name)
+            .ok_or_else(|| format!("Unknown expansion chip: {}", name))?;
+        let level: f32 = level
+            .parse()
+            .map_err(|_| format!("Invalid level: {}", level))?;
+        if level < 0.0 {
+            return Err(format!("Invalid level: {}", level));
+        }
+        self.set(*chip, level);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_setting() {
+        let mut levels = ExpansionLevels::default();
+        levels.parse_setting("vrc6=0.5").unwrap();
+        levels.parse_setting("5b=2").unwrap();
+
+        assert_eq!(levels.get(ExpansionChip::Vrc6), 0.5);
+        assert_eq!(levels.get(ExpansionChip::Sunsoft5b), 2.0);
+        assert_eq!(levels.get(ExpansionChip::Fds), 1.0);
+        assert!(levels.parse_setting("sid=1.0").is_err());
+        assert!(levels.parse_setting("fds").is_err());
+        assert!(levels.parse_setting("fds=-1").is_err());
+    }
+}
@@ -0,0 +1,287 @@
+use crate::savestate::impl_save_state;
+
+// 最大出力(波形63 x 音量32)が内蔵の矩形波(音量15)の2.4倍程度になる係数
+const FDS_LEVEL: f32 = 0.1494 * 2.4 / (63.0 * 32.0);
+// $4089のマスター音量(2/2, 2/3, 2/4, 2/5)
+const MASTER_VOLUMES: [f32; 4] = [1.0, 2.0 / 3.0, 2.0 / 4.0, 2.0 / 5.0];
+// モジュレーションテーブルの値による変化量。4はカウンターを0に戻す。
+const MOD_ADJUSTMENTS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+#[derive(Default)]
+struct FdsEnvelope {
+    disabled: bool,
+    increase: bool,
+    speed: u8,
+    gain: u8,
+    counter: u32,
+}
+
+impl_save_state!(FdsEnvelope { disabled, increase, speed, gain, counter });
+
+impl FdsEnvelope {
+    fn write(&mut self, data: u8) {
+        self.disabled = data & 0b1000_0000 != 0;
+        self.increase = data & 0b0100_0000 != 0;
+        self.speed = data & 0b0011_1111;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.counter = 0;
+    }
+
+    // 周期は8 * (speed + 1) * ($408Aの値) CPUサイクル
+    fn clock(&mut self, multiplier: u8) {
+        if self.disabled || multiplier == 0 {
+            return;
+        }
+        self.counter += 1;
+        if self.counter < 8 * (self.speed as u32 + 1) * multiplier as u32 {
+            return;
+        }
+        self.counter = 0;
+        if self.increase && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+}
+
+// FDSの波形メモリ音源。64サンプルの波形を、モジュレーション(周波数変調)付きで鳴らす。
+pub struct FdsAudio {
+    wave: [u8; 64],
+    wave_write: bool,
+    wave_halt: bool,
+    wave_frequency: u16,
+    wave_accumulator: u32,
+    wave_output: u8,
+    envelopes_halted: bool,
+    envelope_multiplier: u8,
+    volume: FdsEnvelope,
+    master_volume: u8,
+
+    sweep: FdsEnvelope,
+    mod_table: [u8; 64],
+    mod_position: u8,
+    mod_counter: i8,
+    mod_frequency: u16,
+    mod_halt: bool,
+    mod_accumulator: u32,
+}
+
+impl_save_state!(FdsAudio {
+    wave,
+    wave_write,
+    wave_halt,
+    wave_frequency,
+    wave_accumulator,
+    wave_output,
+    envelopes_halted,
+    envelope_multiplier,
+    volume,
+    master_volume,
+    sweep,
+    mod_table,
+    mod_position,
+    mod_counter,
+    mod_frequency,
+    mod_halt,
+    mod_accumulator,
+});
+
+impl Default for FdsAudio {
+    fn default() -> Self {
+        FdsAudio {
+            wave: [0; 64],
+            wave_write: false,
+            wave_halt: true,
+            wave_frequency: 0,
+            wave_accumulator: 0,
+            wave_output: 0,
+            envelopes_halted: false,
+            envelope_multiplier: 0xE8,
+            volume: FdsEnvelope::default(),
+            master_volume: 0,
+            sweep: FdsEnvelope::default(),
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_counter: 0,
+            mod_frequency: 0,
+            mod_halt: true,
+            mod_accumulator: 0,
+        }
+    }
+}
+
+impl FdsAudio {
+    // $4040-$4092
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x407F => Some(self.wave[(addr - 0x4040) as usize]),
+            0x4090 => Some(self.volume.gain),
+            0x4092 => Some(self.sweep.gain),
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => {
+                self.wave[(addr - 0x4040) as usize] = data & 0b0011_1111;
+            }
+            0x4080 => self.volume.write(data),
+            0x4082 => self.wave_frequency = (self.wave_frequency & 0x0F00) | data as u16,
+            0x4083 => {
+                self.wave_frequency = (self.wave_frequency & 0x00FF) | ((data & 0x0F) as u16) << 8;
+                self.wave_halt = data & 0b1000_0000 != 0;
+                self.envelopes_halted = data & 0b0100_0000 != 0;
+                if self.wave_halt {
+                    self.wave_accumulator = 0;
+                }
+            }
+            0x4084 => self.sweep.write(data),
+            0x4085 => {
+                self.mod_counter = ((data & 0x7F) << 1) as i8 >> 1;
+                self.mod_accumulator = 0;
+            }
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0F00) | data as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | ((data & 0x0F) as u16) << 8;
+                self.mod_halt = data & 0b1000_0000 != 0;
+                if self.mod_halt {
+                    self.mod_accumulator = 0;
+                }
+            }
+            // モジュレーションを止めている間だけ書ける。1回の書き込みで2つ分進む。
+            0x4088 if self.mod_halt => {
+                for _ in 0..2 {
+                    self.mod_table[self.mod_position as usize] = data & 0b111;
+                    self.mod_position = (self.mod_position + 1) & 0x3F;
+                }
+            }
+            0x4089 => {
+                self.wave_write = data & 0b1000_0000 != 0;
+                self.master_volume = data & 0b11;
+            }
+            0x408A => self.envelope_multiplier = data,
+            _ => {}
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if !self.wave_halt && !self.envelopes_halted {
+            self.volume.clock(self.envelope_multiplier);
+            self.sweep.clock(self.envelope_multiplier);
+        }
+
+        if !self.mod_halt {
+            self.mod_accumulator += self.mod_frequency as u32;
+            if self.mod_accumulator >= 0x10000 {
+                self.mod_accumulator -= 0x10000;
+                self.step_modulator();
+            }
+        }
+
+        if !self.wave_halt && !self.wave_write {
+            let pitch = self.pitch();
+            self.wave_accumulator = (self.wave_accumulator + pitch) & 0x3F_FFFF;
+            self.wave_output = self.wave[(self.wave_accumulator >> 16) as usize];
+        }
+    }
+
+    fn step_modulator(&mut self) {
+        let value = self.mod_table[self.mod_position as usize];
+        self.mod_position = (self.mod_position + 1) & 0x3F;
+        self.mod_counter = if value == 4 {
+            0
+        } else {
+            // 7bitの符号付きとして折り返す。
+            let counter = self.mod_counter.wrapping_add(MOD_ADJUSTMENTS[value as usize]);
+            (counter << 1) >> 1
+        };
+    }
+
+    // モジュレーションを反映した周波数(nesdev wikiの計算式)
+    fn pitch(&self) -> u32 {
+        let counter = self.mod_counter as i32;
+        let mut temp = counter * self.sweep.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        let frequency = self.wave_frequency as i32;
+        temp *= frequency;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        (frequency + temp).max(0) as u32
+    }
+
+    pub fn output(&self) -> f32 {
+        let gain = self.volume.gain.min(32) as f32;
+        self.wave_output as f32 * gain * MASTER_VOLUMES[self.master_volume as usize] * FDS_LEVEL
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wave_playback() {
+        let mut audio = FdsAudio::default();
+        audio.write(0x4089, 0b1000_0000);
+        for i in 0..64 {
+            audio.write(0x4040 + i, i as u8);
+        }
+        audio.write(0x4089, 0);
+        audio.write(0x4080, 0b1010_0000);
+        // 32サイクルで1サンプル進む周波数
+        audio.write(0x4082, 0x00);
+        audio.write(0x4083, 0x08);
+
+        let mut samples = Vec::new();
+        for _ in 0..4 {
+            for _ in 0..32 {
+                audio.clock();
+            }
+            samples.push(audio.wave_output);
+        }
+        assert_eq!(samples, [1, 2, 3, 4]);
+        assert_eq!(audio.read(0x4090), Some(32));
+    }
+
+    #[test]
+    fn test_modulation_bends_pitch() {
+        let mut audio = FdsAudio::default();
+        audio.write(0x4082, 0x00);
+        audio.write(0x4083, 0x01);
+        assert_eq!(audio.pitch(), 0x100);
+
+        audio.write(0x4084, 0b1010_0000);
+        audio.write(0x4087, 0b1000_0000);
+        for _ in 0..32 {
+            audio.write(0x4088, 1);
+        }
+        audio.write(0x4086, 0x00);
+        audio.write(0x4087, 0x08);
+
+        // 0x800 * 32 = 0x10000サイクルで1ステップ(カウンター+1)
+        for _ in 0..32 {
+            audio.clock();
+        }
+        assert_eq!(audio.mod_counter, 1);
+        assert!(audio.pitch() > 0x100);
+        audio.write(0x4085, 0x7F);
+        assert_eq!(audio.mod_counter, -1);
+        assert!(audio.pitch() < 0x100);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::apu::pulse::Pulse;
+use crate::savestate::impl_save_state;
+
+// 矩形波は内蔵の矩形波と同じ尺度。(ミキサーの近似式と同じ係数)
+const PULSE_LEVEL: f32 = 95.52;
+// PCM($FF)がDMC($7F)と同程度になる係数
+const PCM_LEVEL: f32 = 0.42 / 255.0;
+// 内蔵のフレームカウンターとは別に、常に240Hzで長さカウンターとエンベロープを進める。
+const FRAME_PERIOD: u16 = 7457;
+
+// MMC5の矩形波2チャンネル(スイープなし)とPCM
+pub struct Mmc5Audio {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    frame_counter: u16,
+    cycle: u64,
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    pcm: u8,
+    pcm_irq: bool,
+}
+
+impl_save_state!(Mmc5Audio {
+    pulse1,
+    pulse2,
+    frame_counter,
+    cycle,
+    pcm_read_mode,
+    pcm_irq_enabled,
+    pcm,
+    pcm_irq,
+});
+
+impl Default for Mmc5Audio {
+    fn default() -> Self {
+        Mmc5Audio {
+            pulse1: Pulse::without_sweep(),
+            pulse2: Pulse::without_sweep(),
+            frame_counter: 0,
+            cycle: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm: 0,
+            pcm_irq: false,
+        }
+    }
+}
+
+impl Mmc5Audio {
+    // $5000-$5015
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000 => self.pulse1.write_control(data),
+            0x5002 => self.pulse1.write_timer_low(data),
+            0x5003 => self.pulse1.write_timer_high(data),
+            0x5004 => self.pulse2.write_control(data),
+            0x5006 => self.pulse2.write_timer_low(data),
+            0x5007 => self.pulse2.write_timer_high(data),
+            0x5010 => {
+                self.pcm_read_mode = data & 0b0000_0001 != 0;
+                self.pcm_irq_enabled = data & 0b1000_0000 != 0;
+            }
+            // 書き込みモードでは0は無視される。
+            0x5011 if !self.pcm_read_mode && data != 0 => self.pcm = data,
+            0x5015 => {
+                self.pulse1.set_enabled(data & 0b01 != 0);
+                self.pulse2.set_enabled(data & 0b10 != 0);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5010 => {
+                let mut status = 0;
+                if std::mem::take(&mut self.pcm_irq) {
+                    status |= 0b1000_0000;
+                }
+                if self.pcm_read_mode {
+                    status |= 0b0000_0001;
+                }
+                Some(status)
+            }
+            0x5015 => {
+                let mut status = 0;
+                if self.pulse1.is_active() {
+                    status |= 0b01;
+                }
+                if self.pulse2.is_active() {
+                    status |= 0b10;
+                }
+                Some(status)
+            }
+            _ => None,
+        }
+    }
+
+    // 読み込みモードでCPUが$8000-$BFFFを読んだ。0を読むとIRQになる。
+    pub fn read_pcm(&mut self, data: u8) {
+        if !self.pcm_read_mode {
+            return;
+        }
+        if data == 0 {
+            if self.pcm_irq_enabled {
+                self.pcm_irq = true;
+            }
+        } else {
+            self.pcm = data;
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        self.pcm_irq && self.pcm_irq_enabled
+    }
+
+    pub fn clock(&mut self) {
+        self.frame_counter += 1;
+        if self.frame_counter >= FRAME_PERIOD {
+            self.frame_counter = 0;
+            for pulse in [&mut self.pulse1, &mut self.pulse2] {
+                pulse.clock_quarter_frame();
+                pulse.clock_half_frame();
+            }
+        }
+        if self.cycle % 2 == 1 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.cycle += 1;
+    }
+
+    pub fn output(&self) -> f32 {
+        let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+        let pulse = if pulse == 0.0 {
+            0.0
+        } else {
+            PULSE_LEVEL / (8128.0 / pulse + 100.0)
+        };
+        pulse + self.pcm as f32 * PCM_LEVEL
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pcm_read_mode_irq() {
+        let mut audio = Mmc5Audio::default();
+        audio.write(0x5011, 0x40);
+        assert_eq!(audio.pcm, 0x40);
+
+        audio.write(0x5010, 0b1000_0001);
+        audio.write(0x5011, 0x20);
+        assert_eq!(audio.pcm, 0x40);
+        audio.read_pcm(0x60);
+        assert_eq!(audio.pcm, 0x60);
+        assert!(!audio.irq());
+        audio.read_pcm(0x00);
+        assert!(audio.irq());
+        assert_eq!(audio.read(0x5010), Some(0b1000_0001));
+        assert!(!audio.irq());
+    }
+
+    #[test]
+    fn test_length_counter_runs_without_apu() {
+        let mut audio = Mmc5Audio::default();
+        audio.write(0x5015, 0b01);
+        // 長さ2(インデックス3)
+        audio.write(0x5003, 0b0001_1000);
+        assert_eq!(audio.read(0x5015), Some(0b01));
+        for _ in 0..FRAME_PERIOD as u32 * 2 {
+            audio.clock();
+        }
+        assert_eq!(audio.read(0x5015), Some(0));
+    }
+}
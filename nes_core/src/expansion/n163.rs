@@ -0,0 +1,144 @@
+use crate::savestate::impl_save_state;
+
+// 1チャンネル(波形±8 x 音量15)が内蔵の矩形波2つ分程度になる係数
+const N163_LEVEL: f32 = 0.0025;
+// 1チャンネルの更新にかかるCPUサイクル
+const CHANNEL_CYCLES: u8 = 15;
+const RAM_SIZE: usize = 128;
+
+// Namco 163の波形メモリ音源。内蔵RAMの$40-$7Fにチャンネルのレジスタがあり、
+// 有効なチャンネルを15サイクルずつ順番に更新して、そのチャンネルだけを出力する(時分割)。
+pub struct N163Audio {
+    ram: [u8; RAM_SIZE],
+    address: u8,
+    auto_increment: bool,
+    disabled: bool,
+    cycle: u8,
+    // 次に更新するチャンネル(0が$78のチャンネル)
+    channel: u8,
+    output: i16,
+}
+
+impl_save_state!(N163Audio { ram, address, auto_increment, disabled, cycle, channel, output });
+
+impl Default for N163Audio {
+    fn default() -> Self {
+        N163Audio {
+            ram: [0; RAM_SIZE],
+            address: 0,
+            auto_increment: false,
+            disabled: false,
+            cycle: 0,
+            channel: 0,
+            output: 0,
+        }
+    }
+}
+
+impl N163Audio {
+    // $F800
+    pub fn write_address(&mut self, data: u8) {
+        self.address = data & 0x7F;
+        self.auto_increment = data & 0x80 != 0;
+    }
+
+    // $4800
+    pub fn write_data(&mut self, data: u8) {
+        self.ram[self.address as usize] = data;
+        self.increment_address();
+    }
+
+    pub fn read_data(&mut self) -> u8 {
+        let data = self.ram[self.address as usize];
+        self.increment_address();
+        data
+    }
+
+    fn increment_address(&mut self) {
+        if self.auto_increment {
+            self.address = (self.address + 1) & 0x7F;
+        }
+    }
+
+    // $E000のbit6
+    pub fn set_disabled(&mut self, disabled: bool) {
+        self.disabled = disabled;
+    }
+
+    fn channel_count(&self) -> u8 {
+        ((self.ram[0x7F] >> 4) & 0b111) + 1
+    }
+
+    pub fn clock(&mut self) {
+        if self.disabled {
+            return;
+        }
+        self.cycle += 1;
+        if self.cycle < CHANNEL_CYCLES {
+            return;
+        }
+        self.cycle = 0;
+
+        let base = 0x78 - 8 * self.channel as usize;
+        self.output = self.update_channel(base);
+        self.channel += 1;
+        if self.channel >= self.channel_count() {
+            self.channel = 0;
+        }
+    }
+
+    fn update_channel(&mut self, base: usize) -> i16 {
+        let regs = &mut self.ram[base..base + 8];
+        let frequency = regs[0] as u32 | (regs[2] as u32) << 8 | ((regs[4] & 0b11) as u32) << 16;
+        let mut phase = regs[1] as u32 | (regs[3] as u32) << 8 | (regs[5] as u32) << 16;
+        let length = 256 - (regs[4] & 0b1111_1100) as u32;
+        phase = (phase + frequency) % (length << 16);
+        regs[1] = phase as u8;
+        regs[3] = (phase >> 8) as u8;
+        regs[5] = (phase >> 16) as u8;
+
+        let offset = regs[6] as u32;
+        let volume = (regs[7] & 0b1111) as i16;
+        let index = (((phase >> 16) + offset) & 0xFF) as usize;
+        let sample = (self.ram[index / 2] >> ((index & 1) * 4)) & 0b1111;
+        (sample as i16 - 8) * volume
+    }
+
+    pub fn output(&self) -> f32 {
+        self.output as f32 * N163_LEVEL
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_channel_multiplexing() {
+        let mut audio = N163Audio::default();
+        // 2チャンネル。波形はどちらも4サンプル(長さ252)で、先頭の$00と$01に置く。
+        audio.write_address(0x80); // 自動インクリメント, アドレス$00
+        audio.write_data(0xFF);
+        audio.write_data(0x00);
+        audio.write_address(0x80 | 0x7C);
+        audio.write_data(0b1111_1100); // 長さ4
+        audio.write_data(0x00);
+        audio.write_data(0x00);
+        audio.write_data(0b0001_1111); // 2チャンネル, 音量15
+        audio.write_address(0x80 | 0x74);
+        audio.write_data(0b1111_1100);
+        audio.write_data(0x00);
+        audio.write_data(0x04); // 波形の位置4 -> $02
+        audio.write_data(0x05);
+
+        let mut outputs = Vec::new();
+        for _ in 0..2 {
+            for _ in 0..CHANNEL_CYCLES {
+                audio.clock();
+            }
+            outputs.push(audio.output);
+        }
+        // 周波数0なので位相は進まず、$78は$0F(7*15)、$70は$00(-8*5)を交互に出力する。
+        assert_eq!(outputs, [105, -40]);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::savestate::impl_save_state;
+
+// 1チャンネルの最大音量が内蔵の矩形波(音量15)の1.5倍程度になる係数
+const SUNSOFT5B_LEVEL: f32 = 0.22;
+// 内部クロックはCPUクロックの1/2で、さらに1/8したものでトーンを進める。
+const PRESCALER: u8 = 16;
+
+// Sunsoft 5B(AY-3-8910互換)。トーン3チャンネルとノイズ、エンベロープ。
+pub struct Sunsoft5bAudio {
+    register: u8,
+    regs: [u8; 16],
+    prescaler: u8,
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_counter: u8,
+    noise_lfsr: u32,
+    noise_toggle: bool,
+    envelope_counter: u16,
+    // 0〜31。音量は1.5dB刻み
+    envelope_step: u8,
+    envelope_holding: bool,
+    envelope_attack: bool,
+    volume_table: [f32; 32],
+}
+
+impl_save_state!(Sunsoft5bAudio {
+    register,
+    regs,
+    prescaler,
+    tone_counters,
+    tone_outputs,
+    noise_counter,
+    noise_lfsr,
+    noise_toggle,
+    envelope_counter,
+    envelope_step,
+    envelope_holding,
+    envelope_attack,
+});
+
+impl Default for Sunsoft5bAudio {
+    fn default() -> Self {
+        let mut volume_table = [0.0; 32];
+        for (level, volume) in volume_table.iter_mut().enumerate().skip(1) {
+            *volume = 10f32.powf((level as f32 - 31.0) * 1.5 / 20.0);
+        }
+        Sunsoft5bAudio {
+            register: 0,
+            regs: [0; 16],
+            prescaler: 0,
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+            noise_counter: 0,
+            noise_lfsr: 1,
+            noise_toggle: false,
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_holding: false,
+            envelope_attack: false,
+            volume_table,
+        }
+    }
+}
+
+impl Sunsoft5bAudio {
+    // $C000
+    pub fn write_register(&mut self, data: u8) {
+        self.register = data & 0x0F;
+    }
+
+    // $E000
+    pub fn write_data(&mut self, data: u8) {
+        self.regs[self.register as usize] = data;
+        if self.register == 13 {
+            self.restart_envelope();
+        }
+    }
+
+    fn restart_envelope(&mut self) {
+        self.envelope_attack = self.regs[13] & 0b0100 != 0;
+        self.envelope_step = 0;
+        self.envelope_counter = 0;
+        self.envelope_holding = false;
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        let period = self.regs[channel * 2] as u16 | ((self.regs[channel * 2 + 1] & 0x0F) as u16) << 8;
+        period.max(1)
+    }
+
+    pub fn clock(&mut self) {
+        self.prescaler += 1;
+        if self.prescaler < PRESCALER {
+            return;
+        }
+        self.prescaler = 0;
+
+        for channel in 0..3 {
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= self.tone_period(channel) {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        // ノイズはトーンの半分の速さでシフトする。
+        self.noise_counter += 1;
+        if self.noise_counter >= (self.regs[6] & 0x1F).max(1) {
+            self.noise_counter = 0;
+            self.noise_toggle = !self.noise_toggle;
+            if self.noise_toggle {
+                let feedback = (self.noise_lfsr ^ (self.noise_lfsr >> 3)) & 1;
+                self.noise_lfsr = (self.noise_lfsr >> 1) | (feedback << 16);
+            }
+        }
+
+        let envelope_period = (self.regs[11] as u16 | (self.regs[12] as u16) << 8).max(1);
+        self.envelope_counter += 1;
+        if self.envelope_counter >= envelope_period {
+            self.envelope_counter = 0;
+            self.clock_envelope();
+        }
+    }
+
+    // $0D: bit3 継続, bit2 アタック, bit1 交互, bit0 ホールド
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+        let shape = self.regs[13];
+        let cont = shape & 0b1000 != 0;
+        let alternate = shape & 0b0010 != 0;
+        let hold = shape & 0b0001 != 0;
+        if !cont {
+            // 1周期で終わり、音量0で止まる。
+            self.envelope_holding = true;
+            self.envelope_attack = false;
+            self.envelope_step = 31;
+        } else if hold {
+            self.envelope_holding = true;
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+        } else {
+            self.envelope_step = 0;
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+        }
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        let mixer = self.regs[7];
+        let noise = self.noise_lfsr & 1 != 0;
+        let mut sum = 0.0;
+        for channel in 0..3 {
+            let tone_on = self.tone_outputs[channel] || mixer & (1 << channel) != 0;
+            let noise_on = noise || mixer & (0b1000 << channel) != 0;
+            if !(tone_on && noise_on) {
+                continue;
+            }
+            let volume = self.regs[8 + channel];
+            let level = if volume & 0b1_0000 != 0 {
+                self.envelope_level()
+            } else if volume & 0x0F == 0 {
+                0
+            } else {
+                (volume & 0x0F) * 2 + 1
+            };
+            sum += self.volume_table[level as usize];
+        }
+        sum * SUNSOFT5B_LEVEL
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(audio: &mut Sunsoft5bAudio, register: u8, data: u8) {
+        audio.write_register(register);
+        audio.write_data(data);
+    }
+
+    #[test]
+    fn test_tone_period() {
+        let mut audio = Sunsoft5bAudio::default();
+        write(&mut audio, 0, 10);
+        // トーンAだけ有効、ノイズは全て無効
+        write(&mut audio, 7, 0b0011_1110);
+        write(&mut audio, 8, 15);
+
+        let mut toggles = 0;
+        let mut last = audio.output();
+        for _ in 0..16 * 100 {
+            audio.clock();
+            let output = audio.output();
+            if output != last {
+                toggles += 1;
+                last = output;
+            }
+        }
+        // 16*10サイクルごとに反転する。
+        assert_eq!(toggles, 10);
+        assert!((audio.volume_table[31] - 1.0).abs() < 1e-6);
+        // 1段で1.5dB
+        assert!((audio.volume_table[30] - 0.841).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_envelope_decay_and_stop() {
+        let mut audio = Sunsoft5bAudio::default();
+        write(&mut audio, 11, 1);
+        write(&mut audio, 13, 0b0000);
+        assert_eq!(audio.envelope_level(), 31);
+        for _ in 0..16 * 31 {
+            audio.clock();
+        }
+        assert_eq!(audio.envelope_level(), 0);
+        for _ in 0..16 * 100 {
+            audio.clock();
+        }
+        assert_eq!(audio.envelope_level(), 0);
+    }
+}
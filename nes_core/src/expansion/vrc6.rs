@@ -0,0 +1,198 @@
+use crate::savestate::impl_save_state;
+
+// 矩形波(音量15)が内蔵の矩形波(音量15)と同程度になる係数
+const VRC6_LEVEL: f32 = 0.1494 / 15.0;
+
+#[derive(Default)]
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    // 1ならデューティを無視して常に音量を出力する。
+    digitized: bool,
+    enabled: bool,
+    period: u16,
+    counter: u16,
+    step: u8,
+}
+
+impl_save_state!(Vrc6Pulse { volume, duty, digitized, enabled, period, counter, step });
+
+impl Vrc6Pulse {
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.digitized = data & 0b1000_0000 != 0;
+                self.duty = (data >> 4) & 0b111;
+                self.volume = data & 0b1111;
+            }
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data & 0b1111) as u16) << 8;
+                self.enabled = data & 0b1000_0000 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.counter == 0 {
+            self.counter = self.period >> shift;
+            self.step = (self.step + 1) % 16;
+        } else {
+            self.counter -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.digitized || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Default)]
+struct Vrc6Saw {
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    counter: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl_save_state!(Vrc6Saw { rate, enabled, period, counter, step, accumulator });
+
+impl Vrc6Saw {
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => self.rate = data & 0b0011_1111,
+            1 => self.period = (self.period & 0x0F00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data & 0b1111) as u16) << 8;
+                self.enabled = data & 0b1000_0000 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    // 2クロックごとにrateを足し込み、14クロックで0に戻る。
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.counter > 0 {
+            self.counter -= 1;
+            return;
+        }
+        self.counter = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+// VRC6の矩形波2チャンネルとノコギリ波
+#[derive(Default)]
+pub struct Vrc6Audio {
+    pulses: [Vrc6Pulse; 2],
+    saw: Vrc6Saw,
+    halt: bool,
+    shift: u8,
+}
+
+impl_save_state!(Vrc6Audio { pulses, saw, halt, shift });
+
+impl Vrc6Audio {
+    // addrは$9000-$B003(VRC6bのアドレス線の入れ替えは済ませておく)
+    pub fn write(&mut self, addr: u16, data: u8) {
+        let reg = addr & 0b11;
+        match addr & 0xF000 {
+            0x9000 if reg == 3 => {
+                self.halt = data & 0b001 != 0;
+                self.shift = if data & 0b100 != 0 {
+                    8
+                } else if data & 0b010 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            0x9000 => self.pulses[0].write(reg, data),
+            0xA000 if reg != 3 => self.pulses[1].write(reg, data),
+            0xB000 if reg != 3 => self.saw.write(reg, data),
+            _ => {}
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if self.halt {
+            return;
+        }
+        for pulse in self.pulses.iter_mut() {
+            pulse.clock(self.shift);
+        }
+        self.saw.clock(self.shift);
+    }
+
+    pub fn output(&self) -> f32 {
+        let sum = self.pulses[0].output() + self.pulses[1].output() + self.saw.output();
+        sum as f32 * VRC6_LEVEL
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_saw_ramp() {
+        let mut audio = Vrc6Audio::default();
+        audio.write(0xB000, 42);
+        audio.write(0xB001, 0);
+        audio.write(0xB002, 0b1000_0000);
+
+        let mut outputs = Vec::new();
+        for _ in 0..14 {
+            audio.clock();
+            outputs.push(audio.saw.output());
+        }
+        // 42ずつ6回足し込んで(252)、7回目で0に戻る。
+        assert_eq!(outputs, [0, 5, 5, 10, 10, 15, 15, 21, 21, 26, 26, 31, 31, 0]);
+    }
+
+    #[test]
+    fn test_pulse_duty() {
+        let mut audio = Vrc6Audio::default();
+        audio.write(0x9000, 0b0011_1010);
+        audio.write(0x9001, 0);
+        audio.write(0x9002, 0b1000_0000);
+
+        let mut high = 0;
+        for _ in 0..16 {
+            audio.clock();
+            if audio.pulses[0].output() == 10 {
+                high += 1;
+            }
+        }
+        // デューティ3 -> 16ステップ中4ステップ
+        assert_eq!(high, 4);
+    }
+}
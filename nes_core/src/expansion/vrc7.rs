@@ -0,0 +1,440 @@
+use std::f32::consts::PI;
+use crate::savestate::{impl_save_state, SaveState, StateReader, StateWriter};
+
+// 1チャンネルの最大出力が内蔵の矩形波(音量15)と同程度になる係数
+const VRC7_LEVEL: f32 = 0.15;
+// OPLLは3.58MHz/72(約49.7kHz)で1サンプルを出力する。CPUサイクルでは36。
+const SAMPLE_CYCLES: u8 = 36;
+const OPLL_RATE: f32 = 49_716.0;
+// これ以上減衰したら無音として扱う(dB)
+const MAX_ATTENUATION: f32 = 48.0;
+// 減衰率15のディケイ(48dB)にかかる時間を基準に、率が1下がるごとに倍になる。
+const FASTEST_DECAY_SECONDS: f32 = 0.0024;
+// アタックはディケイより約14倍速い。
+const ATTACK_SPEEDUP: f32 = 14.0;
+// モジュレーターの出力1.0で搬送波の位相をずらす量(ラジアン)
+const MODULATION_INDEX: f32 = 4.0 * PI;
+const TREMOLO_DEPTH: f32 = 4.8;
+const TREMOLO_HZ: f32 = 3.7;
+const VIBRATO_DEPTH: f32 = 0.004;
+const VIBRATO_HZ: f32 = 6.4;
+
+const MULTIPLIERS: [f32; 16] = [
+    0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0,
+];
+
+// キースケールレベル(オクターブ7での減衰量, dB)
+const KSL_TABLE: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5,
+    41.25, 42.0,
+];
+
+// VRC7に内蔵された15種類の音色(音色0はユーザー定義)
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum EnvelopeStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+const ENVELOPE_STAGES: [EnvelopeStage; 5] = [
+    EnvelopeStage::Attack,
+    EnvelopeStage::Decay,
+    EnvelopeStage::Sustain,
+    EnvelopeStage::Release,
+    EnvelopeStage::Off,
+];
+
+impl SaveState for EnvelopeStage {
+    fn save(&self, w: &mut StateWriter) {
+        (*self as u8).save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut index = 0u8;
+        index.load(r)?;
+        *self = *ENVELOPE_STAGES
+            .get(index as usize)
+            .ok_or_else(|| format!("Invalid VRC7 envelope stage {}", index))?;
+        Ok(())
+    }
+}
+
+// 音色データから取り出した1オペレーター分のパラメータ
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: f32,
+    key_scale_level: u8,
+    rectified: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+impl OperatorPatch {
+    // operatorは0がモジュレーター、1が搬送波
+    fn new(patch: &[u8; 8], operator: usize) -> Self {
+        let flags = patch[operator];
+        OperatorPatch {
+            tremolo: flags & 0b1000_0000 != 0,
+            vibrato: flags & 0b0100_0000 != 0,
+            sustained: flags & 0b0010_0000 != 0,
+            key_scale_rate: flags & 0b0001_0000 != 0,
+            multiplier: MULTIPLIERS[(flags & 0x0F) as usize],
+            key_scale_level: patch[2 + operator] >> 6,
+            rectified: patch[3] & (0b1000 << operator) != 0,
+            attack: patch[4 + operator] >> 4,
+            decay: patch[4 + operator] & 0x0F,
+            sustain_level: patch[6 + operator] >> 4,
+            release: patch[6 + operator] & 0x0F,
+        }
+    }
+}
+
+struct Operator {
+    phase: f32,
+    stage: EnvelopeStage,
+    // エンベロープによる減衰(dB)
+    attenuation: f32,
+}
+
+impl_save_state!(Operator { phase, stage, attenuation });
+
+impl Default for Operator {
+    fn default() -> Self {
+        Operator {
+            phase: 0.0,
+            stage: EnvelopeStage::Off,
+            attenuation: MAX_ATTENUATION,
+        }
+    }
+}
+
+impl Operator {
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.stage = EnvelopeStage::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.stage != EnvelopeStage::Off {
+            self.stage = EnvelopeStage::Release;
+        }
+    }
+
+    // rateは0〜63(4 * 設定値 + キースケール)
+    fn clock_envelope(&mut self, patch: &OperatorPatch, key_scale: u8, release_rate: u8) {
+        let rate = |value: u8| -> f32 {
+            if value == 0 {
+                return 0.0;
+            }
+            let rate = (4 * value + key_scale).min(63) as f32;
+            let seconds = FASTEST_DECAY_SECONDS * 2f32.powf((60.0 - rate) / 4.0);
+            MAX_ATTENUATION / (seconds * OPLL_RATE)
+        };
+        match self.stage {
+            EnvelopeStage::Attack => {
+                if patch.attack == 15 {
+                    self.attenuation = 0.0;
+                } else {
+                    self.attenuation -= rate(patch.attack) * ATTACK_SPEEDUP;
+                }
+                if self.attenuation <= 0.0 {
+                    self.attenuation = 0.0;
+                    self.stage = EnvelopeStage::Decay;
+                }
+            }
+            EnvelopeStage::Decay => {
+                let sustain_level = patch.sustain_level as f32 * 3.0;
+                self.attenuation += rate(patch.decay);
+                if self.attenuation >= sustain_level {
+                    self.attenuation = sustain_level;
+                    self.stage = EnvelopeStage::Sustain;
+                }
+            }
+            // 持続音はキーオフまで保持、減衰音はリリースの率で下がり続ける。
+            EnvelopeStage::Sustain => {
+                if !patch.sustained {
+                    self.attenuation += rate(patch.release);
+                }
+            }
+            EnvelopeStage::Release => self.attenuation += rate(release_rate),
+            EnvelopeStage::Off => {}
+        }
+        if self.attenuation >= MAX_ATTENUATION {
+            self.attenuation = MAX_ATTENUATION;
+            self.stage = EnvelopeStage::Off;
+        }
+    }
+
+    fn wave(&self, phase_offset: f32, rectified: bool) -> f32 {
+        let value = (2.0 * PI * self.phase + phase_offset).sin();
+        if rectified && value < 0.0 {
+            0.0
+        } else {
+            value
+        }
+    }
+}
+
+#[derive(Default)]
+struct FmChannel {
+    fnum: u16,
+    block: u8,
+    key_on: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+    feedback: [f32; 2],
+}
+
+impl_save_state!(FmChannel {
+    fnum,
+    block,
+    key_on,
+    sustain,
+    instrument,
+    volume,
+    modulator,
+    carrier,
+    feedback,
+});
+
+// VRC7のFM音源(YM2413の6チャンネル版)。
+// 実チップのテーブルやビット精度までは再現せず、浮動小数点で2オペレーターFMを計算する。
+pub struct Vrc7Audio {
+    register: u8,
+    custom_patch: [u8; 8],
+    channels: [FmChannel; 6],
+    cycle: u8,
+    lfo_time: f32,
+    output: f32,
+    muted: bool,
+}
+
+impl_save_state!(Vrc7Audio { register, custom_patch, channels, cycle, lfo_time, output, muted });
+
+impl Default for Vrc7Audio {
+    fn default() -> Self {
+        Vrc7Audio {
+            register: 0,
+            custom_patch: [0; 8],
+            channels: Default::default(),
+            cycle: 0,
+            lfo_time: 0.0,
+            output: 0.0,
+            muted: false,
+        }
+    }
+}
+
+impl Vrc7Audio {
+    // $9010
+    pub fn write_register(&mut self, data: u8) {
+        self.register = data;
+    }
+
+    // $9030
+    pub fn write_data(&mut self, data: u8) {
+        match self.register {
+            0x00..=0x07 => self.custom_patch[self.register as usize] = data,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[(self.register - 0x10) as usize];
+                channel.fnum = (channel.fnum & 0x100) | data as u16;
+            }
+            0x20..=0x25 => {
+                let channel = &mut self.channels[(self.register - 0x20) as usize];
+                channel.fnum = (channel.fnum & 0xFF) | ((data & 0b1) as u16) << 8;
+                channel.block = (data >> 1) & 0b111;
+                channel.sustain = data & 0b0010_0000 != 0;
+                let key_on = data & 0b0001_0000 != 0;
+                if key_on && !channel.key_on {
+                    channel.modulator.key_on();
+                    channel.carrier.key_on();
+                } else if !key_on && channel.key_on {
+                    channel.modulator.key_off();
+                    channel.carrier.key_off();
+                }
+                channel.key_on = key_on;
+            }
+            0x30..=0x35 => {
+                let channel = &mut self.channels[(self.register - 0x30) as usize];
+                channel.instrument = data >> 4;
+                channel.volume = data & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    // $E000のbit6。立っている間はリセットされて無音になる。
+    pub fn set_muted(&mut self, muted: bool) {
+        if muted && !self.muted {
+            for channel in self.channels.iter_mut() {
+                *channel = FmChannel::default();
+            }
+            self.output = 0.0;
+        }
+        self.muted = muted;
+    }
+
+    fn patch(&self, instrument: u8) -> [u8; 8] {
+        match instrument {
+            0 => self.custom_patch,
+            n => PATCHES[n as usize - 1],
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if self.muted {
+            return;
+        }
+        self.cycle += 1;
+        if self.cycle < SAMPLE_CYCLES {
+            return;
+        }
+        self.cycle = 0;
+
+        self.lfo_time += 1.0 / OPLL_RATE;
+        let tremolo = TREMOLO_DEPTH * 0.5 * (1.0 + (2.0 * PI * TREMOLO_HZ * self.lfo_time).sin());
+        let vibrato = 1.0 + VIBRATO_DEPTH * (2.0 * PI * VIBRATO_HZ * self.lfo_time).sin();
+
+        let mut output = 0.0;
+        for index in 0..self.channels.len() {
+            let patch = self.patch(self.channels[index].instrument);
+            output += clock_channel(&mut self.channels[index], &patch, tremolo, vibrato);
+        }
+        self.output = output;
+    }
+
+    pub fn output(&self) -> f32 {
+        self.output * VRC7_LEVEL
+    }
+}
+
+fn clock_channel(channel: &mut FmChannel, patch: &[u8; 8], tremolo: f32, vibrato: f32) -> f32 {
+    let operators = [OperatorPatch::new(patch, 0), OperatorPatch::new(patch, 1)];
+    let octave = (channel.block << 1) | (channel.fnum >> 8) as u8;
+    let key_scale_level = (KSL_TABLE[(channel.fnum >> 5) as usize & 0x0F]
+        - 6.0 * (7 - channel.block) as f32)
+        .max(0.0);
+    let frequency = channel.fnum as f32 * 2f32.powi(channel.block as i32) / (1 << 19) as f32;
+
+    let mut levels = [0.0; 2];
+    for (i, op) in operators.iter().enumerate() {
+        let operator = if i == 0 {
+            &mut channel.modulator
+        } else {
+            &mut channel.carrier
+        };
+        let key_scale = if op.key_scale_rate { octave } else { octave >> 2 };
+        // キーオフ後: サステインオンなら5、持続音はリリースの率、減衰音は7
+        let release_rate = if channel.sustain {
+            5
+        } else if op.sustained {
+            op.release
+        } else {
+            7
+        };
+        operator.clock_envelope(op, key_scale, release_rate);
+
+        let mut attenuation = operator.attenuation
+            + key_scale_level * [0.0, 0.5, 1.0, 2.0][op.key_scale_level as usize];
+        if op.tremolo {
+            attenuation += tremolo;
+        }
+        attenuation += if i == 0 {
+            (patch[2] & 0x3F) as f32 * 0.75
+        } else {
+            channel.volume as f32 * 3.0
+        };
+        levels[i] = if operator.stage == EnvelopeStage::Off {
+            0.0
+        } else {
+            10f32.powf(-attenuation / 20.0)
+        };
+
+        let step = frequency * op.multiplier * if op.vibrato { vibrato } else { 1.0 };
+        operator.phase = (operator.phase + step).fract();
+    }
+
+    let feedback = patch[3] & 0b111;
+    let feedback_offset = if feedback == 0 {
+        0.0
+    } else {
+        MODULATION_INDEX * 2f32.powi(feedback as i32 - 7) * (channel.feedback[0] + channel.feedback[1]) / 2.0
+    };
+    let modulator = channel.modulator.wave(feedback_offset, operators[0].rectified) * levels[0];
+    channel.feedback = [channel.feedback[1], modulator];
+    channel.carrier.wave(modulator * MODULATION_INDEX, operators[1].rectified) * levels[1]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(audio: &mut Vrc7Audio, register: u8, data: u8) {
+        audio.write_register(register);
+        audio.write_data(data);
+    }
+
+    fn run(audio: &mut Vrc7Audio, samples: u32) -> f32 {
+        let mut peak: f32 = 0.0;
+        for _ in 0..samples * SAMPLE_CYCLES as u32 {
+            audio.clock();
+            peak = peak.max(audio.output.abs());
+        }
+        peak
+    }
+
+    #[test]
+    fn test_key_on_and_release() {
+        let mut audio = Vrc7Audio::default();
+        // 音色3(ピアノ), 音量最大, A4付近(fnum 288, block 4)
+        write(&mut audio, 0x30, 0x30);
+        write(&mut audio, 0x10, 0x20);
+        write(&mut audio, 0x20, 0b0001_1001);
+
+        assert!(run(&mut audio, 2000) > 0.1);
+
+        write(&mut audio, 0x20, 0b0000_1001);
+        run(&mut audio, 50_000);
+        assert_eq!(audio.channels[0].carrier.stage, EnvelopeStage::Off);
+        assert_eq!(run(&mut audio, 100), 0.0);
+    }
+
+    #[test]
+    fn test_mute_resets_channels() {
+        let mut audio = Vrc7Audio::default();
+        write(&mut audio, 0x30, 0x10);
+        write(&mut audio, 0x20, 0b0001_1001);
+        audio.set_muted(true);
+        assert!(!audio.channels[0].key_on);
+        assert_eq!(run(&mut audio, 100), 0.0);
+    }
+}
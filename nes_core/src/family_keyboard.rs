@@ -0,0 +1,105 @@
+use crate::ports::{InputDevice, JOYPAD2};
+use crate::savestate::impl_save_state;
+
+// ファミリーベーシックのキーボード(拡張端子)。9行 x 2列のマトリクスで、列ごとに4キーを$4017のD4-D1に0で読む。
+// $4016への書き込み: bit0で1行目に戻り、bit1が列、bit2が1の間だけキーボードが有効。列が1から0になると次の行へ進む。
+// キーはSDLのキー名で、対応するキーがないものは近い位置のキーに割り当てる。
+//   STOP: End, ¥: \, カナ: Right Alt, ^: =, : : ', @: `, _: Page Down, GRPH: Left Alt, CLR HOME: Home
+pub const KEY_MATRIX: [[&str; 8]; 9] = [
+    ["]", "[", "Return", "F8", "End", "\\", "Right Shift", "Right Alt"],
+    [";", "'", "`", "F7", "=", "-", "/", "Page Down"],
+    ["K", "L", "O", "F6", "0", "P", ",", "."],
+    ["J", "U", "I", "F5", "8", "9", "N", "M"],
+    ["H", "G", "Y", "F4", "6", "7", "V", "B"],
+    ["D", "R", "T", "F3", "4", "5", "C", "F"],
+    ["A", "S", "W", "F2", "3", "E", "Z", "X"],
+    ["Left Ctrl", "Q", "Escape", "F1", "2", "1", "Left Alt", "Left Shift"],
+    ["Left", "Right", "Up", "Home", "Insert", "Delete", "Space", "Down"],
+];
+
+#[derive(Default)]
+pub struct FamilyKeyboard {
+    pressed: [[bool; 8]; 9],
+    row: usize,
+    column: usize,
+    enabled: bool,
+}
+
+impl_save_state!(FamilyKeyboard { row, column, enabled });
+
+impl FamilyKeyboard {
+    pub fn new() -> Self {
+        FamilyKeyboard::default()
+    }
+
+    // マトリクスにないキーならfalseを返す。
+    pub fn set_key(&mut self, name: &str, pressed: bool) -> bool {
+        for (row, keys) in KEY_MATRIX.iter().enumerate() {
+            if let Some(index) = keys.iter().position(|key| *key == name) {
+                self.pressed[row][index] = pressed;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl InputDevice for FamilyKeyboard {
+    fn write(&mut self, data: u8) {
+        self.enabled = data & 0b100 != 0;
+        let column = ((data >> 1) & 1) as usize;
+        if data & 1 == 1 {
+            self.row = 0;
+        } else if self.column == 1 && column == 0 {
+            self.row += 1;
+        }
+        self.column = column;
+    }
+
+    fn read(&mut self, address: u16, _cycles: u64) -> u8 {
+        if address != JOYPAD2 || !self.enabled {
+            return 0;
+        }
+        let mut data = 0b0001_1110;
+        if let Some(keys) = self.pressed.get(self.row) {
+            for i in 0..4 {
+                if keys[self.column * 4 + i] {
+                    data &= !(0b0001_0000 >> i);
+                }
+            }
+        }
+        data
+    }
+
+    fn as_keyboard_mut(&mut self) -> Option<&mut FamilyKeyboard> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_matrix_scan() {
+        let mut keyboard = FamilyKeyboard::new();
+        assert!(keyboard.set_key("Return", true));
+        assert!(keyboard.set_key("M", true));
+        assert!(!keyboard.set_key("F12", true));
+
+        // 1行目の列0, 列1, 2行目の列0...と読む。
+        keyboard.write(0b101);
+        let mut rows = Vec::new();
+        for _ in 0..4 {
+            keyboard.write(0b100);
+            let column0 = keyboard.read(JOYPAD2, 0);
+            keyboard.write(0b110);
+            rows.push((column0, keyboard.read(JOYPAD2, 0)));
+        }
+        assert_eq!(rows[0], (0b1_1010, 0b1_1110));
+        assert_eq!(rows[3], (0b1_1110, 0b1_1100));
+
+        keyboard.write(0);
+        assert_eq!(keyboard.read(JOYPAD2, 0), 0);
+    }
+}
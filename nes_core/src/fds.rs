@@ -0,0 +1,689 @@
+use crate::cartridge::{Mirroring, Rom, TvSystem};
+use crate::expansion::fds::FdsAudio;
+use crate::expansion::ExpansionChip;
+use crate::mapper::Mapper;
+use crate::patch;
+use crate::romdb::RomHash;
+use crate::savestate::impl_save_state;
+
+// iNESのマッパー番号20をFDSとして扱う。
+pub const FDS_MAPPER: u8 = 20;
+pub const SIDE_SIZE: usize = 65500;
+pub const BIOS_SIZE: usize = 0x2000;
+const FWNES_TAG: [u8; 4] = [0x46, 0x44, 0x53, 0x1A];
+const FWNES_HEADER_SIZE: usize = 16;
+const DISK_INFO_TAG: &[u8] = b"\x01*NINTENDO-HVC*";
+const RAM_SIZE: usize = 0x8000;
+
+// .fdsのブロックにはギャップとCRCが含まれていないので、ドライブから読む時のために補う。
+const LEADING_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+const GAP_END_MARK: u8 = 0x80;
+const CRC_SIZE: usize = 2;
+const MIN_RAW_SIDE_SIZE: usize = 65526 + LEADING_GAP;
+
+// ドライブのタイミング(CPUサイクル)
+const BYTE_TRANSFER_CYCLES: u32 = 149;
+const HEAD_RETURN_CYCLES: u32 = 50000;
+// ディスクを入れ替えた時、BIOSが取り出しを検出できるまで未挿入の状態を続ける。
+const DISK_SWAP_DELAY: u32 = 1_800_000;
+
+pub fn is_disk_image(raw: &[u8]) -> bool {
+    raw.starts_with(&FWNES_TAG) || raw.starts_with(DISK_INFO_TAG)
+}
+
+// fwNESヘッダー付き/なしの両方を受け付ける。
+pub fn parse_image(raw: &[u8]) -> Result<Rom, String> {
+    let data = if raw.starts_with(&FWNES_TAG) {
+        raw.get(FWNES_HEADER_SIZE..).ok_or("FDS image is truncated")?
+    } else {
+        raw
+    };
+    if !data.starts_with(DISK_INFO_TAG) {
+        return Err("File is not a Famicom Disk System image".to_string());
+    }
+
+    let disk_sides: Vec<Vec<u8>> = data
+        .chunks(SIDE_SIZE)
+        .filter(|side| side.starts_with(DISK_INFO_TAG))
+        .map(|side| {
+            let mut side = side.to_vec();
+            side.resize(SIDE_SIZE, 0);
+            side
+        })
+        .collect();
+
+    Ok(Rom {
+        prg_rom: Vec::new(),
+        chr_rom: Vec::new(),
+        mapper: FDS_MAPPER,
+        screen_mirroring: Mirroring::HORIZONTAL,
+        battery: false,
+        prg_ram_size: 0,
+        tv_system: TvSystem::NTSC,
+        title: None,
+        hash: RomHash::new(&disk_sides.concat(), &[]),
+        disk_sides,
+        nsf: None,
+    })
+}
+
+// BIOS(disksys.rom)はROMと別に読み込んでprg_romに入れる。
+pub fn attach_bios(rom: &mut Rom, bios: Vec<u8>) -> Result<(), String> {
+    if bios.len() != BIOS_SIZE {
+        return Err(format!("FDS BIOS must be {} bytes (got {})", BIOS_SIZE, bios.len()));
+    }
+    rom.prg_rom = bios;
+    Ok(())
+}
+
+// ブロック長はブロック種別から決まる。ファイルデータ(4)の長さは直前のヘッダー(3)に書かれている。
+fn block_len(block_type: u8, data: &[u8], file_size: usize) -> Option<usize> {
+    match block_type {
+        1 => Some(56),
+        2 => Some(2),
+        3 => Some(16),
+        4 => Some(1 + file_size),
+        _ => None,
+    }
+    .filter(|&len| len <= data.len())
+}
+
+fn header_file_size(block: &[u8]) -> usize {
+    block[13] as usize | (block[14] as usize) << 8
+}
+
+// .fdsの1面を、ギャップとCRCを含むドライブ上のデータ列に変換する。
+fn to_raw_side(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; LEADING_GAP];
+    let mut pos = 0;
+    let mut file_size = 0;
+    while pos < side.len() {
+        let len = match block_len(side[pos], &side[pos..], file_size) {
+            Some(len) => len,
+            None => break,
+        };
+        let block = &side[pos..pos + len];
+        if block[0] == 3 {
+            file_size = header_file_size(block);
+        }
+        raw.push(GAP_END_MARK);
+        raw.extend_from_slice(block);
+        // CRCは読み込み時に検査しないので中身は何でもよい。
+        raw.extend_from_slice(&[0x4D, 0x62]);
+        raw.resize(raw.len() + BLOCK_GAP, 0);
+        pos += len;
+    }
+    if raw.len() < MIN_RAW_SIDE_SIZE {
+        raw.resize(MIN_RAW_SIDE_SIZE, 0);
+    }
+    raw
+}
+
+// to_raw_sideの逆。ディスクへの書き込みを.fds形式に戻して保存するのに使う。
+fn from_raw_side(raw: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(SIDE_SIZE);
+    let mut pos = 0;
+    let mut file_size = 0;
+    loop {
+        pos = match raw[pos..].iter().position(|&b| b == GAP_END_MARK) {
+            Some(offset) => pos + offset + 1,
+            None => break,
+        };
+        let len = match raw.get(pos).and_then(|&t| block_len(t, &raw[pos..], file_size)) {
+            Some(len) => len,
+            None => break,
+        };
+        let block = &raw[pos..pos + len];
+        if block[0] == 3 {
+            file_size = header_file_size(block);
+        }
+        side.extend_from_slice(block);
+        pos += len + CRC_SIZE;
+        if pos >= raw.len() {
+            break;
+        }
+    }
+    side.resize(SIDE_SIZE, 0);
+    side
+}
+
+// RAMアダプタ(BIOS, 32KBのRAM, $4020-$4033のディスクレジスタ)とディスクドライブ
+pub struct Fds {
+    bios: Vec<u8>,
+    ram: Vec<u8>,
+    original_image: Vec<u8>,
+    sides: Vec<Vec<u8>>,
+    side: Option<usize>,
+    next_side: Option<usize>,
+    swap_delay: u32,
+    disk_modified: bool,
+
+    disk_reg_enabled: bool,
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_repeat: bool,
+    timer_irq: bool,
+    disk_irq: bool,
+
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    crc_control: bool,
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+    read_data: u8,
+    write_data: u8,
+    transfer_complete: bool,
+
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+
+    // $4040-$4097の拡張音源
+    audio: FdsAudio,
+}
+
+impl_save_state!(Fds {
+    sides,
+    side,
+    next_side,
+    swap_delay,
+    disk_modified,
+    disk_reg_enabled,
+    irq_reload,
+    irq_counter,
+    irq_enabled,
+    irq_repeat,
+    timer_irq,
+    disk_irq,
+    motor_on,
+    reset_transfer,
+    read_mode,
+    crc_control,
+    disk_ready,
+    disk_irq_enabled,
+    read_data,
+    write_data,
+    transfer_complete,
+    position,
+    delay,
+    end_of_head,
+    scanning,
+    gap_ended,
+    audio,
+    ram,
+});
+
+impl Fds {
+    pub fn new(rom: Rom) -> Result<Self, String> {
+        if rom.prg_rom.len() != BIOS_SIZE {
+            return Err("FDS BIOS is not loaded".to_string());
+        }
+        if rom.disk_sides.is_empty() {
+            return Err("FDS image has no disk sides".to_string());
+        }
+
+        Ok(Fds {
+            bios: rom.prg_rom,
+            ram: vec![0; RAM_SIZE],
+            original_image: rom.disk_sides.concat(),
+            sides: rom.disk_sides.iter().map(|side| to_raw_side(side)).collect(),
+            side: Some(0),
+            next_side: None,
+            swap_delay: 0,
+            disk_modified: false,
+
+            disk_reg_enabled: false,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_repeat: false,
+            timer_irq: false,
+            disk_irq: false,
+
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            crc_control: false,
+            disk_ready: false,
+            disk_irq_enabled: false,
+            read_data: 0,
+            write_data: 0,
+            transfer_complete: false,
+
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+
+            audio: FdsAudio::default(),
+        })
+    }
+
+    pub fn side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    pub fn inserted_side(&self) -> Option<usize> {
+        self.side
+    }
+
+    pub fn eject(&mut self) {
+        self.side = None;
+        self.next_side = None;
+    }
+
+    // 一度取り出してから、しばらくしてsideを挿入する。
+    pub fn insert(&mut self, side: usize) {
+        if side >= self.sides.len() {
+            return;
+        }
+        self.side = None;
+        self.next_side = Some(side);
+        self.swap_delay = DISK_SWAP_DELAY;
+    }
+
+    // 次の面(最後の面なら1枚目のA面)に入れ替える。
+    pub fn switch_side(&mut self) {
+        let Some(last) = self.sides.len().checked_sub(1) else {
+            return;
+        };
+        let current = self.side.or(self.next_side).unwrap_or(last);
+        self.insert((current + 1) % self.sides.len());
+    }
+
+    // 現在のディスクを.fds形式(ヘッダーなし)で返す。
+    pub fn disk_image(&self) -> Vec<u8> {
+        self.sides.iter().flat_map(|raw| from_raw_side(raw)).collect()
+    }
+
+    // 元のイメージに対する書き込みの差分(IPS)
+    pub fn disk_patch(&self) -> Vec<u8> {
+        patch::create_ips(&self.original_image, &self.disk_image())
+    }
+
+    // 面の数が変わる差分は別のディスクのものなので読み込まない。面の中のデータ列の長さは変わりうるので、
+    // ヘッドは先頭に戻す。
+    pub fn apply_disk_patch(&mut self, data: &[u8]) -> Result<(), String> {
+        let image = patch::apply_ips(&self.original_image, data)?;
+        let sides: Vec<Vec<u8>> = image.chunks(SIDE_SIZE).map(to_raw_side).collect();
+        if sides.len() != self.sides.len() {
+            return Err(format!("Disk save has {} sides but the disk has {}", sides.len(), self.sides.len()));
+        }
+        self.sides = sides;
+        self.position = 0;
+        self.end_of_head = true;
+        self.scanning = false;
+        Ok(())
+    }
+
+    // 前回の呼び出し以降にディスクへの書き込みがあったかどうか。
+    pub fn take_disk_modified(&mut self) -> bool {
+        std::mem::replace(&mut self.disk_modified, false)
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.timer_irq = true;
+            self.irq_counter = self.irq_reload;
+            if !self.irq_repeat {
+                self.irq_enabled = false;
+            }
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    fn clock_disk(&mut self) {
+        if self.swap_delay > 0 {
+            self.swap_delay -= 1;
+            if self.swap_delay == 0 {
+                self.side = self.next_side.take();
+            }
+            return;
+        }
+
+        let side = match self.side {
+            Some(side) if self.motor_on => side,
+            _ => {
+                self.end_of_head = true;
+                self.scanning = false;
+                return;
+            }
+        };
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            // ヘッドが先頭に戻るまで待つ。
+            self.delay = HEAD_RETURN_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let mut need_irq = self.disk_irq_enabled;
+        if self.read_mode {
+            let data = self.sides[side][self.position];
+            if !self.disk_ready {
+                self.gap_ended = false;
+            } else if data != 0 && !self.gap_ended {
+                // ギャップ終端マーク(0x80)自体は転送しない。
+                self.gap_ended = true;
+                need_irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = data;
+                if need_irq {
+                    self.disk_irq = true;
+                }
+            }
+        } else {
+            let mut data = 0;
+            if !self.crc_control {
+                self.transfer_complete = true;
+                data = self.write_data;
+                if need_irq {
+                    self.disk_irq = true;
+                }
+            }
+            if !self.disk_ready {
+                data = 0;
+            }
+            self.sides[side][self.position] = data;
+            self.disk_modified = true;
+            self.gap_ended = false;
+        }
+
+        self.position += 1;
+        if self.position >= self.sides[side].len() {
+            self.motor_on = false;
+            self.end_of_head = true;
+        } else {
+            self.delay = BYTE_TRANSFER_CYCLES;
+        }
+    }
+}
+
+impl Mapper for Fds {
+    // $8000-$DFFFはRAM、$E000-$FFFFはBIOS
+    fn read_prg(&self, addr: u16) -> u8 {
+        if addr >= 0xE000 {
+            self.bios[(addr - 0xE000) as usize]
+        } else {
+            self.ram[(addr - 0x6000) as usize]
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if addr < 0xE000 {
+            self.ram[(addr - 0x6000) as usize] = data;
+        }
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
+        self.ram[(addr - 0x6000) as usize]
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        self.ram[(addr - 0x6000) as usize] = data;
+    }
+
+    fn read_expansion(&mut self, addr: u16) -> u8 {
+        if let Some(data) = self.audio.read(addr) {
+            return data;
+        }
+        if !self.disk_reg_enabled {
+            return 0;
+        }
+        match addr {
+            // 読むとIRQが解除される。
+            0x4030 => {
+                let mut status = 0;
+                if self.timer_irq {
+                    status |= 0b0000_0001;
+                }
+                if self.transfer_complete {
+                    status |= 0b0000_0010;
+                }
+                self.transfer_complete = false;
+                self.timer_irq = false;
+                self.disk_irq = false;
+                status
+            }
+            0x4031 => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                self.read_data
+            }
+            0x4032 => {
+                let inserted = self.side.is_some();
+                let mut status = 0b0100_0000;
+                if !inserted {
+                    status |= 0b0000_0101;
+                }
+                if !inserted || !self.scanning {
+                    status |= 0b0000_0010;
+                }
+                status
+            }
+            // バッテリー良好
+            0x4033 => 0b1000_0000,
+            _ => 0,
+        }
+    }
+
+    fn write_expansion(&mut self, addr: u16, data: u8) {
+        if (0x4040..=0x408A).contains(&addr) {
+            self.audio.write(addr, data);
+            return;
+        }
+        if !self.disk_reg_enabled && (0x4024..=0x4026).contains(&addr) {
+            return;
+        }
+        match addr {
+            0x4020 => self.irq_reload = (self.irq_reload & 0xFF00) | data as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00FF) | (data as u16) << 8,
+            0x4022 => {
+                self.irq_repeat = data & 0b01 != 0;
+                self.irq_enabled = data & 0b10 != 0 && self.disk_reg_enabled;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_reg_enabled = data & 0b01 != 0;
+                if !self.disk_reg_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 => {
+                self.write_data = data;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 => {
+                self.motor_on = data & 0b0000_0001 != 0;
+                self.reset_transfer = data & 0b0000_0010 != 0;
+                self.read_mode = data & 0b0000_0100 != 0;
+                self.crc_control = data & 0b0001_0000 != 0;
+                self.disk_ready = data & 0b0100_0000 != 0;
+                self.disk_irq_enabled = data & 0b1000_0000 != 0;
+                self.disk_irq = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.clock_timer();
+            self.clock_disk();
+            self.audio.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn expansion_chip(&self) -> Option<ExpansionChip> {
+        Some(ExpansionChip::Fds)
+    }
+
+    fn as_fds_mut(&mut self) -> Option<&mut Fds> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_side() -> Vec<u8> {
+        let mut side = DISK_INFO_TAG.to_vec();
+        side.resize(56, 0);
+        side.extend(&[0x02, 0x01]);
+        side.extend(&[0x03, 0, 0]);
+        side.extend(b"FILE0000");
+        side.extend(&[0x00, 0x60, 4, 0, 0]);
+        side.extend(&[0x04, 1, 2, 3, 4]);
+        side.resize(SIDE_SIZE, 0);
+        side
+    }
+
+    fn test_fds() -> Fds {
+        let mut raw = FWNES_TAG.to_vec();
+        raw.push(2);
+        raw.resize(FWNES_HEADER_SIZE, 0);
+        raw.extend(test_side());
+        raw.extend(test_side());
+
+        let mut rom = Rom::new(&raw).unwrap();
+        attach_bios(&mut rom, vec![0; BIOS_SIZE]).unwrap();
+        Fds::new(rom).unwrap()
+    }
+
+    #[test]
+    fn test_parse_image() {
+        let rom = parse_image(&test_side()).unwrap();
+        assert_eq!(rom.mapper, FDS_MAPPER);
+        assert_eq!(rom.disk_sides.len(), 1);
+
+        let fds = test_fds();
+        assert_eq!(fds.side_count(), 2);
+    }
+
+    #[test]
+    fn test_raw_side_round_trip() {
+        let side = test_side();
+        let raw = to_raw_side(&side);
+        assert_eq!(raw[LEADING_GAP], GAP_END_MARK);
+        assert_eq!(raw[LEADING_GAP + 1], 0x01);
+        assert_eq!(from_raw_side(&raw), side);
+    }
+
+    #[test]
+    fn test_timer_irq() {
+        let mut fds = test_fds();
+        fds.write_expansion(0x4023, 0x01);
+        fds.write_expansion(0x4020, 10);
+        fds.write_expansion(0x4021, 0);
+        fds.write_expansion(0x4022, 0b11);
+
+        fds.clock(10);
+        assert!(!fds.irq());
+        fds.clock(1);
+        assert!(fds.irq());
+
+        // $4030を読むとIRQが解除される。
+        assert_eq!(fds.read_expansion(0x4030) & 0b1, 1);
+        assert!(!fds.irq());
+    }
+
+    #[test]
+    fn test_read_disk() {
+        let mut fds = test_fds();
+        fds.write_expansion(0x4023, 0x01);
+        // モーターON, 読み込みモード, 転送開始, IRQ有効
+        fds.write_expansion(0x4025, 0b1100_0101);
+
+        let mut data = Vec::new();
+        while data.len() < DISK_INFO_TAG.len() {
+            fds.clock(1);
+            if fds.irq() {
+                data.push(fds.read_expansion(0x4031));
+            }
+        }
+        assert_eq!(data, DISK_INFO_TAG);
+    }
+
+    #[test]
+    fn test_write_and_patch() {
+        let mut fds = test_fds();
+        let pos = LEADING_GAP + 1 + 56 + CRC_SIZE + BLOCK_GAP + 1 + 1;
+        fds.sides[1][pos] = 0x07;
+
+        let patch = fds.disk_patch();
+        let mut restored = test_fds();
+        restored.apply_disk_patch(&patch).unwrap();
+        assert_eq!(restored.disk_image(), fds.disk_image());
+        assert_eq!(restored.disk_image()[SIDE_SIZE + 57], 0x07);
+    }
+
+    #[test]
+    fn test_reject_patch_changing_sides() {
+        let mut fds = test_fds();
+        let image = fds.disk_image();
+        let one_side = patch::create_ips(&image, &image[..SIDE_SIZE]);
+        let three_sides = patch::create_ips(&image, &[image.clone(), vec![0x01; SIDE_SIZE]].concat());
+        for patch in [one_side, three_sides] {
+            assert!(fds.apply_disk_patch(&patch).is_err());
+            assert_eq!(fds.side_count(), 2);
+            assert_eq!(fds.disk_image(), image);
+        }
+
+        // 途中まで読んだ状態で差分を読み込んでも、ヘッドは先頭からやり直す。
+        fds.position = fds.sides[0].len() - 1;
+        fds.apply_disk_patch(&fds.disk_patch()).unwrap();
+        assert_eq!(fds.position, 0);
+        assert!(fds.end_of_head);
+    }
+
+    #[test]
+    fn test_switch_side() {
+        let mut fds = test_fds();
+        fds.switch_side();
+        assert_eq!(fds.inserted_side(), None);
+
+        fds.write_expansion(0x4023, 0x01);
+        assert_eq!(fds.read_expansion(0x4032) & 0b1, 1);
+        for _ in 0..DISK_SWAP_DELAY {
+            fds.clock_disk();
+        }
+        assert_eq!(fds.inserted_side(), Some(1));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::joypad::JoypadButton;
+
+// 入力マクロ。1フレームごとのボタンの状態の並びを記録して再生する。
+// 再生中のボタンは実際の入力に重ねて押される。
+#[derive(Default)]
+pub struct InputMacro {
+    frames: Vec<JoypadButton>,
+    position: Option<usize>,
+    recording: Option<Vec<JoypadButton>>,
+}
+
+// "right:10 right+a:5 none:3" の形式(ボタン:フレーム数)。フレーム数を省略すると1フレーム
+pub fn parse_sequence(sequence: &str) -> Result<Vec<JoypadButton>, String> {
+    let mut frames = Vec::new();
+    for step in sequence.split_whitespace() {
+        let (buttons, count) = match step.split_once(':') {
+            Some((buttons, count)) => {
+                let count: usize = count.parse().map_err(|_| format!("Invalid frame count: {}", step))?;
+                (buttons, count)
+            }
+            None => (step, 1),
+        };
+        let mut state = JoypadButton::empty();
+        if buttons != "none" {
+            for name in buttons.split('+') {
+                state |= JoypadButton::from_name(name).ok_or_else(|| format!("Unknown button: {}", name))?;
+            }
+        }
+        frames.extend(std::iter::repeat_n(state, count));
+    }
+    Ok(frames)
+}
+
+impl InputMacro {
+    pub fn new(frames: Vec<JoypadButton>) -> Self {
+        InputMacro {
+            frames,
+            position: None,
+            recording: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.position.is_some()
+    }
+
+    // 記録を始める。記録中に呼ぶと記録を終えてマクロを置き換える。
+    pub fn toggle_recording(&mut self) {
+        match self.recording.take() {
+            Some(frames) => self.frames = frames,
+            None => {
+                self.position = None;
+                self.recording = Some(Vec::new());
+            }
+        }
+    }
+
+    // 記録中は再生しない。再生中に呼ぶと最初からやり直す。
+    pub fn play(&mut self) {
+        if !self.is_recording() && !self.frames.is_empty() {
+            self.position = Some(0);
+        }
+    }
+
+    // 1フレームに1回呼ぶ。liveは実際の入力
+    pub fn next_frame(&mut self, live: JoypadButton) -> JoypadButton {
+        if let Some(recording) = &mut self.recording {
+            recording.push(live);
+            return live;
+        }
+        let position = match self.position {
+            Some(position) => position,
+            None => return live,
+        };
+        let pressed = live | self.frames[position];
+        self.position = Some(position + 1).filter(|&p| p < self.frames.len());
+        pressed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_sequence() {
+        let frames = parse_sequence("right:2 right+a none:1").unwrap();
+        assert_eq!(
+            frames,
+            [
+                JoypadButton::RIGHT,
+                JoypadButton::RIGHT,
+                JoypadButton::RIGHT | JoypadButton::A,
+                JoypadButton::empty()
+            ]
+        );
+        assert!(parse_sequence("turbo:2").is_err());
+        assert!(parse_sequence("a:x").is_err());
+    }
+
+    #[test]
+    fn test_record_and_play() {
+        let mut input_macro = InputMacro::default();
+        input_macro.toggle_recording();
+        input_macro.next_frame(JoypadButton::A);
+        input_macro.next_frame(JoypadButton::empty());
+        input_macro.next_frame(JoypadButton::B);
+        input_macro.toggle_recording();
+        assert_eq!(input_macro.len(), 3);
+
+        input_macro.play();
+        assert_eq!(input_macro.next_frame(JoypadButton::UP), JoypadButton::A | JoypadButton::UP);
+        assert_eq!(input_macro.next_frame(JoypadButton::empty()), JoypadButton::empty());
+        assert_eq!(input_macro.next_frame(JoypadButton::empty()), JoypadButton::B);
+        assert!(!input_macro.is_playing());
+        assert_eq!(input_macro.next_frame(JoypadButton::empty()), JoypadButton::empty());
+    }
+}
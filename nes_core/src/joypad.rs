@@ -0,0 +1,142 @@
+use crate::ports::InputDevice;
+use bitflags::bitflags;
+use crate::savestate::{impl_save_state, SaveState, StateReader, StateWriter};
+
+bitflags! {
+    // $4016/$4017から読み出される順(A, B, Select, Start, 上, 下, 左, 右)
+    #[derive(Default)]
+    pub struct JoypadButton: u8 {
+        const RIGHT  = 0b1000_0000;
+        const LEFT   = 0b0100_0000;
+        const DOWN   = 0b0010_0000;
+        const UP     = 0b0001_0000;
+        const START  = 0b0000_1000;
+        const SELECT = 0b0000_0100;
+        const B      = 0b0000_0010;
+        const A      = 0b0000_0001;
+    }
+}
+
+impl JoypadButton {
+    pub fn from_name(name: &str) -> Option<JoypadButton> {
+        match name {
+            "a" => Some(JoypadButton::A),
+            "b" => Some(JoypadButton::B),
+            "select" => Some(JoypadButton::SELECT),
+            "start" => Some(JoypadButton::START),
+            "up" => Some(JoypadButton::UP),
+            "down" => Some(JoypadButton::DOWN),
+            "left" => Some(JoypadButton::LEFT),
+            "right" => Some(JoypadButton::RIGHT),
+            _ => None,
+        }
+    }
+}
+
+impl SaveState for JoypadButton {
+    fn save(&self, w: &mut StateWriter) {
+        self.bits().save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut bits = 0u8;
+        bits.load(r)?;
+        *self = JoypadButton::from_bits_truncate(bits);
+        Ok(())
+    }
+}
+
+// 標準コントローラー。ストローブ(bit0)を1から0にした時点のボタンの状態を1bitずつ読み出す。
+pub struct Joypad {
+    strobe: bool,
+    button_index: u8,
+    button_status: JoypadButton,
+}
+
+impl_save_state!(Joypad { strobe, button_index, button_status });
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Joypad::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            button_index: 0,
+            button_status: JoypadButton::empty(),
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+
+    // 8ボタンを読み終わった後は1を返す。
+    pub fn read(&mut self) -> u8 {
+        if self.button_index > 7 {
+            return 1;
+        }
+        let response = (self.button_status.bits() >> self.button_index) & 1;
+        if !self.strobe {
+            self.button_index += 1;
+        }
+        response
+    }
+
+    pub fn buttons(&self) -> JoypadButton {
+        self.button_status
+    }
+
+    pub fn set_buttons(&mut self, buttons: JoypadButton) {
+        self.button_status = buttons;
+    }
+
+    pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
+        self.button_status.set(button, pressed);
+    }
+}
+
+impl InputDevice for Joypad {
+    fn write(&mut self, data: u8) {
+        Joypad::write(self, data);
+    }
+
+    fn read(&mut self, _address: u16, _cycles: u64) -> u8 {
+        Joypad::read(self)
+    }
+
+    fn joypad_mut(&mut self, index: usize) -> Option<&mut Joypad> {
+        Some(self).filter(|_| index == 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strobe_and_read() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(JoypadButton::A | JoypadButton::START | JoypadButton::RIGHT);
+
+        // ストローブ中は常にAを返す。
+        joypad.write(1);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 1);
+
+        joypad.write(0);
+        let bits: Vec<u8> = (0..10).map(|_| joypad.read()).collect();
+        assert_eq!(bits, [1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+
+        joypad.set_button_pressed_status(JoypadButton::A, false);
+        joypad.write(1);
+        joypad.write(0);
+        assert_eq!(joypad.read(), 0);
+    }
+}
@@ -0,0 +1,36 @@
+// エミュレーターの本体。SDLに依存しないので、SDLのフロントエンド(ワークスペースのルートのmain.rs)と
+// ヘッドレスの実行環境(bin/nes-headless.rs)の両方から使う。フレーム単位の実行はどちらもNesにまとめてある。
+pub mod apu;
+pub mod autofire;
+pub mod bus;
+pub mod cartridge;
+pub mod cpu;
+pub mod expansion;
+pub mod family_keyboard;
+pub mod fds;
+pub mod input_macro;
+pub mod joypad;
+pub mod loader;
+pub mod mapper;
+pub mod movie;
+pub mod nes;
+pub mod nsf;
+pub mod opcodes;
+pub mod paddle;
+pub mod patch;
+pub mod png;
+pub mod ports;
+pub mod power_pad;
+pub mod rewind;
+pub mod romdb;
+pub mod run_ahead;
+pub mod savestate;
+pub mod snake;
+pub mod snes_mouse;
+pub mod sram;
+pub mod unif;
+pub mod wav;
+pub mod zapper;
+
+pub use cpu::{Mem, CPU, FRAME_CYCLES, NTSC_FRAME_RATE};
+pub use nes::Nes;
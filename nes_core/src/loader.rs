@@ -0,0 +1,140 @@
+use crate::cartridge::MAX_ROM_SIZE;
+use crate::patch;
+use flate2::read::GzDecoder;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+// アーカイブの中から探すROMの拡張子
+pub const ROM_EXTENSIONS: [&str; 4] = ["nes", "fds", "nsf", "unf"];
+
+// ROMファイルを読み込み、パッチがあれば適用してからRom::newに渡すバイト列を返す。
+// zip/gzipは中身を展開する。zipの場合はentryで指定したファイル、なければ最初のROMを使う。
+// patch_pathが指定されていない場合はROMと同名の.ips/.bps/.upsを探す。
+pub fn load_rom(rom_path: &Path, patch_path: Option<&Path>, entry: Option<&str>) -> Result<Vec<u8>, String> {
+    let file = std::fs::read(rom_path)
+        .map_err(|e| format!("Failed to read {}: {}", rom_path.display(), e))?;
+    let raw = extract(file, entry).map_err(|e| format!("{}: {}", rom_path.display(), e))?;
+
+    let patch_path = match patch_path {
+        Some(path) => Some(path.to_path_buf()),
+        None => patch::find_patch(rom_path),
+    };
+
+    match patch_path {
+        Some(path) => {
+            let data = std::fs::read(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            patch::apply(&raw, &data).map_err(|e| format!("{}: {}", path.display(), e))
+        }
+        None => Ok(raw),
+    }
+}
+
+// 拡張子ではなく先頭のマジックナンバーで判別する。
+pub fn extract(file: Vec<u8>, entry: Option<&str>) -> Result<Vec<u8>, String> {
+    if file.starts_with(&ZIP_MAGIC) {
+        extract_zip(file, entry)
+    } else if file.starts_with(&GZIP_MAGIC) {
+        read_limited(GzDecoder::new(&file[..]), MAX_ROM_SIZE).map_err(|e| format!("Failed to decompress gzip: {}", e))
+    } else {
+        Ok(file)
+    }
+}
+
+// 展開後の大きさはヘッダーの値を信用せず、実際に読んだ量で上限を確かめる。
+fn read_limited(reader: impl Read, limit: usize) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| e.to_string())?;
+    if data.len() > limit {
+        return Err(format!("File is larger than {} bytes", limit));
+    }
+    Ok(data)
+}
+
+fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ROM_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+fn extract_zip(file: Vec<u8>, entry: Option<&str>) -> Result<Vec<u8>, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(file))
+        .map_err(|e| format!("Failed to open zip: {}", e))?;
+
+    let name = match entry {
+        Some(name) => name.to_string(),
+        None => archive
+            .file_names()
+            .filter(|name| is_rom_name(name))
+            .min_by_key(|name| archive.index_for_name(name))
+            .map(|name| name.to_string())
+            .ok_or("No ROM file found in zip")?,
+    };
+
+    let zip_file = archive
+        .by_name(&name)
+        .map_err(|e| format!("Failed to open {} in zip: {}", name, e))?;
+    read_limited(zip_file, MAX_ROM_SIZE).map_err(|e| format!("Failed to extract {}: {}", name, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn create_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, data) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_extract_zip_first_rom() {
+        let zip = create_zip(&[("readme.txt", b"hello"), ("Game.NES", b"rom1"), ("other.nes", b"rom2")]);
+        assert_eq!(extract(zip, None).unwrap(), b"rom1");
+    }
+
+    #[test]
+    fn test_extract_zip_named_entry() {
+        let zip = create_zip(&[("a.nes", b"rom1"), ("b.nes", b"rom2")]);
+        assert_eq!(extract(zip, Some("b.nes")).unwrap(), b"rom2");
+    }
+
+    #[test]
+    fn test_extract_zip_without_rom() {
+        let zip = create_zip(&[("readme.txt", b"hello")]);
+        assert!(extract(zip, None).is_err());
+    }
+
+    #[test]
+    fn test_extract_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"NES\x1a rom").unwrap();
+        let gz = encoder.finish().unwrap();
+        assert_eq!(extract(gz, None).unwrap(), b"NES\x1a rom");
+    }
+
+    #[test]
+    fn test_read_limited() {
+        assert_eq!(read_limited(&b"rom"[..], 3).unwrap(), b"rom");
+        assert!(read_limited(&b"rom!"[..], 3).is_err());
+    }
+
+    #[test]
+    fn test_extract_raw() {
+        assert_eq!(extract(b"NES\x1a".to_vec(), None).unwrap(), b"NES\x1a");
+    }
+}
@@ -0,0 +1,207 @@
+mod fme7;
+mod mmc5;
+mod multicart;
+mod namco163;
+mod vrc6;
+mod vrc7;
+mod vrc_irq;
+
+use crate::cartridge::Rom;
+use crate::expansion::ExpansionChip;
+use crate::fds::{self, Fds};
+use crate::nsf::{self, Nsf};
+use crate::savestate::{impl_save_state, SaveState};
+
+const PRG_RAM_START: u16 = 0x6000;
+
+// カートリッジ側($4020-$FFFF)のアクセスはすべてMapperを経由する。
+// セーブステートにはバンクレジスタやRAMなどROM以外の状態を保存する。
+pub trait Mapper: SaveState {
+    // 副作用のない読み込み。Bus::peekやデバッグ表示からも呼ばれる。
+    fn read_prg(&self, addr: u16) -> u8;
+
+    // CPUが$8000-$FFFFを読んだ。読み込みで状態が変わるマッパーはこちらで変える。
+    fn cpu_read_prg(&mut self, addr: u16) -> u8 {
+        self.read_prg(addr)
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8);
+
+    fn read_prg_ram(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn write_prg_ram(&mut self, _addr: u16, _data: u8) {}
+
+    // $4020-$5FFFの拡張領域
+    fn read_expansion(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn write_expansion(&mut self, _addr: u16, _data: u8) {}
+
+    // CPUのサイクル数だけマッパー内部のタイマー等を進める。
+    fn clock(&mut self, _cycles: u8) {}
+
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // 拡張音源の出力。内蔵音源のミキサー出力と同じ尺度の振幅で返す。
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    fn expansion_chip(&self) -> Option<ExpansionChip> {
+        None
+    }
+
+    // ディスクの入れ替えなどフロントエンドからの操作用
+    fn as_fds_mut(&mut self) -> Option<&mut Fds> {
+        None
+    }
+
+    // 曲の選択用
+    fn as_nsf_mut(&mut self) -> Option<&mut Nsf> {
+        None
+    }
+
+    // バッテリーバックアップされたPRG-RAM。.savファイルとの読み書きに使う。
+    fn battery_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+}
+
+pub fn new_mapper(rom: Rom) -> Result<Box<dyn Mapper>, String> {
+    match rom.mapper {
+        0 => Ok(Box::new(Nrom::new(rom))),
+        5 => Ok(Box::new(mmc5::Mmc5::new(rom))),
+        58 | 201 => Ok(Box::new(multicart::AddressLatch::new(rom))),
+        19 => Ok(Box::new(namco163::Namco163::new(rom))),
+        fds::FDS_MAPPER => Ok(Box::new(Fds::new(rom)?)),
+        24 => Ok(Box::new(vrc6::Vrc6::new(rom, false))),
+        26 => Ok(Box::new(vrc6::Vrc6::new(rom, true))),
+        nsf::NSF_MAPPER => Ok(Box::new(Nsf::new(rom)?)),
+        69 => Ok(Box::new(fme7::Fme7::new(rom))),
+        85 => Ok(Box::new(vrc7::Vrc7::new(rom))),
+        n => Err(format!("Mapper {} is not supported", n)),
+    }
+}
+
+// sizeバイト単位のbank番目のバンクを読む。バンク番号はROMのサイズで折り返す。
+pub(crate) fn read_bank(rom: &[u8], bank: usize, size: usize, offset: usize) -> u8 {
+    let banks = (rom.len() / size).max(1);
+    rom[(bank % banks) * size + offset % size]
+}
+
+pub struct PrgRam {
+    data: Vec<u8>,
+    battery: bool,
+}
+
+impl_save_state!(PrgRam { data });
+
+impl PrgRam {
+    pub fn new(size: usize, battery: bool) -> Self {
+        PrgRam {
+            data: vec![0; size],
+            battery,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        if self.data.is_empty() {
+            return 0;
+        }
+        let offset = (addr - PRG_RAM_START) as usize % self.data.len();
+        self.data[offset]
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        if self.data.is_empty() {
+            return;
+        }
+        let offset = (addr - PRG_RAM_START) as usize % self.data.len();
+        self.data[offset] = data;
+    }
+
+    // バンク切り替えのあるマッパー用。offsetはRAMの先頭からの位置
+    pub fn read_offset(&self, offset: usize) -> u8 {
+        if self.data.is_empty() {
+            return 0;
+        }
+        self.data[offset % self.data.len()]
+    }
+
+    pub fn write_offset(&mut self, offset: usize, data: u8) {
+        if self.data.is_empty() {
+            return;
+        }
+        let len = self.data.len();
+        self.data[offset % len] = data;
+    }
+
+    pub fn battery_ram(&self) -> Option<&[u8]> {
+        if self.battery {
+            Some(&self.data)
+        } else {
+            None
+        }
+    }
+
+    pub fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        if self.battery {
+            Some(&mut self.data)
+        } else {
+            None
+        }
+    }
+}
+
+// Mapper 0
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+}
+
+impl_save_state!(Nrom { prg_ram });
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Self {
+        Nrom {
+            prg_ram: PrgRam::new(rom.prg_ram_size, rom.battery),
+            prg_rom: rom.prg_rom,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    // 16KBのROMは$C000にも同じ内容が出る。それ以外の大きさも範囲外を読まないよう折り返す。
+    fn read_prg(&self, addr: u16) -> u8 {
+        self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()]
+    }
+
+    fn write_prg(&mut self, _addr: u16, _data: u8) {
+        panic!("Attempt to write to Cartridge ROM space")
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
+        self.prg_ram.read(addr)
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        self.prg_ram.write(addr, data)
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.battery_ram_mut()
+    }
+}
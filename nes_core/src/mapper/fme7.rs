@@ -0,0 +1,191 @@
+use super::{read_bank, Mapper, PrgRam};
+use crate::cartridge::Rom;
+use crate::expansion::sunsoft5b::Sunsoft5bAudio;
+use crate::expansion::ExpansionChip;
+use crate::savestate::impl_save_state;
+
+// Mapper 69(Sunsoft FME-7 / 5B)
+// $8000にコマンド番号、$A000に値を書く。$6000-$7FFFもROMかRAMに切り替えられる。
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    command: u8,
+    // コマンド8($6000), 9-B($8000, $A000, $C000)
+    prg_banks: [u8; 4],
+    // CHRバンクはPPUの実装がないので保持するだけ
+    chr_banks: [u8; 8],
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+    audio: Sunsoft5bAudio,
+}
+
+impl_save_state!(Fme7 {
+    prg_ram,
+    command,
+    prg_banks,
+    chr_banks,
+    irq_enabled,
+    irq_counter_enabled,
+    irq_counter,
+    irq_pending,
+    audio,
+});
+
+impl Fme7 {
+    pub fn new(rom: Rom) -> Self {
+        Fme7 {
+            prg_ram: PrgRam::new(rom.prg_ram_size, rom.battery),
+            prg_rom: rom.prg_rom,
+            command: 0,
+            prg_banks: [0; 4],
+            chr_banks: [0; 8],
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5bAudio::default(),
+        }
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = data,
+            0x8..=0xB => self.prg_banks[(self.command - 8) as usize] = data,
+            0xD => {
+                self.irq_enabled = data & 0b0000_0001 != 0;
+                self.irq_counter_enabled = data & 0b1000_0000 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            0xF => self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8,
+            _ => {}
+        }
+    }
+
+    // $6000-$7FFF: bit6がRAM選択、bit7がRAM有効
+    fn prg_ram_selected(&self) -> bool {
+        self.prg_banks[0] & 0b0100_0000 != 0
+    }
+}
+
+impl Mapper for Fme7 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x8000..=0x9FFF => self.prg_banks[1] as usize & 0x3F,
+            0xA000..=0xBFFF => self.prg_banks[2] as usize & 0x3F,
+            0xC000..=0xDFFF => self.prg_banks[3] as usize & 0x3F,
+            _ => self.prg_rom.len() / 0x2000 - 1,
+        };
+        read_bank(&self.prg_rom, bank, 0x2000, addr as usize & 0x1FFF)
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr & 0xE000 {
+            0x8000 => self.command = data & 0x0F,
+            0xA000 => self.write_parameter(data),
+            0xC000 => self.audio.write_register(data),
+            _ => self.audio.write_data(data),
+        }
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
+        if !self.prg_ram_selected() {
+            let bank = self.prg_banks[0] as usize & 0x3F;
+            return read_bank(&self.prg_rom, bank, 0x2000, addr as usize & 0x1FFF);
+        }
+        if self.prg_banks[0] & 0b1000_0000 != 0 {
+            self.prg_ram.read(addr)
+        } else {
+            0
+        }
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        if self.prg_ram_selected() && self.prg_banks[0] & 0b1000_0000 != 0 {
+            self.prg_ram.write(addr, data);
+        }
+    }
+
+    // カウンターは毎サイクル減り、$0000から$FFFFになった時にIRQ
+    fn clock(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            if self.irq_counter_enabled {
+                self.irq_counter = self.irq_counter.wrapping_sub(1);
+                if self.irq_counter == 0xFFFF && self.irq_enabled {
+                    self.irq_pending = true;
+                }
+            }
+            self.audio.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn expansion_chip(&self) -> Option<ExpansionChip> {
+        Some(ExpansionChip::Sunsoft5b)
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.battery_ram_mut()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::romdb::RomHash;
+    use crate::cartridge::{Mirroring, TvSystem};
+
+    #[test]
+    fn test_prg_banks_and_irq() {
+        // 8KBごとにバンク番号を埋めた64KBのROM
+        let prg_rom: Vec<u8> = (0..8u8).flat_map(|bank| vec![bank; 0x2000]).collect();
+        let rom = Rom {
+            hash: RomHash::new(&prg_rom, &[]),
+            prg_rom,
+            chr_rom: Vec::new(),
+            mapper: 69,
+            screen_mirroring: Mirroring::VERTICAL,
+            battery: false,
+            prg_ram_size: 0x2000,
+            tv_system: TvSystem::NTSC,
+            title: None,
+            disk_sides: Vec::new(),
+            nsf: None,
+        };
+        let mut mapper = Fme7::new(rom);
+        mapper.write_prg(0x8000, 0x9);
+        mapper.write_prg(0xA000, 3);
+        mapper.write_prg(0x8000, 0x8);
+        mapper.write_prg(0xA000, 5);
+        assert_eq!(mapper.read_prg(0x8000), 3);
+        assert_eq!(mapper.read_prg(0xE000), 7);
+        assert_eq!(mapper.read_prg_ram(0x6000), 5);
+
+        // RAMを有効にすると書き込める。
+        mapper.write_prg(0xA000, 0b1100_0000);
+        mapper.write_prg_ram(0x6000, 0x42);
+        assert_eq!(mapper.read_prg_ram(0x6000), 0x42);
+
+        mapper.write_prg(0x8000, 0xE);
+        mapper.write_prg(0xA000, 2);
+        mapper.write_prg(0x8000, 0xD);
+        mapper.write_prg(0xA000, 0b1000_0001);
+        mapper.clock(2);
+        assert!(!mapper.irq());
+        mapper.clock(1);
+        assert!(mapper.irq());
+    }
+}
@@ -0,0 +1,255 @@
+use super::{read_bank, Mapper, PrgRam};
+use crate::cartridge::Rom;
+use crate::expansion::mmc5::Mmc5Audio;
+use crate::expansion::ExpansionChip;
+use crate::savestate::impl_save_state;
+
+const EXRAM_SIZE: usize = 0x400;
+
+// Mapper 5(MMC5)
+// PRGは$5100のモードで32KB/16KB/16KB+8KB/8KBの単位で切り替え、$5114-$5116のbit7が0のバンクはRAMになる。
+// スキャンラインIRQ、CHR、拡張ネームテーブルはPPUの実装がないので対応していない。
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    prg_mode: u8,
+    // $5113-$5117
+    prg_banks: [u8; 5],
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    exram: [u8; EXRAM_SIZE],
+    multiplicand: u8,
+    multiplier: u8,
+    audio: Mmc5Audio,
+}
+
+impl_save_state!(Mmc5 {
+    prg_ram,
+    prg_mode,
+    prg_banks,
+    prg_ram_protect,
+    exram_mode,
+    exram,
+    multiplicand,
+    multiplier,
+    audio,
+});
+
+impl Mmc5 {
+    pub fn new(rom: Rom) -> Self {
+        Mmc5 {
+            prg_ram: PrgRam::new(rom.prg_ram_size, rom.battery),
+            prg_rom: rom.prg_rom,
+            prg_mode: 3,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            exram: [0; EXRAM_SIZE],
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            audio: Mmc5Audio::default(),
+        }
+    }
+
+    // addr($8000-$FFFF)に割り当てられたバンクのレジスタ値とバンクの大きさ
+    fn prg_bank(&self, addr: u16) -> (u8, usize) {
+        let (register, size) = match (self.prg_mode, addr) {
+            (0, _) => (4, 0x8000),
+            (1, 0x8000..=0xBFFF) => (2, 0x4000),
+            (1, _) => (4, 0x4000),
+            (2, 0x8000..=0xBFFF) => (2, 0x4000),
+            (2, 0xC000..=0xDFFF) => (3, 0x2000),
+            (2, _) => (4, 0x2000),
+            (_, _) => (1 + ((addr - 0x8000) / 0x2000) as usize, 0x2000),
+        };
+        let value = self.prg_banks[register];
+        // $5117は常にROM
+        let value = if register == 4 { value | 0x80 } else { value };
+        (value, size)
+    }
+
+    // 8KB単位のバンク番号と、ROMかどうか
+    fn map_prg(&self, addr: u16) -> (bool, usize, usize) {
+        let (value, size) = self.prg_bank(addr);
+        let units = size / 0x2000;
+        let offset = (addr as usize - 0x8000) % size;
+        let bank = (value as usize & 0x7F & !(units - 1)) + offset / 0x2000;
+        (value & 0x80 != 0, bank, offset & 0x1FFF)
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01]
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let (is_rom, bank, offset) = self.map_prg(addr);
+        if is_rom {
+            read_bank(&self.prg_rom, bank, 0x2000, offset)
+        } else {
+            self.prg_ram.read_offset((bank & 0x07) * 0x2000 + offset)
+        }
+    }
+
+    // PCMの読み込みモードでは$8000-$BFFFから読んだ値を鳴らす。
+    fn cpu_read_prg(&mut self, addr: u16) -> u8 {
+        let data = self.read_prg(addr);
+        if addr < 0xC000 {
+            self.audio.read_pcm(data);
+        }
+        data
+    }
+
+    // ROMへの書き込みは無視される。
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        let (is_rom, bank, offset) = self.map_prg(addr);
+        if !is_rom && self.prg_ram_writable() {
+            self.prg_ram.write_offset((bank & 0x07) * 0x2000 + offset, data);
+        }
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
+        let bank = (self.prg_banks[0] & 0x07) as usize;
+        self.prg_ram.read_offset(bank * 0x2000 + (addr as usize & 0x1FFF))
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        if self.prg_ram_writable() {
+            let bank = (self.prg_banks[0] & 0x07) as usize;
+            self.prg_ram.write_offset(bank * 0x2000 + (addr as usize & 0x1FFF), data);
+        }
+    }
+
+    fn read_expansion(&mut self, addr: u16) -> u8 {
+        if let Some(data) = self.audio.read(addr) {
+            return data;
+        }
+        match addr {
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[(addr - 0x5C00) as usize],
+            _ => 0,
+        }
+    }
+
+    fn write_expansion(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5015 => self.audio.write(addr, data),
+            0x5100 => self.prg_mode = data & 0b11,
+            0x5102 => self.prg_ram_protect[0] = data & 0b11,
+            0x5103 => self.prg_ram_protect[1] = data & 0b11,
+            0x5104 => self.exram_mode = data & 0b11,
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = data,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            // モード3では読み込み専用
+            0x5C00..=0x5FFF if self.exram_mode != 3 => self.exram[(addr - 0x5C00) as usize] = data,
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.audio.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.audio.irq()
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn expansion_chip(&self) -> Option<ExpansionChip> {
+        Some(ExpansionChip::Mmc5)
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.battery_ram_mut()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{Mirroring, TvSystem};
+    use crate::romdb::RomHash;
+
+    fn test_mapper() -> Mmc5 {
+        // 8KBごとにバンク番号を埋めた128KBのROM
+        let prg_rom: Vec<u8> = (0..16u8).flat_map(|bank| vec![bank; 0x2000]).collect();
+        Mmc5::new(Rom {
+            hash: RomHash::new(&prg_rom, &[]),
+            prg_rom,
+            chr_rom: Vec::new(),
+            mapper: 5,
+            screen_mirroring: Mirroring::VERTICAL,
+            battery: false,
+            prg_ram_size: 0x4000,
+            tv_system: TvSystem::NTSC,
+            title: None,
+            disk_sides: Vec::new(),
+            nsf: None,
+        })
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mapper = test_mapper();
+        // 電源投入時はモード3で、$E000-$FFFFに最後のバンク
+        assert_eq!(mapper.read_prg(0xE000), 15);
+
+        mapper.write_expansion(0x5100, 1);
+        mapper.write_expansion(0x5115, 0x80 | 5);
+        mapper.write_expansion(0x5117, 9);
+        assert_eq!(mapper.read_prg(0x8000), 4);
+        assert_eq!(mapper.read_prg(0xA000), 5);
+        assert_eq!(mapper.read_prg(0xC000), 8);
+        assert_eq!(mapper.read_prg(0xE000), 9);
+
+        mapper.write_expansion(0x5100, 0);
+        assert_eq!(mapper.read_prg(0x8000), 8);
+        assert_eq!(mapper.read_prg(0xE000), 11);
+    }
+
+    #[test]
+    fn test_prg_ram_in_rom_space() {
+        let mut mapper = test_mapper();
+        mapper.write_expansion(0x5114, 0x01);
+        mapper.write_prg(0x8000, 0x42);
+        assert_eq!(mapper.read_prg(0x8000), 0);
+
+        mapper.write_expansion(0x5102, 0b10);
+        mapper.write_expansion(0x5103, 0b01);
+        mapper.write_prg(0x8000, 0x42);
+        assert_eq!(mapper.read_prg(0x8000), 0x42);
+        // 同じRAMのバンクを$6000に割り当てる。
+        mapper.write_expansion(0x5113, 0x01);
+        assert_eq!(mapper.read_prg_ram(0x6000), 0x42);
+
+        mapper.write_expansion(0x5205, 200);
+        mapper.write_expansion(0x5206, 100);
+        assert_eq!(mapper.read_expansion(0x5205), (20000 & 0xFF) as u8);
+        assert_eq!(mapper.read_expansion(0x5206), (20000 >> 8) as u8);
+    }
+
+    // PCMの読み込みモードでも、read_prg(Bus::peekなど)ではIRQは起きない。
+    #[test]
+    fn test_pcm_read_only_on_cpu_read() {
+        let mut mapper = test_mapper();
+        // $8000にバンク0(中身は0)
+        mapper.write_expansion(0x5114, 0x80);
+        mapper.write_expansion(0x5010, 0b1000_0001);
+        assert_eq!(mapper.read_prg(0x8000), 0);
+        assert!(!mapper.irq());
+        assert_eq!(mapper.cpu_read_prg(0x8000), 0);
+        assert!(mapper.irq());
+    }
+}
@@ -0,0 +1,91 @@
+use super::{read_bank, Mapper};
+use crate::cartridge::Rom;
+use crate::savestate::impl_save_state;
+
+// 書き込んだアドレスの下位ビットでバンクを選ぶマルチカート。書き込む値は使わない。
+//   Mapper 58 (GK-192など): A~[.... .... MOCC CPPP] P: PRGバンク, O: 1なら16KB単位で$C000にも同じバンク,
+//                          C: 8KBのCHRバンク, M: ミラーリング
+//   Mapper 201 (NovelDiamond 9999999-in-1など): A~[.... .... BBBB BBBB] B: 32KBのPRGバンクと8KBのCHRバンク
+pub struct AddressLatch {
+    mapper: u8,
+    prg_rom: Vec<u8>,
+    latch: u16,
+}
+
+// CHRバンクとミラーリングはPPUの実装がないので、latchに保持するだけ
+impl_save_state!(AddressLatch { latch });
+
+impl AddressLatch {
+    pub fn new(rom: Rom) -> Self {
+        AddressLatch {
+            mapper: rom.mapper,
+            prg_rom: rom.prg_rom,
+            latch: 0,
+        }
+    }
+}
+
+impl Mapper for AddressLatch {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let offset = (addr - 0x8000) as usize;
+        let latch = self.latch as usize;
+        match self.mapper {
+            58 if latch & 0b0100_0000 != 0 => read_bank(&self.prg_rom, latch & 0b111, 0x4000, offset),
+            58 => read_bank(&self.prg_rom, (latch & 0b110) >> 1, 0x8000, offset),
+            _ => read_bank(&self.prg_rom, latch & 0xFF, 0x8000, offset),
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, _data: u8) {
+        self.latch = addr;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{Mirroring, TvSystem};
+    use crate::romdb::RomHash;
+
+    // 16KBごとに番号を埋めたPRG-ROM
+    fn rom(mapper: u8, banks: u8) -> Rom {
+        let prg_rom: Vec<u8> = (0..banks).flat_map(|bank| vec![bank; 0x4000]).collect();
+        Rom {
+            hash: RomHash::new(&prg_rom, &[]),
+            prg_rom,
+            chr_rom: Vec::new(),
+            mapper,
+            screen_mirroring: Mirroring::VERTICAL,
+            battery: false,
+            prg_ram_size: 0,
+            tv_system: TvSystem::NTSC,
+            title: None,
+            disk_sides: Vec::new(),
+            nsf: None,
+        }
+    }
+
+    #[test]
+    fn test_mapper_58() {
+        let mut mapper = AddressLatch::new(rom(58, 8));
+        // 32KBモード: バンク2,3
+        mapper.write_prg(0x8002, 0);
+        assert_eq!(mapper.read_prg(0x8000), 2);
+        assert_eq!(mapper.read_prg(0xC000), 3);
+        // 16KBモード: 同じバンクが$8000と$C000に出る
+        mapper.write_prg(0x8045, 0);
+        assert_eq!(mapper.read_prg(0x8000), 5);
+        assert_eq!(mapper.read_prg(0xFFFF), 5);
+    }
+
+    #[test]
+    fn test_mapper_201() {
+        let mut mapper = AddressLatch::new(rom(201, 8));
+        mapper.write_prg(0xC003, 0xFF);
+        assert_eq!(mapper.read_prg(0x8000), 6);
+        assert_eq!(mapper.read_prg(0xC000), 7);
+        // ROMより大きいバンク番号は折り返す
+        mapper.write_prg(0x8005, 0);
+        assert_eq!(mapper.read_prg(0x8000), 2);
+    }
+}
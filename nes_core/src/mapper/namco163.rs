@@ -0,0 +1,125 @@
+use super::{read_bank, Mapper, PrgRam};
+use crate::cartridge::Rom;
+use crate::expansion::n163::N163Audio;
+use crate::expansion::ExpansionChip;
+use crate::savestate::impl_save_state;
+
+// Mapper 19(Namco 163)
+// $8000, $A000, $C000: 8KB切り替え, $E000-$FFFF: 最後の8KB固定
+pub struct Namco163 {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    prg_banks: [u8; 3],
+    // CHRバンクはPPUの実装がないので保持するだけ
+    chr_banks: [u8; 12],
+    // 15bitのカウンターで、bit15が有効フラグ。$7FFFに達するとIRQ。
+    irq_counter: u16,
+    irq_pending: bool,
+    audio: N163Audio,
+}
+
+impl_save_state!(Namco163 { prg_ram, prg_banks, chr_banks, irq_counter, irq_pending, audio });
+
+impl Namco163 {
+    pub fn new(rom: Rom) -> Self {
+        Namco163 {
+            prg_ram: PrgRam::new(rom.prg_ram_size, rom.battery),
+            prg_rom: rom.prg_rom,
+            prg_banks: [0; 3],
+            chr_banks: [0; 12],
+            irq_counter: 0,
+            irq_pending: false,
+            audio: N163Audio::default(),
+        }
+    }
+}
+
+impl Mapper for Namco163 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x8000..=0x9FFF => self.prg_banks[0] as usize,
+            0xA000..=0xBFFF => self.prg_banks[1] as usize,
+            0xC000..=0xDFFF => self.prg_banks[2] as usize,
+            _ => self.prg_rom.len() / 0x2000 - 1,
+        };
+        read_bank(&self.prg_rom, bank, 0x2000, addr as usize & 0x1FFF)
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr & 0xF800 {
+            0x8000..=0xD800 => self.chr_banks[((addr - 0x8000) >> 11) as usize] = data,
+            0xE000 => {
+                self.prg_banks[0] = data & 0x3F;
+                self.audio.set_disabled(data & 0b0100_0000 != 0);
+            }
+            0xE800 => self.prg_banks[1] = data & 0x3F,
+            0xF000 => self.prg_banks[2] = data & 0x3F,
+            0xF800 => self.audio.write_address(data),
+            _ => {}
+        }
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
+        self.prg_ram.read(addr)
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        self.prg_ram.write(addr, data);
+    }
+
+    fn read_expansion(&mut self, addr: u16) -> u8 {
+        match addr & 0xF800 {
+            0x4800 => self.audio.read_data(),
+            0x5000 => self.irq_counter as u8,
+            0x5800 => (self.irq_counter >> 8) as u8,
+            _ => 0,
+        }
+    }
+
+    fn write_expansion(&mut self, addr: u16, data: u8) {
+        match addr & 0xF800 {
+            0x4800 => self.audio.write_data(data),
+            0x5000 => {
+                self.irq_counter = (self.irq_counter & 0xFF00) | data as u16;
+                self.irq_pending = false;
+            }
+            0x5800 => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8;
+                self.irq_pending = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            if self.irq_counter & 0x8000 != 0 && self.irq_counter & 0x7FFF != 0x7FFF {
+                self.irq_counter += 1;
+                if self.irq_counter & 0x7FFF == 0x7FFF {
+                    self.irq_pending = true;
+                }
+            }
+            self.audio.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn expansion_chip(&self) -> Option<ExpansionChip> {
+        Some(ExpansionChip::N163)
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.battery_ram_mut()
+    }
+}
@@ -0,0 +1,123 @@
+use super::vrc_irq::VrcIrq;
+use super::{read_bank, Mapper, PrgRam};
+use crate::cartridge::Rom;
+use crate::expansion::vrc6::Vrc6Audio;
+use crate::expansion::ExpansionChip;
+use crate::savestate::impl_save_state;
+
+// Mapper 24(VRC6a), 26(VRC6b)
+// $8000-$BFFF: 16KB切り替え, $C000-$DFFF: 8KB切り替え, $E000-$FFFF: 最後の8KB固定
+pub struct Vrc6 {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    // VRC6bはA0とA1が入れ替わっている。
+    swap_address_lines: bool,
+    prg_bank_16k: u8,
+    prg_bank_8k: u8,
+    prg_ram_enabled: bool,
+    // CHRバンクはPPUの実装がないので保持するだけ
+    chr_banks: [u8; 8],
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+}
+
+impl_save_state!(Vrc6 {
+    prg_ram,
+    prg_bank_16k,
+    prg_bank_8k,
+    prg_ram_enabled,
+    chr_banks,
+    irq,
+    audio,
+});
+
+impl Vrc6 {
+    pub fn new(rom: Rom, swap_address_lines: bool) -> Self {
+        Vrc6 {
+            prg_ram: PrgRam::new(rom.prg_ram_size, rom.battery),
+            prg_rom: rom.prg_rom,
+            swap_address_lines,
+            prg_bank_16k: 0,
+            prg_bank_8k: 0,
+            prg_ram_enabled: false,
+            chr_banks: [0; 8],
+            irq: VrcIrq::default(),
+            audio: Vrc6Audio::default(),
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let offset = addr as usize & 0x1FFF;
+        match addr {
+            0x8000..=0xBFFF => read_bank(&self.prg_rom, self.prg_bank_16k as usize, 0x4000, addr as usize & 0x3FFF),
+            0xC000..=0xDFFF => read_bank(&self.prg_rom, self.prg_bank_8k as usize, 0x2000, offset),
+            _ => read_bank(&self.prg_rom, self.prg_rom.len() / 0x2000 - 1, 0x2000, offset),
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        let addr = if self.swap_address_lines {
+            (addr & !0b11) | ((addr & 0b01) << 1) | ((addr & 0b10) >> 1)
+        } else {
+            addr
+        };
+        let addr = addr & 0xF003;
+        match addr {
+            0x8000..=0x8003 => self.prg_bank_16k = data & 0x0F,
+            0x9000..=0xB002 => self.audio.write(addr, data),
+            0xB003 => self.prg_ram_enabled = data & 0b1000_0000 != 0,
+            0xC000..=0xC003 => self.prg_bank_8k = data & 0x1F,
+            0xD000..=0xE003 => {
+                let index = ((addr - 0xD000) >> 10) | (addr & 0b11);
+                self.chr_banks[index as usize] = data;
+            }
+            0xF000 => self.irq.write_latch(data),
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
+        if self.prg_ram_enabled {
+            self.prg_ram.read(addr)
+        } else {
+            0
+        }
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        if self.prg_ram_enabled {
+            self.prg_ram.write(addr, data);
+        }
+    }
+
+    fn clock(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.irq.clock();
+            self.audio.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn expansion_chip(&self) -> Option<ExpansionChip> {
+        Some(ExpansionChip::Vrc6)
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.battery_ram_mut()
+    }
+}
@@ -0,0 +1,113 @@
+use super::vrc_irq::VrcIrq;
+use super::{read_bank, Mapper, PrgRam};
+use crate::cartridge::Rom;
+use crate::expansion::vrc7::Vrc7Audio;
+use crate::expansion::ExpansionChip;
+use crate::savestate::impl_save_state;
+
+// Mapper 85(VRC7)
+// $8000, $A000, $C000: 8KB切り替え, $E000-$FFFF: 最後の8KB固定
+// VRC7aはA4($x010)、VRC7bはA3($x008)でレジスタを選ぶので、どちらも受け付ける。
+pub struct Vrc7 {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    prg_banks: [u8; 3],
+    prg_ram_enabled: bool,
+    // CHRバンクはPPUの実装がないので保持するだけ
+    chr_banks: [u8; 8],
+    irq: VrcIrq,
+    audio: Vrc7Audio,
+}
+
+impl_save_state!(Vrc7 { prg_ram, prg_banks, prg_ram_enabled, chr_banks, irq, audio });
+
+impl Vrc7 {
+    pub fn new(rom: Rom) -> Self {
+        Vrc7 {
+            prg_ram: PrgRam::new(rom.prg_ram_size, rom.battery),
+            prg_rom: rom.prg_rom,
+            prg_banks: [0; 3],
+            prg_ram_enabled: false,
+            chr_banks: [0; 8],
+            irq: VrcIrq::default(),
+            audio: Vrc7Audio::default(),
+        }
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x8000..=0x9FFF => self.prg_banks[0] as usize,
+            0xA000..=0xBFFF => self.prg_banks[1] as usize,
+            0xC000..=0xDFFF => self.prg_banks[2] as usize,
+            _ => self.prg_rom.len() / 0x2000 - 1,
+        };
+        read_bank(&self.prg_rom, bank, 0x2000, addr as usize & 0x1FFF)
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        // 音源のポートは$9010と$9030
+        let high = addr & 0x0018 != 0;
+        match (addr & 0xF000, high) {
+            _ if addr & 0xF030 == 0x9010 => self.audio.write_register(data),
+            _ if addr & 0xF030 == 0x9030 => self.audio.write_data(data),
+            (0x8000, false) => self.prg_banks[0] = data & 0x3F,
+            (0x8000, true) => self.prg_banks[1] = data & 0x3F,
+            (0x9000, false) => self.prg_banks[2] = data & 0x3F,
+            (0xA000..=0xD000, _) => {
+                let index = ((addr - 0xA000) >> 11) as usize | high as usize;
+                self.chr_banks[index] = data;
+            }
+            (0xE000, false) => {
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+                self.audio.set_muted(data & 0b0100_0000 != 0);
+            }
+            (0xE000, true) => self.irq.write_latch(data),
+            (0xF000, false) => self.irq.write_control(data),
+            (0xF000, true) => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
+        if self.prg_ram_enabled {
+            self.prg_ram.read(addr)
+        } else {
+            0
+        }
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        if self.prg_ram_enabled {
+            self.prg_ram.write(addr, data);
+        }
+    }
+
+    fn clock(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.irq.clock();
+            self.audio.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn expansion_chip(&self) -> Option<ExpansionChip> {
+        Some(ExpansionChip::Vrc7)
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery_ram()
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.battery_ram_mut()
+    }
+}
@@ -0,0 +1,98 @@
+use crate::savestate::impl_save_state;
+
+// VRC4/6/7共通のIRQカウンター。スキャンラインモードではプリスケーラーで
+// 341/3 CPUサイクルごとに、サイクルモードでは毎サイクルカウンターを進める。
+#[derive(Default)]
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl_save_state!(VrcIrq {
+    latch,
+    counter,
+    prescaler,
+    enabled,
+    enable_after_ack,
+    cycle_mode,
+    pending,
+});
+
+impl VrcIrq {
+    pub fn write_latch(&mut self, data: u8) {
+        self.latch = data;
+    }
+
+    pub fn write_control(&mut self, data: u8) {
+        self.enable_after_ack = data & 0b001 != 0;
+        self.enabled = data & 0b010 != 0;
+        self.cycle_mode = data & 0b100 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock_counter();
+            return;
+        }
+        self.prescaler -= 3;
+        if self.prescaler <= 0 {
+            self.prescaler += 341;
+            self.clock_counter();
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scanline_mode() {
+        let mut irq = VrcIrq::default();
+        irq.write_latch(0xFE);
+        irq.write_control(0b010);
+
+        // 2スキャンライン(341*2/3 = 227.3サイクル)で$FE -> $FF -> IRQ
+        for _ in 0..227 {
+            irq.clock();
+        }
+        assert!(!irq.pending());
+        irq.clock();
+        assert!(irq.pending());
+
+        irq.acknowledge();
+        assert!(!irq.pending());
+        assert!(!irq.enabled);
+    }
+}
@@ -0,0 +1,508 @@
+use crate::joypad::JoypadButton;
+use crate::CPU;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::path::{Path, PathBuf};
+
+// FCEUXのFM2形式の入力ムービー。ヘッダー("キー 値"の行)の後に1フレーム1行の入力が続く。
+//   |コマンド|1P|2P|拡張端子|        (fourscore 1なら |コマンド|1P|2P|3P|4P|拡張端子|)
+// コントローラーは"RLDUTSBA"の順で、押していないボタンは'.'。光線銃などの機器は記録しない。
+// 非同期の検出用に、FCEUXが読み飛ばす独自のヘッダー"ramHash <フレーム> <CRC32>"を書く。
+const FM2_VERSION: u32 = 3;
+const EMU_VERSION: u32 = 22020;
+pub const MOVIE_PLAYERS: usize = 4;
+const BUTTON_CHARS: &[u8; 8] = b"RLDUTSBA";
+// FM2のポートの種類
+const PORT_NONE: u8 = 0;
+const PORT_GAMEPAD: u8 = 1;
+// RAMのハッシュを記録する間隔(フレーム)
+const RAM_HASH_INTERVAL: usize = 60;
+
+// フレームの先頭で実行するコマンド
+pub const COMMAND_RESET: u8 = 0x01;
+pub const COMMAND_POWER: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MovieFrame {
+    pub commands: u8,
+    pub joypads: [JoypadButton; MOVIE_PLAYERS],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_filename: String,
+    pub rom_checksum: [u8; 16],
+    pub guid: String,
+    pub rerecord_count: u32,
+    pub four_score: bool,
+    // 1コン, 2コンにコントローラーをつないでいるか
+    pub gamepads: [bool; 2],
+    pub comments: Vec<String>,
+    // セーブステートから始まるムービーの開始時の状態
+    pub savestate: Option<Vec<u8>>,
+    // (フレーム, 2KBのRAMのCRC32)。フレームの入力を設定した直後に計算する。
+    pub ram_hashes: Vec<(usize, u32)>,
+    pub frames: Vec<MovieFrame>,
+}
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64_CHARS[(bits >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+pub fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.trim_end_matches('=').bytes() {
+        let value = BASE64_CHARS.iter().position(|&b| b == c).ok_or_else(|| format!("Invalid base64: {}", text))?;
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            data.push((bits >> count) as u8);
+        }
+    }
+    Ok(data)
+}
+
+fn format_joypad(buttons: JoypadButton) -> String {
+    BUTTON_CHARS
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| if buttons.bits() & (0x80 >> i) != 0 { c as char } else { '.' })
+        .collect()
+}
+
+fn parse_joypad(field: &str) -> Result<JoypadButton, String> {
+    if field.len() != 8 {
+        return Err(format!("Invalid controller input: {}", field));
+    }
+    let bits = field
+        .bytes()
+        .enumerate()
+        .filter(|&(_, c)| c != b'.' && c != b' ')
+        .fold(0, |bits, (i, _)| bits | 0x80 >> i);
+    Ok(JoypadButton::from_bits_truncate(bits))
+}
+
+impl Movie {
+    pub fn new(rom_filename: &str, rom_checksum: [u8; 16], four_score: bool) -> Self {
+        let id: [u8; 16] = rand::random();
+        let hex: String = id.iter().map(|b| format!("{:02X}", b)).collect();
+        Movie {
+            rom_filename: rom_filename.to_string(),
+            rom_checksum,
+            guid: format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]),
+            rerecord_count: 0,
+            four_score,
+            gamepads: [true; 2],
+            comments: Vec::new(),
+            savestate: None,
+            ram_hashes: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read movie {}: {}", path.display(), e))?;
+        Movie::parse(&text)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_fm2()).map_err(|e| format!("Failed to write movie {}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut movie = Movie::new("", [0; 16], false);
+        movie.guid.clear();
+        let mut version = None;
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: &str| format!("Movie line {}: {}", number + 1, message);
+            let line = line.trim_end_matches('\r');
+            if line.starts_with('|') {
+                movie.frames.push(movie.parse_frame(line).map_err(|e| error(&e))?);
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let number = || value.parse::<u32>().map_err(|_| error("invalid number"));
+            match key {
+                "version" => version = Some(number()?),
+                "rerecordCount" => movie.rerecord_count = number()?,
+                "palFlag" if number()? != 0 => return Err(error("PAL movies are not supported")),
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "romChecksum" => {
+                    let checksum = value.strip_prefix("base64:").ok_or_else(|| error("expected base64:"))?;
+                    let checksum = base64_decode(checksum).map_err(|e| error(&e))?;
+                    movie.rom_checksum = checksum.try_into().map_err(|_| error("checksum must be 16 bytes"))?;
+                }
+                "guid" => movie.guid = value.to_string(),
+                "fourscore" => movie.four_score = number()? != 0,
+                "port0" => movie.gamepads[0] = number()? as u8 == PORT_GAMEPAD,
+                "port1" => movie.gamepads[1] = number()? as u8 == PORT_GAMEPAD,
+                "comment" => movie.comments.push(value.to_string()),
+                "savestate" => {
+                    let state = value.strip_prefix("base64:").ok_or_else(|| error("expected base64:"))?;
+                    movie.savestate = Some(base64_decode(state).map_err(|e| error(&e))?);
+                }
+                "ramHash" => {
+                    let hash = value
+                        .split_once(' ')
+                        .and_then(|(frame, crc)| Some((frame.parse().ok()?, u32::from_str_radix(crc, 16).ok()?)))
+                        .ok_or_else(|| error("expected ramHash <frame> <crc32>"))?;
+                    movie.ram_hashes.push(hash);
+                }
+                // それ以外のキー(emuVersion, microphone, FDS, NewPPUなど)は読み飛ばす。
+                _ => {}
+            }
+        }
+        match version {
+            Some(FM2_VERSION) => Ok(movie),
+            Some(version) => Err(format!("Unsupported FM2 version: {}", version)),
+            None => Err("Not an FM2 movie (missing version)".to_string()),
+        }
+    }
+
+    fn parse_frame(&self, line: &str) -> Result<MovieFrame, String> {
+        let fields: Vec<&str> = line.split('|').collect();
+        // 先頭と末尾の'|'の外側は空
+        let players = if self.four_score { MOVIE_PLAYERS } else { 2 };
+        if fields.len() < players + 3 {
+            return Err("too few fields".to_string());
+        }
+        let mut frame = MovieFrame {
+            commands: fields[1].parse().map_err(|_| format!("Invalid command: {}", fields[1]))?,
+            ..MovieFrame::default()
+        };
+        for player in 0..players {
+            let field = fields[player + 2];
+            if !field.is_empty() {
+                frame.joypads[player] = parse_joypad(field)?;
+            }
+        }
+        Ok(frame)
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut text = format!("version {}\nemuVersion {}\nrerecordCount {}\npalFlag 0\n", FM2_VERSION, EMU_VERSION, self.rerecord_count);
+        text += &format!("romFilename {}\nromChecksum base64:{}\n", self.rom_filename, base64_encode(&self.rom_checksum));
+        text += &format!("guid {}\nfourscore {}\nmicrophone 0\n", self.guid, self.four_score as u8);
+        for (port, &gamepad) in self.gamepads.iter().enumerate() {
+            let port_type = if gamepad { PORT_GAMEPAD } else { PORT_NONE };
+            text += &format!("port{} {}\n", port, port_type);
+        }
+        text += "port2 0\nFDS 0\nNewPPU 0\n";
+        for comment in &self.comments {
+            text += &format!("comment {}\n", comment);
+        }
+        if let Some(state) = &self.savestate {
+            text += &format!("savestate base64:{}\n", base64_encode(state));
+        }
+        for (frame, crc) in &self.ram_hashes {
+            text += &format!("ramHash {} {:08X}\n", frame, crc);
+        }
+        for frame in &self.frames {
+            text += &format!("|{}|", frame.commands);
+            if self.four_score {
+                for &buttons in &frame.joypads {
+                    text += &format_joypad(buttons);
+                    text += "|";
+                }
+            } else {
+                for (player, &gamepad) in self.gamepads.iter().enumerate() {
+                    if gamepad {
+                        text += &format_joypad(frame.joypads[player]);
+                    }
+                    text += "|";
+                }
+            }
+            text += "|\n";
+        }
+        text
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MovieState {
+    Recording,
+    Playing,
+    Finished,
+}
+
+// 記録中または再生中のムービー。
+// 読み込み専用で再生すると最後まで再生して止まる。読み書き可能なら最後のフレームの後は記録を続け、
+// 途中のフレームから記録し直す(rerecord)こともできる。
+pub struct MovieSession {
+    pub movie: Movie,
+    pub path: PathBuf,
+    state: MovieState,
+    read_only: bool,
+    // 次に処理するフレーム
+    frame: usize,
+    // 保存していない変更があるか
+    dirty: bool,
+    desynced: bool,
+}
+
+impl MovieSession {
+    pub fn record(movie: Movie, path: PathBuf) -> Self {
+        MovieSession {
+            movie,
+            path,
+            state: MovieState::Recording,
+            read_only: false,
+            frame: 0,
+            dirty: true,
+            desynced: false,
+        }
+    }
+
+    pub fn play(movie: Movie, path: PathBuf, read_only: bool) -> Self {
+        let state = if movie.frames.is_empty() && read_only { MovieState::Finished } else { MovieState::Playing };
+        MovieSession {
+            movie,
+            path,
+            state,
+            read_only,
+            frame: 0,
+            dirty: false,
+            desynced: false,
+        }
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.state == MovieState::Recording
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state == MovieState::Finished
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    // 記録中は切り替えない。
+    pub fn toggle_read_only(&mut self) {
+        if self.state != MovieState::Recording {
+            self.read_only = !self.read_only;
+        }
+    }
+
+    // 1フレームに1回、フレームの先頭で呼ぶ。liveは実際の入力で、記録中はそれを記録し、再生中はムービーの入力を返す。
+    pub fn next_frame(&mut self, live: MovieFrame) -> MovieFrame {
+        match self.state {
+            MovieState::Recording => {
+                self.movie.frames.truncate(self.frame);
+                self.movie.frames.push(live);
+                self.frame += 1;
+                self.dirty = true;
+                live
+            }
+            MovieState::Playing => {
+                if self.frame >= self.movie.frames.len() {
+                    self.finish();
+                    return self.next_frame(live);
+                }
+                let frame = self.movie.frames[self.frame];
+                self.frame += 1;
+                if self.frame == self.movie.frames.len() {
+                    self.finish();
+                }
+                frame
+            }
+            MovieState::Finished => live,
+        }
+    }
+
+    // 最後まで再生した。読み書き可能なら続きを記録する。
+    fn finish(&mut self) {
+        self.state = if self.read_only { MovieState::Finished } else { MovieState::Recording };
+    }
+
+    // next_frameで返したフレームの入力とコマンドを反映した後に呼ぶ。
+    // 記録中はRAMのハッシュを記録し、再生中は記録と比べる。最初に非同期を見つけた時だけそのフレームを返す。
+    pub fn check_ram(&mut self, ram: &[u8]) -> Option<usize> {
+        let frame = self.frame.checked_sub(1)?;
+        if frame % RAM_HASH_INTERVAL != 0 {
+            return None;
+        }
+        let crc = crc32fast::hash(ram);
+        if self.state == MovieState::Recording && self.movie.frames.len() == frame + 1 {
+            self.movie.ram_hashes.retain(|&(f, _)| f < frame);
+            self.movie.ram_hashes.push((frame, crc));
+            return None;
+        }
+        let expected = self.movie.ram_hashes.iter().find(|&&(f, _)| f == frame)?.1;
+        if expected != crc && !self.desynced {
+            self.desynced = true;
+            return Some(frame);
+        }
+        None
+    }
+
+    // 読み書き可能なムービーをframeから記録し直す(セーブステートを読み込んだ時)。
+    pub fn rerecord(&mut self, frame: usize) -> Result<(), String> {
+        if self.read_only {
+            return Err("Movie is read-only".to_string());
+        }
+        if frame > self.movie.frames.len() {
+            return Err(format!("Frame {} is past the end of the movie", frame));
+        }
+        self.movie.frames.truncate(frame);
+        self.movie.ram_hashes.retain(|&(f, _)| f < frame);
+        self.movie.rerecord_count += 1;
+        self.frame = frame;
+        self.state = MovieState::Recording;
+        self.desynced = false;
+        self.dirty = true;
+        Ok(())
+    }
+
+    // 読み込み専用のムービーの再生位置をframeに移す(セーブステートを読み込んだ時)。
+    pub fn seek(&mut self, frame: usize) -> Result<(), String> {
+        if frame > self.movie.frames.len() {
+            return Err(format!("Frame {} is past the end of the movie", frame));
+        }
+        self.frame = frame;
+        self.state = MovieState::Playing;
+        if frame == self.movie.frames.len() {
+            self.finish();
+        }
+        self.desynced = false;
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> Result<(), String> {
+        self.movie.save(&self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+// ムービーでsnakeの乱数を再現するための種
+pub const MOVIE_RNG_SEED: u64 = 0;
+
+// セーブステートを読み込んでも同じ乱数になるよう、ムービー中はフレームごとに種を決める。
+pub fn movie_rng(session: &MovieSession) -> StdRng {
+    StdRng::seed_from_u64(MOVIE_RNG_SEED.wrapping_add(session.frame() as u64))
+}
+
+// 電源を入れ直す。RAMは0で埋める。
+pub fn power_cycle(cpu: &mut CPU) {
+    cpu.bus.clear_ram();
+    cpu.reset();
+}
+
+// ムービーを1フレーム進めて、入力とコマンドを反映する。
+pub fn apply_movie_frame(cpu: &mut CPU, movie: &mut Option<MovieSession>, live: MovieFrame) {
+    let frame = match movie {
+        Some(session) => session.next_frame(live),
+        None => live,
+    };
+    for (player, &buttons) in frame.joypads.iter().enumerate() {
+        if let Some(joypad) = cpu.bus.ports.joypad_mut(player) {
+            joypad.set_buttons(buttons);
+        }
+    }
+    if frame.commands & COMMAND_POWER != 0 {
+        power_cycle(cpu);
+    } else if frame.commands & COMMAND_RESET != 0 {
+        cpu.reset();
+    }
+    if let Some(session) = movie {
+        if let Some(frame) = session.check_ram(cpu.bus.ram()) {
+            eprintln!("Movie desynced at frame {}", frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_movie() -> Movie {
+        let mut movie = Movie::new("snake", [0xAB; 16], false);
+        movie.frames.push(MovieFrame { commands: COMMAND_POWER, ..MovieFrame::default() });
+        let mut frame = MovieFrame::default();
+        frame.joypads[0] = JoypadButton::RIGHT | JoypadButton::A;
+        frame.joypads[1] = JoypadButton::START;
+        movie.frames.push(frame);
+        movie.ram_hashes.push((0, 0x1234ABCD));
+        movie
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64_encode(b"Man"), "TWFu");
+        assert_eq!(base64_encode(b"Ma"), "TWE=");
+        assert_eq!(base64_encode(b"M"), "TQ==");
+        assert_eq!(base64_decode("TWE=").unwrap(), b"Ma");
+        assert!(base64_decode("T*==").is_err());
+    }
+
+    #[test]
+    fn test_fm2_round_trip() {
+        let movie = test_movie();
+        let text = movie.to_fm2();
+        assert!(text.contains("|2|........|........||\n|0|R......A|....T...||\n"));
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+
+        let mut four_score = test_movie();
+        four_score.four_score = true;
+        four_score.frames[1].joypads[3] = JoypadButton::UP;
+        let text = four_score.to_fm2();
+        assert!(text.contains("|0|R......A|....T...|........|...U....||\n"));
+        assert_eq!(Movie::parse(&text).unwrap(), four_score);
+
+        assert!(Movie::parse("|0|........|........||").is_err());
+        assert!(Movie::parse("version 3\n|0|...|........||").is_err());
+    }
+
+    #[test]
+    fn test_playback_and_rerecord() {
+        let live = MovieFrame { joypads: [JoypadButton::B; MOVIE_PLAYERS], ..MovieFrame::default() };
+        let mut session = MovieSession::play(test_movie(), PathBuf::from("test.fm2"), true);
+        assert_eq!(session.next_frame(live).commands, COMMAND_POWER);
+        assert_eq!(session.check_ram(&[1, 2, 3]), Some(0));
+        assert_eq!(session.next_frame(live).joypads[1], JoypadButton::START);
+        assert!(session.is_finished());
+        assert_eq!(session.next_frame(live), live);
+        assert!(session.rerecord(1).is_err());
+
+        // 読み込み専用ならステートを読み込んだフレームから再生し直す。
+        session.seek(1).unwrap();
+        assert!(!session.is_finished());
+        assert_eq!(session.next_frame(live).joypads[1], JoypadButton::START);
+        assert!(session.seek(3).is_err());
+
+        session.toggle_read_only();
+        session.rerecord(1).unwrap();
+        assert_eq!(session.next_frame(live), live);
+        assert_eq!(session.movie.frames.len(), 2);
+        assert_eq!(session.movie.frames[1], live);
+        assert_eq!(session.movie.rerecord_count, 1);
+    }
+}
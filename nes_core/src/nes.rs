@@ -0,0 +1,273 @@
+use crate::bus::Bus;
+use crate::cartridge::Rom;
+use crate::joypad::JoypadButton;
+use crate::run_ahead::RunAhead;
+use crate::snake::{self, SCREEN_BYTES, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::{fds, nsf, CPU, FRAME_CYCLES};
+
+// snakeのデモは待ち時間を命令の実行速度に任せているので、1フレームに全部のサイクルを実行すると
+// 速すぎて遊べない。1フレームにこのサイクル数だけ実行し、残りは止まって次のフレームを待つ。
+// (以前の1命令ごとに70µs待っていた速さとほぼ同じ)
+const SNAKE_FRAME_CYCLES: f64 = 700.0;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+// 本体を外から使うための窓口。フロントエンドもヘッドレスもこれで1フレームずつ実行する。
+// snakeのデモ用の書き込み(命令ごとの乱数とフレームの境目のキー)はset_snakeかnew_snakeで有効にした時だけ行う。
+pub struct Nes {
+    cpu: CPU,
+    rng: StdRng,
+    screen: [u8; SCREEN_BYTES],
+    screen_changed: bool,
+    next_frame: f64,
+    is_nsf: bool,
+    snake: bool,
+    running: bool,
+}
+
+impl Nes {
+    // iNES, UNIF, NSFのROMを読み込む。FDSはBIOSが要るのでload_diskを使う。
+    pub fn load_rom(raw: &[u8]) -> Result<Nes, String> {
+        let rom = Rom::new(raw)?;
+        if rom.mapper == fds::FDS_MAPPER {
+            return Err("FDS images need the BIOS: use Nes::load_disk".to_string());
+        }
+        Nes::new(rom)
+    }
+
+    // FDSのディスクイメージをBIOS(disksys.rom)と一緒に読み込む。
+    pub fn load_disk(raw: &[u8], bios: Vec<u8>) -> Result<Nes, String> {
+        let mut rom = Rom::new(raw)?;
+        if rom.mapper != fds::FDS_MAPPER {
+            return Err("File is not a Famicom Disk System image".to_string());
+        }
+        fds::attach_bios(&mut rom, bios)?;
+        Nes::new(rom)
+    }
+
+    pub fn new(rom: Rom) -> Result<Nes, String> {
+        Ok(Nes::from_bus(Bus::new(rom)?))
+    }
+
+    pub fn new_snake(rom: Rom) -> Result<Nes, String> {
+        let mut nes = Nes::new(rom)?;
+        nes.set_snake(true);
+        Ok(nes)
+    }
+
+    // 機器や音量を設定したBusから作る。リセットし、NSFなら最初の曲を始める。
+    pub fn from_bus(bus: Bus) -> Nes {
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        let is_nsf = match cpu.bus.mapper_mut().as_nsf_mut() {
+            Some(nsf) => {
+                let track = nsf.info().starting_song;
+                nsf::start_track(&mut cpu, track);
+                true
+            }
+            None => false,
+        };
+        let mut nes = Nes {
+            cpu,
+            rng: StdRng::seed_from_u64(0),
+            screen: [0; SCREEN_BYTES],
+            screen_changed: false,
+            next_frame: 0.0,
+            is_nsf,
+            snake: false,
+            running: true,
+        };
+        nes.resync_frame();
+        nes
+    }
+
+    // snakeのデモは命令ごとに$FEの乱数を、フレームの境目で$FFのキーを読む。他のROMのRAMを壊さないよう
+    // 既定では書き込まない。NSFのゼロページも壊すので、NSFでは有効にしても書かない。
+    pub fn set_snake(&mut self, enabled: bool) {
+        self.snake = enabled && !self.is_nsf;
+    }
+
+    pub fn is_snake(&self) -> bool {
+        self.snake
+    }
+
+    pub fn is_nsf(&self) -> bool {
+        self.is_nsf
+    }
+
+    // snakeに渡す乱数。始めは種0で、ムービーの再生中はフレームごとに決め直す。
+    pub fn set_rng(&mut self, rng: StdRng) {
+        self.rng = rng;
+    }
+
+    // ステートを読み込んだ後などに、次のフレームの境目を今のCPUサイクルに合わせる。
+    pub fn resync_frame(&mut self) {
+        self.next_frame = ((self.cpu.bus.cycles() as f64 / FRAME_CYCLES).floor() + 1.0) * FRAME_CYCLES;
+    }
+
+    // 1フレーム実行する。BRKで止まっていればfalseを返し、それ以降は何もしない。
+    pub fn step_frame(&mut self) -> bool {
+        if !self.running {
+            return false;
+        }
+        let snake = self.snake;
+        if snake {
+            snake::write_snake_key(&mut self.cpu);
+        }
+        self.running = run_frame(&mut self.cpu, self.next_frame, snake, &mut self.rng);
+        self.next_frame += FRAME_CYCLES;
+        self.screen_changed = !self.is_nsf && snake::read_screen_state(&mut self.cpu, &mut self.screen);
+        self.update_zapper();
+        self.running
+    }
+
+    // 今の入力のまま先行実行し、その画面をsnake_screenに入れる。状態は元に戻る。
+    // 先行実行中もsnakeの乱数は本体と同じ列を使う。画面がstep_frameから変わっていればtrueを返す。
+    pub fn run_ahead(&mut self, run_ahead: &mut RunAhead) -> bool {
+        if self.is_nsf {
+            return false;
+        }
+        let snake = self.snake;
+        if snake {
+            snake::write_snake_key(&mut self.cpu);
+        }
+        let mut rng = self.rng.clone();
+        let screen = &mut self.screen;
+        let changed = run_ahead.run(
+            &mut self.cpu,
+            self.next_frame,
+            |cpu, frame_end| run_frame(cpu, frame_end, snake, &mut rng),
+            |cpu| snake::read_screen_state(cpu, screen),
+        );
+        self.screen_changed |= changed;
+        self.update_zapper();
+        self.screen_changed
+    }
+
+    // 光線銃は表示している画面で受光を調べる。
+    fn update_zapper(&mut self) {
+        if let Some(zapper) = self.cpu.bus.ports.zapper_mut() {
+            zapper.set_frame(&self.screen, SCREEN_WIDTH, SCREEN_HEIGHT);
+        }
+    }
+
+    // NESの画面ではなく、snakeのデバッグ画面(PPUができるまでの代わり)。RAMの$0200-$05FFを
+    // snakeの色でRGB24のsnake::SCREEN_WIDTH x SCREEN_HEIGHTに描いたもので、snake以外のROMでは
+    // 意味のある画面にならない。NSFでは描かない。
+    pub fn snake_screen(&self) -> &[u8] {
+        &self.screen
+    }
+
+    // これまでに溜まった音のサンプル(-1.0〜1.0、モノラル)を取り出す。
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        let samples = self.cpu.bus.apu.samples();
+        let mut out = vec![0.0; samples.len()];
+        let len = samples.read(&mut out);
+        out.truncate(len);
+        out
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.cpu.bus.apu.sample_rate()
+    }
+
+    // 次のフレームから反映する。コントローラーがつながっていないプレイヤーは無視する。
+    pub fn set_buttons(&mut self, player: usize, buttons: JoypadButton) {
+        if let Some(joypad) = self.cpu.bus.ports.joypad_mut(player) {
+            joypad.set_buttons(buttons);
+        }
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+}
+
+// frame_endまで1フレーム実行する。snakeなら命令ごとに乱数を書き、SNAKE_FRAME_CYCLESだけ実行したら
+// 残りは待つ。BRKで止まったらfalseを返す。
+fn run_frame(cpu: &mut CPU, frame_end: f64, snake: bool, rng: &mut StdRng) -> bool {
+    if !snake {
+        return cpu.run_until(frame_end, |_| {});
+    }
+    let budget_end = (cpu.bus.cycles() as f64 + SNAKE_FRAME_CYCLES).min(frame_end);
+    if !cpu.run_until(budget_end, |cpu| snake::write_random(cpu, rng)) {
+        return false;
+    }
+    cpu.wait_until(frame_end);
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Mem;
+
+    fn snake() -> Nes {
+        Nes::new_snake(Rom::new(&std::fs::read("../snake.nes").unwrap()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_step_frame() {
+        let mut nes = snake();
+        assert!(nes.step_frame());
+        assert_eq!(nes.snake_screen().len(), SCREEN_BYTES);
+        // snakeは最初のフレームで頭を描く。
+        assert!(nes.snake_screen().iter().any(|&byte| byte != 0));
+        assert!(!nes.audio_samples().is_empty());
+        assert!(nes.audio_samples().is_empty());
+
+        // 同じ入力なら同じ画面になる。
+        let mut nes = snake();
+        let mut other = snake();
+        for _ in 0..3 {
+            nes.set_buttons(0, JoypadButton::DOWN);
+            other.set_buttons(0, JoypadButton::DOWN);
+            assert_eq!(nes.step_frame(), other.step_frame());
+            assert_eq!(nes.snake_screen(), other.snake_screen());
+        }
+    }
+
+    // nes-headless --snake --seed nと同じ進め方で、入力なしでも壁に着くまでの40フレームはBRKで止まらない。
+    #[test]
+    fn test_snake_survives() {
+        for seed in 1..=5 {
+            let mut nes = snake();
+            nes.set_rng(StdRng::seed_from_u64(seed));
+            for frame in 0..40 {
+                assert!(nes.step_frame(), "seed {} stopped at frame {}", seed, frame);
+            }
+            // 待っている間もフレームの長さは変わらない。
+            assert_eq!(nes.cpu().bus.cycles(), (40.0 * FRAME_CYCLES).ceil() as u64);
+        }
+    }
+
+    #[test]
+    fn test_snake_is_opt_in() {
+        let raw = std::fs::read("../snake.nes").unwrap();
+        let mut nes = Nes::load_rom(&raw).unwrap();
+        assert!(!nes.is_snake());
+        nes.set_buttons(0, JoypadButton::DOWN);
+        nes.step_frame();
+        assert_ne!(nes.cpu_mut().mem_read(0x00ff), 0x73);
+
+        let mut nes = snake();
+        nes.set_buttons(0, JoypadButton::DOWN);
+        nes.step_frame();
+        assert_eq!(nes.cpu_mut().mem_read(0x00ff), 0x73);
+    }
+
+    #[test]
+    fn test_load_disk_needs_bios() {
+        let mut raw = b"FDS\x1a\x01".to_vec();
+        raw.resize(16, 0);
+        raw.extend(b"\x01*NINTENDO-HVC*");
+        raw.resize(16 + 65500, 0);
+        assert!(Nes::load_rom(&raw).err().unwrap().contains("load_disk"));
+        assert!(Nes::load_disk(&raw, vec![0; 0x2000]).is_ok());
+        assert!(Nes::load_disk(&std::fs::read("../snake.nes").unwrap(), vec![0; 0x2000]).is_err());
+    }
+}
@@ -0,0 +1,687 @@
+use crate::apu::mixer::CPU_CLOCK_RATE;
+use crate::cartridge::{Mirroring, Rom, TvSystem};
+use crate::expansion::fds::FdsAudio;
+use crate::expansion::mmc5::Mmc5Audio;
+use crate::expansion::n163::N163Audio;
+use crate::expansion::sunsoft5b::Sunsoft5bAudio;
+use crate::expansion::vrc6::Vrc6Audio;
+use crate::expansion::vrc7::Vrc7Audio;
+use crate::expansion::ExpansionChip;
+use crate::mapper::{read_bank, Mapper};
+use crate::romdb::RomHash;
+use crate::{Mem, CPU};
+use crate::savestate::impl_save_state;
+
+// NSFのバンク切り替え($5FF8-$5FFFに4KB単位)はマッパー31と同じなので31として扱う。
+pub const NSF_MAPPER: u8 = 31;
+pub const NSF_TAG: [u8; 5] = [0x4E, 0x45, 0x53, 0x4D, 0x1A];
+pub const NSFE_TAG: [u8; 4] = [0x4E, 0x53, 0x46, 0x45];
+const NSF_HEADER_SIZE: usize = 0x80;
+const BANK_SIZE: usize = 0x1000;
+// $6000-$FFFFの4KBバンクの数
+const SLOTS: usize = 10;
+// ヘッダーで再生間隔が0の時に使う値(マイクロ秒)
+const DEFAULT_PLAY_SPEED_NTSC: u16 = 16639;
+const DEFAULT_PLAY_SPEED_PAL: u16 = 19997;
+
+// INIT/PLAYを呼び出すドライバーを拡張領域に置く。
+// $4100: JSR INIT
+// $4103: LDA PLAY_FLAG / BEQ $4103   (PLAYを呼ぶ時間になるまで待つ)
+// $4108: JSR PLAY / JMP $4103
+const DRIVER_ADDR: u16 = 0x4100;
+const DRIVER_IDLE: u16 = DRIVER_ADDR + 3;
+const PLAY_FLAG: u16 = 0x4110;
+const DRIVER_SIZE: usize = 14;
+const DRIVER_END: u16 = DRIVER_ADDR + DRIVER_SIZE as u16 - 1;
+
+// ヘッダーの拡張音源フラグの並び
+const CHIP_FLAGS: [ExpansionChip; 6] = [
+    ExpansionChip::Vrc6,
+    ExpansionChip::Vrc7,
+    ExpansionChip::Fds,
+    ExpansionChip::Mmc5,
+    ExpansionChip::N163,
+    ExpansionChip::Sunsoft5b,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NsfInfo {
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub total_songs: u8,
+    // 0始まり
+    pub starting_song: u8,
+    // $8000-$FFFFの4KBバンクの初期値。Noneならバンク切り替えなし
+    pub banks: Option<[u8; 8]>,
+    // PLAYを呼ぶ間隔(マイクロ秒)
+    pub play_speed_ntsc: u16,
+    pub play_speed_pal: u16,
+    pub pal: bool,
+    pub chips: Vec<ExpansionChip>,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    // NSFeのtlbl, timeチャンク(曲の長さはミリ秒、不明なら負)
+    pub track_names: Vec<String>,
+    pub track_times: Vec<i32>,
+}
+
+impl NsfInfo {
+    pub fn track_name(&self, track: u8) -> String {
+        match self.track_names.get(track as usize) {
+            Some(name) if !name.is_empty() => name.clone(),
+            _ => format!("Track {}", track as u32 + 1),
+        }
+    }
+
+    pub fn track_time_ms(&self, track: u8) -> Option<u32> {
+        self.track_times
+            .get(track as usize)
+            .filter(|&&time| time >= 0)
+            .map(|&time| time as u32)
+    }
+
+    fn uses(&self, chip: ExpansionChip) -> bool {
+        self.chips.contains(&chip)
+    }
+}
+
+pub fn is_nsf(raw: &[u8]) -> bool {
+    raw.starts_with(&NSF_TAG) || raw.starts_with(&NSFE_TAG)
+}
+
+pub fn parse(raw: &[u8]) -> Result<Rom, String> {
+    let (info, data) = if raw.starts_with(&NSFE_TAG) {
+        parse_nsfe(raw)?
+    } else {
+        parse_nsf(raw)?
+    };
+    if info.load_addr < 0x6000 {
+        return Err(format!("NSF load address ${:04X} is out of range", info.load_addr));
+    }
+    if info.total_songs == 0 {
+        return Err("NSF has no songs".to_string());
+    }
+
+    Ok(Rom {
+        hash: RomHash::new(&data, &[]),
+        prg_rom: data,
+        chr_rom: Vec::new(),
+        mapper: NSF_MAPPER,
+        screen_mirroring: Mirroring::HORIZONTAL,
+        battery: false,
+        prg_ram_size: 0x2000,
+        tv_system: if info.pal { TvSystem::PAL } else { TvSystem::NTSC },
+        title: Some(info.title.clone()).filter(|t| !t.is_empty()),
+        disk_sides: Vec::new(),
+        nsf: Some(info),
+    })
+}
+
+fn read_u16(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+// NUL終端の文字列
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+fn chips_from_flags(flags: u8) -> Vec<ExpansionChip> {
+    CHIP_FLAGS
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| flags & (1 << bit) != 0)
+        .map(|(_, &chip)| chip)
+        .collect()
+}
+
+fn banks_from_header(banks: &[u8]) -> Option<[u8; 8]> {
+    let mut result = [0; 8];
+    result[..banks.len()].copy_from_slice(banks);
+    if result.iter().any(|&b| b != 0) {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+fn parse_nsf(raw: &[u8]) -> Result<(NsfInfo, Vec<u8>), String> {
+    if raw.len() <= NSF_HEADER_SIZE {
+        return Err("NSF file is truncated".to_string());
+    }
+    let speed = |pos, default| match read_u16(raw, pos) {
+        0 => default,
+        speed => speed,
+    };
+    let info = NsfInfo {
+        load_addr: read_u16(raw, 0x08),
+        init_addr: read_u16(raw, 0x0A),
+        play_addr: read_u16(raw, 0x0C),
+        total_songs: raw[0x06],
+        starting_song: raw[0x07].saturating_sub(1),
+        banks: banks_from_header(&raw[0x70..0x78]),
+        play_speed_ntsc: speed(0x6E, DEFAULT_PLAY_SPEED_NTSC),
+        play_speed_pal: speed(0x78, DEFAULT_PLAY_SPEED_PAL),
+        // bit1が立っていればNTSC/PAL両対応
+        pal: raw[0x7A] & 0b11 == 0b01,
+        chips: chips_from_flags(raw[0x7B]),
+        title: read_string(&raw[0x0E..0x2E]),
+        artist: read_string(&raw[0x2E..0x4E]),
+        copyright: read_string(&raw[0x4E..0x6E]),
+        track_names: Vec::new(),
+        track_times: Vec::new(),
+    };
+    Ok((info, raw[NSF_HEADER_SIZE..].to_vec()))
+}
+
+// NSFeはチャンク(長さ4バイト, ID4バイト, データ)の並び。IDが大文字で始まるチャンクは必須。
+fn parse_nsfe(raw: &[u8]) -> Result<(NsfInfo, Vec<u8>), String> {
+    let mut info = None;
+    let mut data = None;
+    let mut banks = None;
+    let mut rate: Option<&[u8]> = None;
+    let mut auth: Vec<String> = Vec::new();
+    let mut track_names = Vec::new();
+    let mut track_times = Vec::new();
+
+    let mut pos = NSFE_TAG.len();
+    while pos + 8 <= raw.len() {
+        let len = u32::from_le_bytes([raw[pos], raw[pos + 1], raw[pos + 2], raw[pos + 3]]) as usize;
+        let id = &raw[pos + 4..pos + 8];
+        let chunk = raw.get(pos + 8..pos + 8 + len).ok_or("NSFe chunk is truncated")?;
+        pos += 8 + len;
+
+        match id {
+            b"INFO" => {
+                if chunk.len() < 9 {
+                    return Err("NSFe INFO chunk is too short".to_string());
+                }
+                info = Some(chunk);
+            }
+            b"DATA" => data = Some(chunk.to_vec()),
+            b"BANK" => banks = banks_from_header(&chunk[..chunk.len().min(8)]),
+            b"RATE" => rate = Some(chunk),
+            b"NEND" => break,
+            b"auth" => auth = chunk.split(|&b| b == 0).map(read_string).collect(),
+            b"tlbl" => track_names = chunk.split(|&b| b == 0).map(read_string).collect(),
+            b"time" => {
+                track_times = chunk
+                    .chunks_exact(4)
+                    .map(|t| i32::from_le_bytes([t[0], t[1], t[2], t[3]]))
+                    .collect()
+            }
+            _ if id[0].is_ascii_uppercase() => {
+                return Err(format!("Unsupported NSFe chunk: {}", String::from_utf8_lossy(id)));
+            }
+            _ => {}
+        }
+    }
+
+    let chunk = info.ok_or("NSFe file has no INFO chunk")?;
+    let data = data.ok_or("NSFe file has no DATA chunk")?;
+    let speed = |index: usize, default| match rate.filter(|r| r.len() >= index * 2 + 2) {
+        Some(r) if read_u16(r, index * 2) != 0 => read_u16(r, index * 2),
+        _ => default,
+    };
+    let mut auth = auth.into_iter();
+    let info = NsfInfo {
+        load_addr: read_u16(chunk, 0),
+        init_addr: read_u16(chunk, 2),
+        play_addr: read_u16(chunk, 4),
+        total_songs: chunk.get(8).copied().unwrap_or(1),
+        starting_song: chunk.get(9).copied().unwrap_or(0),
+        banks,
+        play_speed_ntsc: speed(0, DEFAULT_PLAY_SPEED_NTSC),
+        play_speed_pal: speed(1, DEFAULT_PLAY_SPEED_PAL),
+        pal: chunk[6] & 0b11 == 0b01,
+        chips: chips_from_flags(chunk[7]),
+        title: auth.next().unwrap_or_default(),
+        artist: auth.next().unwrap_or_default(),
+        copyright: auth.next().unwrap_or_default(),
+        track_names,
+        track_times,
+    };
+    Ok((info, data))
+}
+
+// NSFの再生用マッパー。$6000-$FFFFを4KBのバンクに分け、ベクターとドライバーを差し込む。
+// FDSの曲では$6000-$DFFFがRAMになり、バンク切り替えはROMからRAMへのコピーになる。
+pub struct Nsf {
+    info: NsfInfo,
+    prg: Vec<u8>,
+    initial_banks: [u8; SLOTS],
+    banks: [u8; SLOTS],
+    ram: Vec<u8>,
+    driver: [u8; DRIVER_SIZE],
+    track: u8,
+    play_period: u32,
+    play_timer: u32,
+    play_pending: bool,
+    // MMC5の乗算器と拡張RAM
+    multiplicand: u8,
+    multiplier: u8,
+    exram: Vec<u8>,
+    vrc6: Option<Vrc6Audio>,
+    vrc7: Option<Vrc7Audio>,
+    fds: Option<FdsAudio>,
+    mmc5: Option<Mmc5Audio>,
+    n163: Option<N163Audio>,
+    sunsoft5b: Option<Sunsoft5bAudio>,
+}
+
+impl_save_state!(Nsf {
+    banks,
+    ram,
+    track,
+    play_period,
+    play_timer,
+    play_pending,
+    multiplicand,
+    multiplier,
+    exram,
+    vrc6,
+    vrc7,
+    fds,
+    mmc5,
+    n163,
+    sunsoft5b,
+});
+
+impl Nsf {
+    pub fn new(rom: Rom) -> Result<Self, String> {
+        let info = rom.nsf.ok_or("Mapper 31 is only supported for NSF files")?;
+        let (prg, initial_banks) = match info.banks {
+            // 先頭のバンクはロードアドレスの下位12bitだけずれて始まる。
+            Some(banks) => {
+                let mut prg = vec![0; info.load_addr as usize & 0x0FFF];
+                prg.extend(&rom.prg_rom);
+                prg.resize(prg.len().div_ceil(BANK_SIZE) * BANK_SIZE, 0);
+                let mut initial_banks = [0; SLOTS];
+                initial_banks[0] = banks[6];
+                initial_banks[1] = banks[7];
+                initial_banks[2..].copy_from_slice(&banks);
+                (prg, initial_banks)
+            }
+            None => {
+                let mut prg = vec![0; SLOTS * BANK_SIZE];
+                let offset = (info.load_addr - 0x6000) as usize;
+                let len = rom.prg_rom.len().min(prg.len() - offset);
+                prg[offset..offset + len].copy_from_slice(&rom.prg_rom[..len]);
+                let mut initial_banks = [0; SLOTS];
+                for (slot, bank) in initial_banks.iter_mut().enumerate() {
+                    *bank = slot as u8;
+                }
+                (prg, initial_banks)
+            }
+        };
+
+        let [init_lo, init_hi] = info.init_addr.to_le_bytes();
+        let [play_lo, play_hi] = info.play_addr.to_le_bytes();
+        let [idle_lo, idle_hi] = DRIVER_IDLE.to_le_bytes();
+        let [flag_lo, flag_hi] = PLAY_FLAG.to_le_bytes();
+        let driver = [
+            0x20, init_lo, init_hi, // JSR INIT
+            0xAD, flag_lo, flag_hi, // LDA PLAY_FLAG
+            0xF0, 0xFB, // BEQ $4103
+            0x20, play_lo, play_hi, // JSR PLAY
+            0x4C, idle_lo, idle_hi, // JMP $4103
+        ];
+
+        let speed = if info.pal { info.play_speed_pal } else { info.play_speed_ntsc };
+        let play_period = (speed as f64 * CPU_CLOCK_RATE / 1_000_000.0).round() as u32;
+        let ram_size = if info.uses(ExpansionChip::Fds) { 0x8000 } else { 0x2000 };
+
+        let mut nsf = Nsf {
+            track: info.starting_song,
+            info,
+            prg,
+            initial_banks,
+            banks: initial_banks,
+            ram: vec![0; ram_size],
+            driver,
+            play_period: play_period.max(1),
+            play_timer: 0,
+            play_pending: false,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            exram: vec![0; 0x400],
+            vrc6: None,
+            vrc7: None,
+            fds: None,
+            mmc5: None,
+            n163: None,
+            sunsoft5b: None,
+        };
+        nsf.select_track(nsf.track);
+        Ok(nsf)
+    }
+
+    pub fn info(&self) -> &NsfInfo {
+        &self.info
+    }
+
+    pub fn track(&self) -> u8 {
+        self.track
+    }
+
+    // バンクやRAM、拡張音源を初期状態に戻す。CPU側の初期化はstart_trackで行う。
+    fn select_track(&mut self, track: u8) {
+        self.track = track.min(self.info.total_songs - 1);
+        self.ram.iter_mut().for_each(|b| *b = 0);
+        self.exram.iter_mut().for_each(|b| *b = 0);
+        self.banks = self.initial_banks;
+        if self.is_fds() {
+            for slot in 0..8 {
+                self.load_ram_bank(slot);
+            }
+        }
+        self.play_timer = 0;
+        self.play_pending = false;
+
+        let info = &self.info;
+        self.vrc6 = info.uses(ExpansionChip::Vrc6).then(Vrc6Audio::default);
+        self.vrc7 = info.uses(ExpansionChip::Vrc7).then(Vrc7Audio::default);
+        self.fds = info.uses(ExpansionChip::Fds).then(FdsAudio::default);
+        self.mmc5 = info.uses(ExpansionChip::Mmc5).then(Mmc5Audio::default);
+        self.n163 = info.uses(ExpansionChip::N163).then(N163Audio::default);
+        self.sunsoft5b = info.uses(ExpansionChip::Sunsoft5b).then(Sunsoft5bAudio::default);
+    }
+
+    fn is_fds(&self) -> bool {
+        self.info.uses(ExpansionChip::Fds)
+    }
+
+    // FDSの曲: RAMの4KBにROMのバンクをコピーする。
+    fn load_ram_bank(&mut self, slot: usize) {
+        let start = slot * BANK_SIZE;
+        for offset in 0..BANK_SIZE {
+            self.ram[start + offset] = read_bank(&self.prg, self.banks[slot] as usize, BANK_SIZE, offset);
+        }
+    }
+
+    fn write_bank(&mut self, slot: usize, data: u8) {
+        self.banks[slot] = data;
+        if self.is_fds() && slot < 8 {
+            self.load_ram_bank(slot);
+        }
+    }
+}
+
+impl Mapper for Nsf {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match addr {
+            // リセットベクターはドライバーを指す。
+            0xFFFC => DRIVER_ADDR as u8,
+            0xFFFD => (DRIVER_ADDR >> 8) as u8,
+            0x8000..=0xDFFF if self.is_fds() => self.ram[(addr - 0x6000) as usize],
+            _ => {
+                let slot = ((addr - 0x6000) as usize) / BANK_SIZE;
+                read_bank(&self.prg, self.banks[slot] as usize, BANK_SIZE, addr as usize & 0x0FFF)
+            }
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if addr < 0xE000 && self.is_fds() {
+            self.ram[(addr - 0x6000) as usize] = data;
+        }
+        if let Some(vrc6) = &mut self.vrc6 {
+            if let 0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002 = addr {
+                vrc6.write(addr, data);
+            }
+        }
+        if let Some(vrc7) = &mut self.vrc7 {
+            match addr {
+                0x9010 => vrc7.write_register(data),
+                0x9030 => vrc7.write_data(data),
+                _ => {}
+            }
+        }
+        if let Some(n163) = &mut self.n163 {
+            if addr >= 0xF800 {
+                n163.write_address(data);
+            }
+        }
+        if let Some(sunsoft5b) = &mut self.sunsoft5b {
+            match addr & 0xE000 {
+                0xC000 => sunsoft5b.write_register(data),
+                0xE000 => sunsoft5b.write_data(data),
+                _ => {}
+            }
+        }
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
+        self.ram[(addr - 0x6000) as usize]
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        self.ram[(addr - 0x6000) as usize] = data;
+    }
+
+    fn read_expansion(&mut self, addr: u16) -> u8 {
+        if let Some(data) = self.fds.as_ref().and_then(|fds| fds.read(addr)) {
+            return data;
+        }
+        if let Some(data) = self.mmc5.as_mut().and_then(|mmc5| mmc5.read(addr)) {
+            return data;
+        }
+        match addr {
+            DRIVER_ADDR..=DRIVER_END => self.driver[(addr - DRIVER_ADDR) as usize],
+            // 読むとフラグが下りる。
+            PLAY_FLAG => std::mem::replace(&mut self.play_pending, false) as u8,
+            0x4800..=0x4FFF => self.n163.as_mut().map_or(0, |n163| n163.read_data()),
+            0x5205 if self.mmc5.is_some() => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 if self.mmc5.is_some() => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FF5 if self.mmc5.is_some() => self.exram[(addr - 0x5C00) as usize],
+            _ => 0,
+        }
+    }
+
+    fn write_expansion(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x408A => {
+                if let Some(fds) = &mut self.fds {
+                    fds.write(addr, data);
+                }
+            }
+            0x4800..=0x4FFF => {
+                if let Some(n163) = &mut self.n163 {
+                    n163.write_data(data);
+                }
+            }
+            0x5000..=0x5015 => {
+                if let Some(mmc5) = &mut self.mmc5 {
+                    mmc5.write(addr, data);
+                }
+            }
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            0x5C00..=0x5FF5 if self.mmc5.is_some() => self.exram[(addr - 0x5C00) as usize] = data,
+            // $6000-$7FFFのバンクはFDSの曲だけ
+            0x5FF6..=0x5FF7 if self.is_fds() => self.write_bank((addr - 0x5FF6) as usize, data),
+            0x5FF8..=0x5FFF => self.write_bank((addr - 0x5FF6) as usize, data),
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.play_timer += 1;
+            if self.play_timer >= self.play_period {
+                self.play_timer = 0;
+                self.play_pending = true;
+            }
+            if let Some(vrc6) = &mut self.vrc6 {
+                vrc6.clock();
+            }
+            if let Some(vrc7) = &mut self.vrc7 {
+                vrc7.clock();
+            }
+            if let Some(fds) = &mut self.fds {
+                fds.clock();
+            }
+            if let Some(mmc5) = &mut self.mmc5 {
+                mmc5.clock();
+            }
+            if let Some(n163) = &mut self.n163 {
+                n163.clock();
+            }
+            if let Some(sunsoft5b) = &mut self.sunsoft5b {
+                sunsoft5b.clock();
+            }
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.vrc6.as_ref().map_or(0.0, |c| c.output())
+            + self.vrc7.as_ref().map_or(0.0, |c| c.output())
+            + self.fds.as_ref().map_or(0.0, |c| c.output())
+            + self.mmc5.as_ref().map_or(0.0, |c| c.output())
+            + self.n163.as_ref().map_or(0.0, |c| c.output())
+            + self.sunsoft5b.as_ref().map_or(0.0, |c| c.output())
+    }
+
+    // 複数の拡張音源を使う曲では、音量は最初のチップの設定でまとめて調整する。
+    fn expansion_chip(&self) -> Option<ExpansionChip> {
+        self.info.chips.first().copied()
+    }
+
+    fn as_nsf_mut(&mut self) -> Option<&mut Nsf> {
+        Some(self)
+    }
+}
+
+// 曲を選んでINITから実行し直す。trackは0始まり。
+pub fn start_track(cpu: &mut CPU, track: u8) {
+    let track = match cpu.bus.mapper_mut().as_nsf_mut() {
+        Some(nsf) => {
+            nsf.select_track(track);
+            nsf.track()
+        }
+        None => return,
+    };
+    cpu.reset();
+    for addr in 0x0000..0x0800 {
+        cpu.mem_write(addr, 0);
+    }
+    for addr in 0x4000..=0x4013 {
+        cpu.mem_write(addr, 0);
+    }
+    cpu.mem_write(0x4015, 0x00);
+    cpu.mem_write(0x4015, 0x0F);
+    cpu.mem_write(0x4017, 0x40);
+    // INITにはAに曲番号、XにNTSC(0)/PAL(1)を渡す。
+    cpu.register_a = track;
+    cpu.register_x = 0;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    // INITで$00に曲番号を書き、PLAYのたびに$01を増やすだけの曲
+    fn test_nsf(chips: u8) -> Vec<u8> {
+        let mut raw = NSF_TAG.to_vec();
+        raw.resize(NSF_HEADER_SIZE, 0);
+        raw[0x05] = 1;
+        raw[0x06] = 3;
+        raw[0x07] = 2;
+        raw[0x08..0x0A].copy_from_slice(&0x8000u16.to_le_bytes());
+        raw[0x0A..0x0C].copy_from_slice(&0x8000u16.to_le_bytes());
+        raw[0x0C..0x0E].copy_from_slice(&0x8003u16.to_le_bytes());
+        raw[0x0E..0x13].copy_from_slice(b"Title");
+        raw[0x6E..0x70].copy_from_slice(&16639u16.to_le_bytes());
+        raw[0x7B] = chips;
+        raw.extend([
+            0x85, 0x00, // INIT: STA $00
+            0x60, // RTS
+            0xE6, 0x01, // PLAY: INC $01
+            0x60, // RTS
+        ]);
+        raw
+    }
+
+    #[test]
+    fn test_parse_nsf() {
+        let rom = Rom::new(&test_nsf(0b0000_0101)).unwrap();
+        let info = rom.nsf.as_ref().unwrap();
+
+        assert_eq!(rom.mapper, NSF_MAPPER);
+        assert_eq!(rom.title, Some("Title".to_string()));
+        assert_eq!(info.total_songs, 3);
+        assert_eq!(info.starting_song, 1);
+        assert_eq!(info.play_addr, 0x8003);
+        assert_eq!(info.banks, None);
+        assert_eq!(info.chips, vec![ExpansionChip::Vrc6, ExpansionChip::Fds]);
+        assert_eq!(info.track_name(0), "Track 1");
+    }
+
+    #[test]
+    fn test_parse_nsfe() {
+        fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+            let mut result = (data.len() as u32).to_le_bytes().to_vec();
+            result.extend(id);
+            result.extend(data);
+            result
+        }
+        let mut raw = NSFE_TAG.to_vec();
+        raw.extend(chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x03, 0x80, 0x00, 0x20, 2, 0]));
+        raw.extend(chunk(b"DATA", &[0x60; 4]));
+        raw.extend(chunk(b"BANK", &[0, 1]));
+        raw.extend(chunk(b"auth", b"Game\0Artist\0(c)\0Ripper\0"));
+        raw.extend(chunk(b"tlbl", b"Opening\0Ending\0"));
+        raw.extend(chunk(b"time", &[0x10, 0x27, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]));
+        raw.extend(chunk(b"NEND", &[]));
+
+        let rom = Rom::new(&raw).unwrap();
+        let info = rom.nsf.unwrap();
+        assert_eq!(info.total_songs, 2);
+        assert_eq!(info.banks, Some([0, 1, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(info.chips, vec![ExpansionChip::Sunsoft5b]);
+        assert_eq!(info.artist, "Artist");
+        assert_eq!(info.track_name(1), "Ending");
+        assert_eq!(info.track_time_ms(0), Some(10000));
+        assert_eq!(info.track_time_ms(1), None);
+
+        let mut unknown = NSFE_TAG.to_vec();
+        unknown.extend(chunk(b"XTRA", &[]));
+        assert!(parse(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_driver_calls_init_and_play() {
+        let rom = Rom::new(&test_nsf(0)).unwrap();
+        let mut cpu = CPU::new(Bus::new(rom).unwrap());
+        start_track(&mut cpu, 2);
+
+        // 1秒分実行するとPLAYは約60回呼ばれる。
+        while cpu.bus.cycles() < CPU_CLOCK_RATE as u64 {
+            cpu.step();
+        }
+        assert_eq!(cpu.mem_read(0x00), 2);
+        let plays = cpu.mem_read(0x01);
+        assert!((59..=60).contains(&plays), "{}", plays);
+    }
+
+    #[test]
+    fn test_bankswitching() {
+        let mut raw = test_nsf(0);
+        raw[0x70..0x78].copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 1]);
+        raw.truncate(NSF_HEADER_SIZE);
+        raw.extend((0..8u8).flat_map(|bank| vec![bank; BANK_SIZE]));
+        let mut nsf = Nsf::new(Rom::new(&raw).unwrap()).unwrap();
+
+        assert_eq!(nsf.read_prg(0x9000), 1);
+        assert_eq!(nsf.read_prg(0xF000), 1);
+        nsf.write_expansion(0x5FF9, 7);
+        assert_eq!(nsf.read_prg(0x9000), 7);
+        // ベクターはドライバーを指す。
+        assert_eq!(nsf.read_prg(0xFFFC), 0x00);
+        assert_eq!(nsf.read_prg(0xFFFD), 0x41);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::ports::{InputDevice, JOYPAD1};
+use crate::savestate::impl_save_state;
+
+// アルカノイドのバウス(パドル)。ボリュームの値を8bit、上位ビットから反転して読み出す。
+// NES版は2コンの端子のD4が値、D3がボタン。ファミコン版は拡張端子で$4017のD1が値、$4016のD1がボタン。
+// ゲームが受け付けるボリュームの範囲
+const PADDLE_MIN: u8 = 0x62;
+const PADDLE_MAX: u8 = 0xF2;
+
+pub struct Paddle {
+    famicom: bool,
+    // 0(左端)から255(右端)
+    position: u8,
+    button: bool,
+    strobe: bool,
+    shift: u8,
+}
+
+impl_save_state!(Paddle { strobe, shift });
+
+impl Paddle {
+    pub fn new(famicom: bool) -> Self {
+        Paddle {
+            famicom,
+            position: 0x80,
+            button: false,
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    pub fn set_position(&mut self, position: u8) {
+        self.position = position;
+    }
+
+    pub fn set_button(&mut self, pressed: bool) {
+        self.button = pressed;
+    }
+
+    fn value(&self) -> u8 {
+        PADDLE_MIN + (self.position as u32 * (PADDLE_MAX - PADDLE_MIN) as u32 / 255) as u8
+    }
+
+    fn read_serial(&mut self) -> u8 {
+        let bit = (!self.shift >> 7) & 1;
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+        bit
+    }
+}
+
+impl InputDevice for Paddle {
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.shift = self.value();
+        }
+    }
+
+    fn read(&mut self, address: u16, _cycles: u64) -> u8 {
+        let button = self.button as u8;
+        match (self.famicom, address) {
+            (true, JOYPAD1) => button << 1,
+            (true, _) => self.read_serial() << 1,
+            (false, _) => self.read_serial() << 4 | button << 3,
+        }
+    }
+
+    fn as_paddle_mut(&mut self) -> Option<&mut Paddle> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ports::JOYPAD2;
+
+    fn read_value(paddle: &mut Paddle, shift: u8) -> u8 {
+        paddle.write(1);
+        paddle.write(0);
+        (0..8).fold(0, |value, _| value << 1 | ((paddle.read(JOYPAD2, 0) >> shift) & 1))
+    }
+
+    #[test]
+    fn test_potentiometer() {
+        let mut paddle = Paddle::new(false);
+        paddle.set_position(0);
+        assert_eq!(read_value(&mut paddle, 4), !PADDLE_MIN);
+        paddle.set_position(255);
+        paddle.set_button(true);
+        assert_eq!(read_value(&mut paddle, 4), !PADDLE_MAX);
+        assert_eq!(paddle.read(JOYPAD2, 0) & 0b0000_1000, 0b0000_1000);
+
+        let mut paddle = Paddle::new(true);
+        paddle.set_button(true);
+        assert_eq!(read_value(&mut paddle, 1), !paddle.value());
+        assert_eq!(paddle.read(JOYPAD1, 0), 0b10);
+    }
+}
@@ -0,0 +1,391 @@
+use crate::cartridge::MAX_ROM_SIZE;
+use std::path::{Path, PathBuf};
+
+const IPS_TAG: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const UPS_TAG: &[u8] = b"UPS1";
+const BPS_TAG: &[u8] = b"BPS1";
+// UPS/BPSの末尾はsource, target, patch自身のCRC32
+const FOOTER_SIZE: usize = 12;
+
+pub const PATCH_EXTENSIONS: [&str; 3] = ["ips", "bps", "ups"];
+
+// ROMと同じ名前の.ips/.bps/.upsがあればそれを返す。
+pub fn find_patch(rom_path: &Path) -> Option<PathBuf> {
+    PATCH_EXTENSIONS
+        .iter()
+        .map(|ext| rom_path.with_extension(ext))
+        .find(|path| path.is_file())
+}
+
+// パッチの形式は先頭のタグで判別する。
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(IPS_TAG) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(UPS_TAG) {
+        apply_ups(rom, patch)
+    } else if patch.starts_with(BPS_TAG) {
+        apply_bps(rom, patch)
+    } else {
+        Err("Unknown patch format".to_string())
+    }
+}
+
+struct PatchReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        PatchReader { data, pos }
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.data.len() - self.pos {
+            return Err("Patch file is truncated".to_string());
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_be(&mut self, len: usize) -> Result<usize, String> {
+        Ok(self
+            .read_bytes(len)?
+            .iter()
+            .fold(0, |acc, &b| (acc << 8) | b as usize))
+    }
+
+    // UPS/BPS共通の可変長整数。壊れたパッチで桁あふれしないよう、すべての計算を検査する。
+    fn read_varint(&mut self) -> Result<usize, String> {
+        let malformed = || "Patch file has a malformed varint".to_string();
+        let mut data: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let x = self.read_u8()?;
+            data = ((x & 0x7f) as usize)
+                .checked_mul(shift)
+                .and_then(|value| data.checked_add(value))
+                .ok_or_else(malformed)?;
+            if x & 0x80 != 0 {
+                return Ok(data);
+            }
+            shift = shift.checked_mul(0x80).ok_or_else(malformed)?;
+            data = data.checked_add(shift).ok_or_else(malformed)?;
+        }
+    }
+}
+
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = PatchReader::new(patch, IPS_TAG.len());
+    let mut output = rom.to_vec();
+
+    loop {
+        if reader.read_bytes(IPS_EOF.len())? == IPS_EOF {
+            break;
+        }
+        reader.pos -= IPS_EOF.len();
+
+        let offset = reader.read_be(3)?;
+        let size = reader.read_be(2)?;
+        // サイズ0のレコードはRLE
+        let (len, data) = if size == 0 {
+            let len = reader.read_be(2)?;
+            (len, vec![reader.read_u8()?; len])
+        } else {
+            (size, reader.read_bytes(size)?.to_vec())
+        };
+
+        if output.len() < offset + len {
+            output.resize(offset + len, 0);
+        }
+        output[offset..offset + len].copy_from_slice(&data);
+    }
+
+    // EOFの後の3バイトは切り詰め後のサイズ(拡張仕様)
+    if let Ok(truncate) = reader.read_be(3) {
+        output.truncate(truncate);
+    }
+    Ok(output)
+}
+
+// originalとmodifiedの差分をIPSパッチとして作る。FDSのディスク書き込みの保存に使う。
+pub fn create_ips(original: &[u8], modified: &[u8]) -> Vec<u8> {
+    let mut patch = IPS_TAG.to_vec();
+    let mut pos = 0;
+    while pos < modified.len() {
+        if original.get(pos) == Some(&modified[pos]) {
+            pos += 1;
+            continue;
+        }
+        // "EOF"と同じオフセットは終端と区別できないので1バイト前から書く。
+        let start = if pos == 0x454F46 { pos - 1 } else { pos };
+        let mut end = pos;
+        while end < modified.len() && end - start < 0xFFFF && original.get(end) != Some(&modified[end]) {
+            end += 1;
+        }
+        patch.extend(&(start as u32).to_be_bytes()[1..]);
+        patch.extend(&((end - start) as u16).to_be_bytes());
+        patch.extend(&modified[start..end]);
+        pos = end;
+    }
+    patch.extend(IPS_EOF);
+    if modified.len() < original.len() {
+        patch.extend(&(modified.len() as u32).to_be_bytes()[1..]);
+    }
+    patch
+}
+
+fn read_footer(patch: &[u8]) -> Result<(u32, u32), String> {
+    if patch.len() < FOOTER_SIZE + 4 {
+        return Err("Patch file is truncated".to_string());
+    }
+    let footer = &patch[patch.len() - FOOTER_SIZE..];
+    let crc = |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+
+    let patch_crc = crc32fast::hash(&patch[..patch.len() - 4]);
+    if patch_crc != crc(8) {
+        return Err(format!("Patch checksum mismatch (expected {:08X}, got {:08X})", crc(8), patch_crc));
+    }
+    Ok((crc(0), crc(4)))
+}
+
+fn verify_crc(name: &str, data: &[u8], expected: u32) -> Result<(), String> {
+    let actual = crc32fast::hash(data);
+    if actual != expected {
+        return Err(format!("{} checksum mismatch (expected {:08X}, got {:08X})", name, expected, actual));
+    }
+    Ok(())
+}
+
+// パッチに書かれた出力の大きさ。確保する前に上限と比べる。
+fn check_target_size(target_size: usize) -> Result<usize, String> {
+    if target_size > MAX_ROM_SIZE {
+        return Err(format!("Patched ROM would be too large ({} bytes, limit {})", target_size, MAX_ROM_SIZE));
+    }
+    Ok(target_size)
+}
+
+pub fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let (source_crc, target_crc) = read_footer(patch)?;
+    let end = patch.len() - FOOTER_SIZE;
+    let mut reader = PatchReader::new(patch, UPS_TAG.len());
+
+    let source_size = reader.read_varint()?;
+    let target_size = check_target_size(reader.read_varint()?)?;
+    if source_size != rom.len() {
+        return Err(format!("ROM size mismatch (patch expects {} bytes, got {})", source_size, rom.len()));
+    }
+    verify_crc("Source ROM", rom, source_crc)?;
+
+    let mut output = rom.to_vec();
+    output.resize(target_size, 0);
+
+    let mut pos: usize = 0;
+    while reader.pos < end {
+        pos = pos.checked_add(reader.read_varint()?).ok_or("Patch file has an invalid offset")?;
+        loop {
+            let x = reader.read_u8()?;
+            if pos < output.len() {
+                output[pos] ^= x;
+            }
+            pos += 1;
+            if x == 0 {
+                break;
+            }
+        }
+    }
+
+    verify_crc("Patched ROM", &output, target_crc)?;
+    Ok(output)
+}
+
+fn apply_relative(offset: &mut usize, data: usize) -> Result<(), String> {
+    let delta = data >> 1;
+    let result = if data & 1 != 0 {
+        offset.checked_sub(delta)
+    } else {
+        offset.checked_add(delta)
+    };
+    *offset = result.ok_or("Patch file has an invalid offset")?;
+    Ok(())
+}
+
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let (source_crc, target_crc) = read_footer(patch)?;
+    let end = patch.len() - FOOTER_SIZE;
+    let mut reader = PatchReader::new(patch, BPS_TAG.len());
+
+    let source_size = reader.read_varint()?;
+    let target_size = check_target_size(reader.read_varint()?)?;
+    let metadata_size = reader.read_varint()?;
+    reader.read_bytes(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(format!("ROM size mismatch (patch expects {} bytes, got {})", source_size, rom.len()));
+    }
+    verify_crc("Source ROM", rom, source_crc)?;
+
+    let out_of_range = || "Patch file reads out of range".to_string();
+    // target_sizeはパッチに書かれた値なので、そのまま確保せずに入力の大きさまでに抑える。
+    let mut output = Vec::with_capacity(target_size.min(rom.len() + patch.len()));
+    let mut source_offset = 0;
+    let mut target_offset = 0;
+
+    while reader.pos < end {
+        let data = reader.read_varint()?;
+        let len = (data >> 2) + 1;
+        match data & 0b11 {
+            // SourceRead
+            0 => {
+                let start = output.len();
+                let bytes = rom.get(start..start + len).ok_or_else(out_of_range)?;
+                output.extend_from_slice(bytes);
+            }
+            // TargetRead
+            1 => output.extend_from_slice(reader.read_bytes(len)?),
+            // SourceCopy
+            2 => {
+                apply_relative(&mut source_offset, reader.read_varint()?)?;
+                let bytes = source_offset
+                    .checked_add(len)
+                    .and_then(|end| rom.get(source_offset..end))
+                    .ok_or_else(out_of_range)?;
+                output.extend_from_slice(bytes);
+                source_offset += len;
+            }
+            // TargetCopy: 書き込み中の領域と重なることがあるので1バイトずつコピーする。
+            _ => {
+                apply_relative(&mut target_offset, reader.read_varint()?)?;
+                for _ in 0..len {
+                    let byte = *output.get(target_offset).ok_or_else(out_of_range)?;
+                    output.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if output.len() != target_size {
+        return Err(format!("Patched ROM size mismatch (expected {} bytes, got {})", target_size, output.len()));
+    }
+    verify_crc("Patched ROM", &output, target_crc)?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend(&crc32fast::hash(source).to_le_bytes());
+        patch.extend(&crc32fast::hash(target).to_le_bytes());
+        let patch_crc = crc32fast::hash(&patch);
+        patch.extend(&patch_crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_malformed_varint() {
+        // 終端のビットがなく、桁あふれするまで続く数
+        assert_eq!(PatchReader::new(&[0x7f; 16], 0).read_varint(), Err("Patch file has a malformed varint".to_string()));
+        assert_eq!(PatchReader::new(&[0x00, 0x81], 0).read_varint(), Ok(0x80 + 0x80));
+
+        let source = [0u8; 4];
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(&[0x84, 0x84, 0x80]);
+        patch.extend(&[0x7f; 12]);
+        let patch = with_footer(patch, &source, &source);
+        assert!(apply(&source, &patch).is_err());
+        let patch = with_footer([b"UPS1".to_vec(), vec![0x84, 0x84], vec![0x7f; 12]].concat(), &source, &source);
+        assert!(apply(&source, &patch).is_err());
+    }
+
+    #[test]
+    fn test_target_size_limit() {
+        let source = [0u8; 4];
+        // 出力が512MB以上になるパッチ
+        let huge = [0x00, 0x00, 0x00, 0x00, 0x81];
+        assert!(PatchReader::new(&huge, 0).read_varint().unwrap() > MAX_ROM_SIZE);
+        let ups = with_footer([b"UPS1".to_vec(), vec![0x84], huge.to_vec()].concat(), &source, &source);
+        assert!(apply(&source, &ups).unwrap_err().contains("too large"));
+        let bps = with_footer([b"BPS1".to_vec(), vec![0x84], huge.to_vec(), vec![0x80]].concat(), &source, &source);
+        assert!(apply(&source, &bps).unwrap_err().contains("too large"));
+    }
+
+    #[test]
+    fn test_ips() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+        // RLE: offset 5, 3バイトを0xCCで埋める
+        patch.extend(&[0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x03, 0xCC]);
+        patch.extend(b"EOF");
+
+        let output = apply(&[0; 6], &patch).unwrap();
+        assert_eq!(output, vec![0x00, 0xAA, 0xBB, 0x00, 0x00, 0xCC, 0xCC, 0xCC]);
+    }
+
+    #[test]
+    fn test_create_ips() {
+        let original = vec![0; 16];
+        let mut modified = original.clone();
+        modified[3] = 1;
+        modified[4] = 2;
+        modified[10] = 3;
+        modified.push(4);
+
+        let patch = create_ips(&original, &modified);
+        assert_eq!(apply(&original, &patch).unwrap(), modified);
+        assert_eq!(apply(&modified, &create_ips(&modified, &original)).unwrap(), original);
+    }
+
+    #[test]
+    fn test_ups() {
+        let source = vec![1, 2, 3, 4];
+        let target = vec![1, 9, 3, 4, 5];
+
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(&[0x84, 0x85]);
+        // offset 1: 2^9, offset 4: 0^5
+        patch.extend(&[0x81, 2 ^ 9, 0x00]);
+        patch.extend(&[0x81, 5, 0x00]);
+        let patch = with_footer(patch, &source, &target);
+
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn test_bps() {
+        let source = vec![1, 2, 3, 4];
+        let target = vec![1, 2, 7, 7, 7, 1, 2];
+
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(&[0x84, 0x87, 0x80]);
+        // SourceRead 2
+        patch.push(0x80 | (1 << 2));
+        // TargetRead 1 (7)
+        patch.extend(&[0x80 | 1, 7]);
+        // TargetCopy 2 from offset 2
+        patch.extend(&[0x80 | (1 << 2) | 3, 0x80 | (2 << 1)]);
+        // SourceCopy 2 from offset 0
+        patch.extend(&[0x80 | (1 << 2) | 2, 0x80]);
+        let patch = with_footer(patch, &source, &target);
+
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn test_source_crc_mismatch() {
+        let source = vec![1, 2, 3, 4];
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(&[0x84, 0x84]);
+        let patch = with_footer(patch, &[9, 9, 9, 9], &source);
+
+        let err = apply(&source, &patch).unwrap_err();
+        assert!(err.starts_with("Source ROM checksum mismatch"));
+    }
+}
@@ -0,0 +1,76 @@
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+// IHDRのビット深度と色の種類(8bitのRGB)
+const BIT_DEPTH: u8 = 8;
+const COLOR_TYPE_RGB: u8 = 2;
+
+pub fn save_png(path: &Path, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_png(&mut out, width, height, rgb)?;
+    out.flush()
+}
+
+// RGB24の画像をPNGで書く。各行の先頭にフィルターなし(0)を付けてzlibで圧縮する。
+pub fn write_png(out: &mut impl Write, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    if rgb.len() != width * height * 3 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Image size does not match the pixel data"));
+    }
+    out.write_all(PNG_SIGNATURE)?;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 圧縮方式、フィルター方式、インターレースはすべて0
+    header.extend_from_slice(&[BIT_DEPTH, COLOR_TYPE_RGB, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &header)?;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in rgb.chunks_exact(width * 3) {
+        encoder.write_all(&[0])?;
+        encoder.write_all(row)?;
+    }
+    write_chunk(out, b"IDAT", &encoder.finish()?)?;
+    write_chunk(out, b"IEND", &[])
+}
+
+// チャンクは長さ、種類、データ、種類とデータのCRC32の順
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+    out.write_all(&hasher.finalize().to_be_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_write_png() {
+        let rgb = [255, 0, 0, 0, 255, 0, 0, 0, 255, 1, 2, 3];
+        let mut data = Vec::new();
+        write_png(&mut data, 2, 2, &rgb).unwrap();
+        assert_eq!(&data[..8], PNG_SIGNATURE);
+        assert_eq!(&data[12..16], b"IHDR");
+        assert_eq!(&data[16..24], &[0, 0, 0, 2, 0, 0, 0, 2]);
+        assert_eq!(&data[data.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+
+        let idat_len = u32::from_be_bytes(data[33..37].try_into().unwrap()) as usize;
+        assert_eq!(&data[37..41], b"IDAT");
+        let mut pixels = Vec::new();
+        ZlibDecoder::new(&data[41..41 + idat_len]).read_to_end(&mut pixels).unwrap();
+        assert_eq!(pixels, [0, 255, 0, 0, 0, 255, 0, 0, 0, 0, 255, 1, 2, 3]);
+
+        assert!(write_png(&mut Vec::new(), 3, 2, &rgb).is_err());
+    }
+}
@@ -0,0 +1,364 @@
+use crate::family_keyboard::FamilyKeyboard;
+use crate::joypad::Joypad;
+use crate::paddle::Paddle;
+use crate::power_pad::{FamilyTrainer, PowerPad};
+use crate::savestate::{impl_save_state, SaveState, StateReader, StateWriter};
+use crate::snes_mouse::SnesMouse;
+use crate::zapper::Zapper;
+
+pub const JOYPAD1: u16 = 0x4016;
+pub const JOYPAD2: u16 = 0x4017;
+
+// Four Scoreの3バイト目(読み出し順にbit0から)。$4016は20回目、$4017は19回目の読み込みが1になる。
+const FOUR_SCORE_SIGNATURES: [u8; 2] = [0b0000_1000, 0b0000_0100];
+const FOUR_SCORE_READS: u8 = 24;
+
+// コントローラー端子(1コン, 2コン)とファミコンの拡張端子につなぐ機器
+pub trait InputDevice: SaveState {
+    // $4016への書き込み。bit0がストローブ、bit1-2は拡張端子の出力
+    fn write(&mut self, data: u8);
+    // $4016/$4017の読み込み。つながっている線のビット(D0-D4)だけを返す。
+    // cyclesは光線銃がビームの位置を知るためのCPUサイクル数
+    fn read(&mut self, address: u16, cycles: u64) -> u8;
+
+    // フロントエンドから状態を設定するための取り出し。indexは機器の中のコントローラーの番号
+    fn joypad_mut(&mut self, _index: usize) -> Option<&mut Joypad> {
+        None
+    }
+
+    fn as_zapper_mut(&mut self) -> Option<&mut Zapper> {
+        None
+    }
+
+    fn as_paddle_mut(&mut self) -> Option<&mut Paddle> {
+        None
+    }
+
+    fn as_power_pad_mut(&mut self) -> Option<&mut PowerPad> {
+        None
+    }
+
+    fn as_keyboard_mut(&mut self) -> Option<&mut FamilyKeyboard> {
+        None
+    }
+
+    fn as_mouse_mut(&mut self) -> Option<&mut SnesMouse> {
+        None
+    }
+}
+
+// 3P, 4Pのつなぎ方
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FourPlayerMode {
+    Off,
+    // NESのFour Score: 1P(2P), 3P(4P), 識別子の順に同じビットから24bit読み出す。
+    FourScore,
+    // ファミコンの拡張端子: 3Pは$4016、4Pは$4017のbit1から読み出す。
+    Famicom,
+}
+
+impl FourPlayerMode {
+    pub fn parse(mode: &str) -> Result<FourPlayerMode, String> {
+        match mode {
+            "off" => Ok(FourPlayerMode::Off),
+            "fourscore" => Ok(FourPlayerMode::FourScore),
+            "famicom" => Ok(FourPlayerMode::Famicom),
+            _ => Err(format!("Unknown four player mode: {} (expected off, fourscore or famicom)", mode)),
+        }
+    }
+}
+
+// 端子につなぐ機器の種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceKind {
+    None,
+    Joypad,
+    Zapper,
+    // アルカノイドのバウス。拡張端子ではファミコン版になる。
+    Paddle,
+    // NESのPower Pad。拡張端子ではファミリートレーナーになる。
+    PowerPad,
+    // スーパーファミコンのマウス
+    Mouse,
+    // ファミリーベーシックのキーボード(拡張端子のみ)
+    Keyboard,
+}
+
+impl DeviceKind {
+    pub fn parse(name: &str) -> Result<DeviceKind, String> {
+        match name {
+            "none" => Ok(DeviceKind::None),
+            "joypad" => Ok(DeviceKind::Joypad),
+            // 受光にはPPUのビームの位置と描いた画面が要る。PPUができるまで通常の指定ではつながない。
+            "zapper" => Err("The Zapper is not available until there is a PPU to sense light from".to_string()),
+            "paddle" => Ok(DeviceKind::Paddle),
+            "powerpad" => Ok(DeviceKind::PowerPad),
+            "mouse" => Ok(DeviceKind::Mouse),
+            "keyboard" => Ok(DeviceKind::Keyboard),
+            _ => Err(format!(
+                "Unknown input device: {} (expected none, joypad, paddle, powerpad, mouse or keyboard)",
+                name
+            )),
+        }
+    }
+}
+
+// NESのFour Score。端子ごとに2つのコントローラーをつなぐ。
+pub struct FourScore {
+    // 1P, 3P(2コンの端子なら2P, 4P)
+    joypads: [Joypad; 2],
+    signature: u8,
+    strobe: bool,
+    read_count: u8,
+}
+
+impl_save_state!(FourScore { joypads, strobe, read_count });
+
+impl FourScore {
+    pub fn new(port: usize) -> Self {
+        FourScore {
+            joypads: Default::default(),
+            signature: FOUR_SCORE_SIGNATURES[port],
+            strobe: false,
+            read_count: 0,
+        }
+    }
+}
+
+impl InputDevice for FourScore {
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.read_count = 0;
+        }
+        for joypad in self.joypads.iter_mut() {
+            joypad.write(data);
+        }
+    }
+
+    fn read(&mut self, _address: u16, _cycles: u64) -> u8 {
+        let count = self.read_count;
+        let data = match count {
+            0..=7 => self.joypads[0].read(),
+            8..=15 => self.joypads[1].read(),
+            16..=23 => (self.signature >> (count - 16)) & 1,
+            _ => 1,
+        };
+        if !self.strobe && count < FOUR_SCORE_READS {
+            self.read_count += 1;
+        }
+        data
+    }
+
+    fn joypad_mut(&mut self, index: usize) -> Option<&mut Joypad> {
+        self.joypads.get_mut(index)
+    }
+}
+
+// ファミコンの拡張端子につなぐ3P, 4Pのコントローラー
+#[derive(Default)]
+pub struct FamicomJoypads {
+    joypads: [Joypad; 2],
+}
+
+impl_save_state!(FamicomJoypads { joypads });
+
+impl InputDevice for FamicomJoypads {
+    fn write(&mut self, data: u8) {
+        for joypad in self.joypads.iter_mut() {
+            joypad.write(data);
+        }
+    }
+
+    fn read(&mut self, address: u16, _cycles: u64) -> u8 {
+        self.joypads[(address - JOYPAD1) as usize].read() << 1
+    }
+
+    fn joypad_mut(&mut self, index: usize) -> Option<&mut Joypad> {
+        self.joypads.get_mut(index)
+    }
+}
+
+// port: 0が1コン、1が2コン、Noneが拡張端子
+pub fn new_device(kind: DeviceKind, port: Option<usize>) -> Result<Option<Box<dyn InputDevice>>, String> {
+    let device: Box<dyn InputDevice> = match (kind, port) {
+        (DeviceKind::None, _) => return Ok(None),
+        (DeviceKind::Joypad, Some(_)) => Box::new(Joypad::new()),
+        (DeviceKind::Joypad, None) => Box::new(FamicomJoypads::default()),
+        (DeviceKind::Zapper, Some(_)) => Box::new(Zapper::new()),
+        (DeviceKind::Paddle, _) => Box::new(Paddle::new(port.is_none())),
+        (DeviceKind::PowerPad, Some(_)) => Box::new(PowerPad::new()),
+        (DeviceKind::PowerPad, None) => Box::new(FamilyTrainer::new()),
+        (DeviceKind::Mouse, Some(_)) => Box::new(SnesMouse::new()),
+        (DeviceKind::Keyboard, None) => Box::new(FamilyKeyboard::new()),
+        (DeviceKind::Zapper | DeviceKind::Mouse, None) => {
+            return Err(format!("{:?} cannot be connected to the expansion port", kind))
+        }
+        (DeviceKind::Keyboard, Some(_)) => return Err("Keyboard can only be connected to the expansion port".to_string()),
+    };
+    Ok(Some(device))
+}
+
+// $4016/$4017につながる機器
+pub struct ControllerPorts {
+    ports: [Option<Box<dyn InputDevice>>; 2],
+    expansion: Option<Box<dyn InputDevice>>,
+}
+
+impl Default for ControllerPorts {
+    fn default() -> Self {
+        ControllerPorts::new()
+    }
+}
+
+impl ControllerPorts {
+    // 標準コントローラーを2つつないだ状態
+    pub fn new() -> Self {
+        ControllerPorts {
+            ports: [Some(Box::new(Joypad::new())), Some(Box::new(Joypad::new()))],
+            expansion: None,
+        }
+    }
+
+    pub fn set_device(&mut self, port: usize, kind: DeviceKind) -> Result<(), String> {
+        self.ports[port] = new_device(kind, Some(port))?;
+        Ok(())
+    }
+
+    pub fn set_expansion_device(&mut self, kind: DeviceKind) -> Result<(), String> {
+        self.expansion = new_device(kind, None)?;
+        Ok(())
+    }
+
+    pub fn set_four_player(&mut self, mode: FourPlayerMode) {
+        match mode {
+            FourPlayerMode::Off => {}
+            FourPlayerMode::FourScore => {
+                self.ports = [Some(Box::new(FourScore::new(0))), Some(Box::new(FourScore::new(1)))];
+            }
+            FourPlayerMode::Famicom => self.expansion = Some(Box::new(FamicomJoypads::default())),
+        }
+    }
+
+    pub fn devices_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn InputDevice>> {
+        self.ports.iter_mut().chain(std::iter::once(&mut self.expansion)).flatten()
+    }
+
+    pub fn zapper_mut(&mut self) -> Option<&mut Zapper> {
+        self.devices_mut().find_map(|device| device.as_zapper_mut())
+    }
+
+    // playerは0始まり。3P, 4PはFour Scoreか拡張端子のコントローラー
+    pub fn joypad_mut(&mut self, player: usize) -> Option<&mut Joypad> {
+        let port = player % 2;
+        if player < 2 {
+            return self.ports[port].as_mut()?.joypad_mut(0);
+        }
+        if self.ports[port].as_mut().is_some_and(|device| device.joypad_mut(1).is_some()) {
+            return self.ports[port].as_mut()?.joypad_mut(1);
+        }
+        self.expansion.as_mut()?.joypad_mut(port)
+    }
+
+    // $4016への書き込み。ストローブはすべての機器に届く。
+    pub fn write(&mut self, data: u8) {
+        for device in self.devices_mut() {
+            device.write(data);
+        }
+    }
+
+    // port 0が$4016、1が$4017
+    pub fn read(&mut self, port: usize, cycles: u64) -> u8 {
+        let address = JOYPAD1 + port as u16;
+        let mut data = match &mut self.ports[port] {
+            Some(device) => device.read(address, cycles),
+            None => 0,
+        };
+        if let Some(expansion) = &mut self.expansion {
+            data |= expansion.read(address, cycles);
+        }
+        data
+    }
+}
+
+// つないだ機器の構成はコマンドラインで決まるので保存しない。
+// 機器ごとにデータの長さを書いておき、構成が違えば読み込みをエラーにする。
+impl SaveState for ControllerPorts {
+    fn save(&self, w: &mut StateWriter) {
+        for device in self.ports.iter().chain(std::iter::once(&self.expansion)) {
+            let mut data = StateWriter::new();
+            if let Some(device) = device {
+                device.save(&mut data);
+            }
+            data.into_bytes().save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        for device in self.ports.iter_mut().chain(std::iter::once(&mut self.expansion)) {
+            let mut len = 0usize;
+            len.load(r)?;
+            let mut data = StateReader::new(r.read_bytes(len)?);
+            if let Some(device) = device {
+                device.load(&mut data)?;
+            }
+            if !data.is_empty() {
+                return Err("Save state was made with different controllers".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::joypad::JoypadButton;
+
+    fn test_ports(mode: FourPlayerMode) -> ControllerPorts {
+        let mut ports = ControllerPorts::new();
+        ports.set_four_player(mode);
+        let buttons = [JoypadButton::A, JoypadButton::B, JoypadButton::START, JoypadButton::RIGHT];
+        for (player, buttons) in buttons.into_iter().enumerate() {
+            ports.joypad_mut(player).unwrap().set_buttons(buttons);
+        }
+        ports.write(1);
+        ports.write(0);
+        ports
+    }
+
+    fn read_bits(ports: &mut ControllerPorts, port: usize, count: usize) -> u32 {
+        (0..count).fold(0, |bits, i| bits | ((ports.read(port, 0) as u32 & 1) << i))
+    }
+
+    #[test]
+    fn test_four_score() {
+        let mut ports = test_ports(FourPlayerMode::FourScore);
+        assert_eq!(read_bits(&mut ports, 0, 24), 0x08_08_01);
+        assert_eq!(read_bits(&mut ports, 1, 24), 0x04_80_02);
+        assert_eq!(ports.read(0, 0), 1);
+    }
+
+    #[test]
+    fn test_famicom_expansion() {
+        let mut ports = test_ports(FourPlayerMode::Famicom);
+        let reads: Vec<u8> = (0..8).map(|_| ports.read(0, 0)).collect();
+        assert_eq!(reads, [0b01, 0, 0, 0b10, 0, 0, 0, 0]);
+        let reads: Vec<u8> = (0..8).map(|_| ports.read(1, 0)).collect();
+        assert_eq!(reads, [0, 0b01, 0, 0, 0, 0, 0, 0b10]);
+    }
+
+    #[test]
+    fn test_device_placement() {
+        let mut ports = ControllerPorts::new();
+        assert!(ports.set_device(0, DeviceKind::Keyboard).is_err());
+        assert!(ports.set_expansion_device(DeviceKind::Zapper).is_err());
+        // 光線銃はPPUができるまで名前では選べない。
+        assert!(DeviceKind::parse("zapper").is_err());
+        assert_eq!(DeviceKind::parse("paddle"), Ok(DeviceKind::Paddle));
+        ports.set_device(1, DeviceKind::None).unwrap();
+        assert!(ports.joypad_mut(1).is_none());
+        assert_eq!(ports.read(1, 0), 0);
+        assert!(ports.joypad_mut(2).is_none());
+    }
+}
@@ -0,0 +1,139 @@
+use crate::ports::{InputDevice, JOYPAD2};
+use crate::savestate::impl_save_state;
+
+// Power Pad / ファミリートレーナーのマット。ボタンは1から12で、表面(Side B)の番号
+//    1  2  3  4
+//    5  6  7  8
+//    9 10 11 12
+pub const POWER_PAD_BUTTONS: usize = 12;
+
+// NES版: D3から読み出されるボタンとD4から読み出されるボタンの順。D4は4つの後に1が続く。
+const LOW_ORDER: [usize; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const HIGH_ORDER: [usize; 4] = [4, 3, 12, 8];
+
+// bit(n - 1)がボタンnの状態
+fn pressed(buttons: u16, button: usize) -> bool {
+    buttons & (1 << (button - 1)) != 0
+}
+
+fn latch(buttons: u16, order: &[usize]) -> u8 {
+    order.iter().enumerate().fold(0, |bits, (i, &button)| bits | (pressed(buttons, button) as u8) << i)
+}
+
+// NESのPower Pad(コントローラー端子)
+#[derive(Default)]
+pub struct PowerPad {
+    buttons: u16,
+    strobe: bool,
+    low: u8,
+    high: u8,
+}
+
+impl_save_state!(PowerPad { strobe, low, high });
+
+impl PowerPad {
+    pub fn new() -> Self {
+        PowerPad::default()
+    }
+
+    pub fn set_button(&mut self, button: usize, pressed: bool) {
+        let bit = 1 << (button - 1);
+        if pressed {
+            self.buttons |= bit;
+        } else {
+            self.buttons &= !bit;
+        }
+    }
+}
+
+impl InputDevice for PowerPad {
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.low = latch(self.buttons, &LOW_ORDER);
+            self.high = latch(self.buttons, &HIGH_ORDER) | 0xF0;
+        }
+    }
+
+    fn read(&mut self, _address: u16, _cycles: u64) -> u8 {
+        let data = (self.low & 1) << 3 | (self.high & 1) << 4;
+        if !self.strobe {
+            self.low = self.low >> 1 | 0x80;
+            self.high = self.high >> 1 | 0x80;
+        }
+        data
+    }
+
+    fn as_power_pad_mut(&mut self) -> Option<&mut PowerPad> {
+        Some(self)
+    }
+}
+
+// ファミリートレーナー(拡張端子)。$4016のbit2-0を0にした行(上から)のボタンが$4017のD4-D1に0で読める。
+#[derive(Default)]
+pub struct FamilyTrainer {
+    pad: PowerPad,
+    ignored_rows: u8,
+}
+
+impl_save_state!(FamilyTrainer { ignored_rows });
+
+impl FamilyTrainer {
+    pub fn new() -> Self {
+        FamilyTrainer::default()
+    }
+}
+
+impl InputDevice for FamilyTrainer {
+    fn write(&mut self, data: u8) {
+        self.ignored_rows = data & 0b111;
+    }
+
+    fn read(&mut self, address: u16, _cycles: u64) -> u8 {
+        if address != JOYPAD2 {
+            return 0;
+        }
+        let mut data = 0b0001_1110;
+        for row in 0..3 {
+            if self.ignored_rows & (0b100 >> row) != 0 {
+                continue;
+            }
+            for column in 0..4 {
+                if pressed(self.pad.buttons, row * 4 + column + 1) {
+                    data &= !(0b0001_0000 >> column);
+                }
+            }
+        }
+        data
+    }
+
+    fn as_power_pad_mut(&mut self) -> Option<&mut PowerPad> {
+        Some(&mut self.pad)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_power_pad_serial() {
+        let mut pad = PowerPad::new();
+        pad.set_button(1, true);
+        pad.set_button(12, true);
+        pad.write(1);
+        pad.write(0);
+        let reads: Vec<u8> = (0..8).map(|_| pad.read(JOYPAD2, 0)).collect();
+        assert_eq!(reads, [0, 0b01000, 0b10000, 0, 0b10000, 0b10000, 0b10000, 0b10000]);
+    }
+
+    #[test]
+    fn test_family_trainer_rows() {
+        let mut trainer = FamilyTrainer::new();
+        trainer.as_power_pad_mut().unwrap().set_button(6, true);
+        trainer.write(0b011);
+        assert_eq!(trainer.read(JOYPAD2, 0), 0b1_1110);
+        trainer.write(0b101);
+        assert_eq!(trainer.read(JOYPAD2, 0), 0b1_0110);
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+
+// 巻き戻し用のスナップショットのバッファ。
+// 最新のスナップショットだけをそのまま持ち、それより古いものは1つ新しいスナップショットとのXORを
+// ランレングス圧縮した差分で持つ。メモリの上限を超えたら古いものから捨てる。
+pub const DEFAULT_REWIND_INTERVAL: u32 = 2;
+pub const DEFAULT_REWIND_MEMORY: usize = 64 << 20;
+
+// 巻き戻し中の音
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RewindAudio {
+    Mute,
+    // 1フレーム分のサンプルを逆順に鳴らす。
+    Reverse,
+}
+
+impl RewindAudio {
+    pub fn parse(name: &str) -> Result<RewindAudio, String> {
+        match name {
+            "mute" => Ok(RewindAudio::Mute),
+            "reverse" => Ok(RewindAudio::Reverse),
+            _ => Err(format!("Unknown rewind audio mode: {} (mute or reverse)", name)),
+        }
+    }
+}
+
+pub struct Rewind {
+    // intervalフレームごとにスナップショットを取る。
+    interval: u32,
+    // 差分と最新のスナップショットに使うバイト数の上限
+    budget: usize,
+    frames: u32,
+    latest: Option<Vec<u8>>,
+    // 古い順。末尾がlatestの1つ前
+    deltas: VecDeque<Vec<u8>>,
+    delta_bytes: usize,
+}
+
+impl Rewind {
+    pub fn new(interval: u32, budget: usize) -> Self {
+        Rewind {
+            interval: interval.max(1),
+            budget,
+            frames: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+        }
+    }
+
+    // 1フレームに1回呼び、スナップショットを取るフレームならtrueを返す。
+    pub fn tick(&mut self) -> bool {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return false;
+        }
+        self.frames = 0;
+        true
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            let delta = encode_delta(&previous, &state);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = Some(state);
+        let latest_bytes = self.latest.as_ref().map_or(0, |state| state.len());
+        while self.delta_bytes + latest_bytes > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.delta_bytes -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    // 最新のスナップショットを返し、1つ前に戻る。一番古いスナップショットは何度でも返す。
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.as_ref()?;
+        let Some(delta) = self.deltas.pop_back() else {
+            return Some(latest.clone());
+        };
+        self.delta_bytes -= delta.len();
+        let previous = decode_delta(&delta, latest);
+        self.frames = 0;
+        self.latest.replace(previous)
+    }
+
+    // 戻れるスナップショットの数
+    pub fn len(&self) -> usize {
+        self.latest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.latest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*position];
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+// targetとbaseのXORを(0の長さ, 0以外の長さ, 0以外のバイト)の繰り返しで表す。先頭にtargetの長さを書く。
+// 長さが違う部分は短い方を0として扱う。
+fn encode_delta(target: &[u8], base: &[u8]) -> Vec<u8> {
+    let mut xor = base.to_vec();
+    xor.resize(target.len().max(base.len()), 0);
+    for (byte, target) in xor.iter_mut().zip(target) {
+        *byte ^= target;
+    }
+    let mut out = Vec::new();
+    write_varint(&mut out, target.len());
+    let mut i = 0;
+    while let Some(zeros) = xor[i..].iter().position(|&byte| byte != 0) {
+        let literal_start = i + zeros;
+        let literal_len = xor[literal_start..].iter().position(|&byte| byte == 0).unwrap_or(xor.len() - literal_start);
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literal_len);
+        out.extend_from_slice(&xor[literal_start..literal_start + literal_len]);
+        i = literal_start + literal_len;
+    }
+    out
+}
+
+fn decode_delta(delta: &[u8], base: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let len = read_varint(delta, &mut position);
+    let mut out = base.to_vec();
+    out.resize(out.len().max(len), 0);
+    let mut i = 0;
+    while position < delta.len() {
+        i += read_varint(delta, &mut position);
+        let literal_len = read_varint(delta, &mut position);
+        for (byte, xor) in out[i..i + literal_len].iter_mut().zip(&delta[position..position + literal_len]) {
+            *byte ^= xor;
+        }
+        i += literal_len;
+        position += literal_len;
+    }
+    out.truncate(len);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state(frame: u8, len: usize) -> Vec<u8> {
+        let mut state = vec![0x55; len];
+        state[3] = frame;
+        state[len - 1] = frame.wrapping_mul(3);
+        state
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let base = state(1, 1000);
+        for target in [state(2, 1000), state(2, 990), state(2, 1010), base.clone(), vec![]] {
+            let delta = encode_delta(&target, &base);
+            assert_eq!(decode_delta(&delta, &base), target);
+        }
+        // 変化の少ない差分は小さくなる。
+        assert!(encode_delta(&state(2, 1000), &base).len() < 16);
+    }
+
+    #[test]
+    fn test_rewind_order_and_budget() {
+        let mut rewind = Rewind::new(2, 1 << 20);
+        let mut pushed = 0;
+        for frame in 0..10 {
+            if rewind.tick() {
+                rewind.push(state(frame, 100 + frame as usize));
+                pushed += 1;
+            }
+        }
+        assert_eq!(rewind.len(), pushed);
+        assert_eq!(rewind.pop(), Some(state(9, 109)));
+        assert_eq!(rewind.pop(), Some(state(7, 107)));
+        for _ in 0..3 {
+            rewind.pop();
+        }
+        // 一番古いところで止まる。
+        assert_eq!(rewind.pop(), Some(state(1, 101)));
+        assert_eq!(rewind.len(), 1);
+
+        // 上限を超えると古いものから捨てる。
+        let mut rewind = Rewind::new(1, 150);
+        for frame in 0..20 {
+            rewind.push(state(frame, 100));
+        }
+        assert!(rewind.len() < 20);
+        while rewind.len() > 1 {
+            rewind.pop();
+        }
+        assert!(rewind.pop().unwrap()[3] > 0);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::cartridge::Mirroring;
+use lazy_static::lazy_static;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// db/nes20db.xmlをバイナリに埋め込み、初回のlookup時にパースする。
+// 埋め込みのデータベースは動作確認用の最小限のものなので、実際のカートリッジには
+// NES 2.0 DB(nes20db.xml)をload_databaseで読み込んで使う。
+const DATABASE_XML: &str = include_str!("../db/nes20db.xml");
+
+lazy_static! {
+    static ref DATABASE: Database = Database::parse(DATABASE_XML);
+}
+
+// 起動時に読み込んだ外部のデータベース。埋め込みのものより優先する。
+static EXTERNAL_DATABASE: OnceLock<Database> = OnceLock::new();
+
+#[derive(Debug, Default)]
+pub struct Database {
+    games: Vec<GameInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameInfo {
+    pub title: String,
+    pub crc32: u32,
+    pub sha1: String,
+    pub mapper: Option<u16>,
+    pub mirroring: Option<Mirroring>,
+    pub battery: Option<bool>,
+    pub prg_ram_size: Option<usize>,
+}
+
+// PRG-ROM+CHR-ROM(ヘッダーとトレーナーを除く)のハッシュ
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomHash {
+    pub crc32: u32,
+    pub sha1: String,
+    // FM2ムービーのromChecksum
+    pub md5: [u8; 16],
+}
+
+impl RomHash {
+    pub fn new(prg_rom: &[u8], chr_rom: &[u8]) -> Self {
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(prg_rom);
+        crc.update(chr_rom);
+
+        let mut sha1 = sha1_smol::Sha1::new();
+        sha1.update(prg_rom);
+        sha1.update(chr_rom);
+
+        let mut md5 = md5::Context::new();
+        md5.consume(prg_rom);
+        md5.consume(chr_rom);
+
+        RomHash {
+            crc32: crc.finalize(),
+            sha1: sha1.digest().to_string().to_uppercase(),
+            md5: md5.finalize().0,
+        }
+    }
+}
+
+impl Database {
+    // NES 2.0 DBのXMLファイルを読み込む。
+    pub fn load(path: &Path) -> Result<Database, String> {
+        let xml = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let database = Database::parse(&xml);
+        if database.games.is_empty() {
+            return Err(format!("{} has no games in NES 2.0 XML format", path.display()));
+        }
+        Ok(database)
+    }
+
+    pub fn parse(xml: &str) -> Database {
+        Database { games: parse_games(xml) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    // CRC32で候補を探し、SHA1も一致した場合のみ採用する。
+    pub fn find(&self, hash: &RomHash) -> Option<&GameInfo> {
+        self.games
+            .iter()
+            .find(|game| game.crc32 == hash.crc32 && game.sha1 == hash.sha1)
+    }
+}
+
+// NES 2.0 DBのXMLを読み込み、lookupで使う。Rom::newより前に1回だけ呼ぶ。戻り値は読み込んだゲームの数
+pub fn load_database(path: &Path) -> Result<usize, String> {
+    let database = Database::load(path)?;
+    let count = database.len();
+    EXTERNAL_DATABASE
+        .set(database)
+        .map_err(|_| "ROM database is already loaded".to_string())?;
+    Ok(count)
+}
+
+// 読み込んだ外部のデータベース、埋め込みのデータベースの順に探す。
+pub fn lookup(hash: &RomHash) -> Option<&'static GameInfo> {
+    lookup_in(EXTERNAL_DATABASE.get(), &DATABASE, hash)
+}
+
+fn lookup_in<'a>(external: Option<&'a Database>, embedded: &'a Database, hash: &RomHash) -> Option<&'a GameInfo> {
+    external.and_then(|database| database.find(hash)).or_else(|| embedded.find(hash))
+}
+
+fn parse_games(xml: &str) -> Vec<GameInfo> {
+    let mut games = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find("<game>") {
+        let body = &rest[start + "<game>".len()..];
+        let end = match body.find("</game>") {
+            Some(end) => end,
+            None => break,
+        };
+        if let Some(game) = parse_game(&body[..end]) {
+            games.push(game);
+        }
+        rest = &body[end..];
+    }
+    games
+}
+
+fn parse_game(body: &str) -> Option<GameInfo> {
+    let title = body
+        .find("<!--")
+        .and_then(|start| {
+            let comment = &body[start + 4..];
+            comment.find("-->").map(|end| comment[..end].trim())
+        })
+        .map(|name| name.trim_end_matches(".nes").to_string())
+        .unwrap_or_default();
+
+    let rom = element_attributes(body, "rom")?;
+    let crc32 = u32::from_str_radix(attribute(&rom, "crc32")?, 16).ok()?;
+    let sha1 = attribute(&rom, "sha1")?.to_uppercase();
+
+    let pcb = element_attributes(body, "pcb").unwrap_or_default();
+    let mirroring = attribute(&pcb, "mirroring").and_then(|m| match m {
+        "H" => Some(Mirroring::HORIZONTAL),
+        "V" => Some(Mirroring::VERTICAL),
+        "4" => Some(Mirroring::FOUR_SCREEN),
+        _ => None,
+    });
+
+    // バッテリー付きのRAMはprgnvram、そうでないものはprgramに書かれている。
+    let prg_ram_size = element_attributes(body, "prgnvram")
+        .or_else(|| element_attributes(body, "prgram"))
+        .and_then(|attrs| attribute(&attrs, "size")?.parse().ok());
+
+    Some(GameInfo {
+        title,
+        crc32,
+        sha1,
+        mapper: attribute(&pcb, "mapper").and_then(|m| m.parse().ok()),
+        mirroring,
+        battery: attribute(&pcb, "battery").map(|b| b == "1"),
+        prg_ram_size,
+    })
+}
+
+// <name key="value" .../> の属性を取り出す。
+fn element_attributes(body: &str, name: &str) -> Option<Vec<(String, String)>> {
+    let open = format!("<{} ", name);
+    let start = body.find(&open)? + open.len();
+    let end = body[start..].find('>')? + start;
+    let mut attrs = Vec::new();
+    let mut rest = body[start..end].trim_end_matches('/');
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim().to_string();
+        let value_start = rest[eq..].find('"')? + eq + 1;
+        let value_end = rest[value_start..].find('"')? + value_start;
+        attrs.push((key, rest[value_start..value_end].to_string()));
+        rest = &rest[value_end + 1..];
+    }
+    Some(attrs)
+}
+
+fn attribute<'a>(attrs: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    attrs
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_game() {
+        let xml = r#"
+<nes20db>
+  <game>
+    <!-- Test Game (Japan).nes -->
+    <prgrom size="32768" crc32="00000001" sha1="AA"/>
+    <rom size="40960" crc32="1234ABCD" sha1="0123456789abcdef0123456789abcdef01234567"/>
+    <prgnvram size="8192"/>
+    <pcb mapper="4" submapper="0" mirroring="V" battery="1"/>
+  </game>
+</nes20db>"#;
+        let games = parse_games(xml);
+
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].title, "Test Game (Japan)");
+        assert_eq!(games[0].crc32, 0x1234ABCD);
+        assert_eq!(games[0].sha1, "0123456789ABCDEF0123456789ABCDEF01234567");
+        assert_eq!(games[0].mapper, Some(4));
+        assert_eq!(games[0].mirroring, Some(Mirroring::VERTICAL));
+        assert_eq!(games[0].battery, Some(true));
+        assert_eq!(games[0].prg_ram_size, Some(8192));
+    }
+
+    #[test]
+    fn test_lookup_embedded_database() {
+        let rom = std::fs::read("../snake.nes").unwrap();
+        let hash = RomHash::new(&rom[16..], &[]);
+        let game = DATABASE.find(&hash).unwrap();
+
+        assert_eq!(game.title, "Snake");
+        assert_eq!(game.mapper, Some(0));
+    }
+
+    #[test]
+    fn test_load_external_database() {
+        let xml = r#"
+<nes20db>
+  <game>
+    <!-- External Game.nes -->
+    <rom size="16" crc32="89ABCDEF" sha1="FEDCBA9876543210FEDCBA9876543210FEDCBA98"/>
+    <pcb mapper="2" mirroring="V" battery="0"/>
+  </game>
+</nes20db>"#;
+        let dir = std::env::temp_dir().join("nes_emulator_romdb_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("nes20db.xml");
+        std::fs::write(&path, xml).unwrap();
+        assert!(Database::load(&dir.join("missing.xml")).is_err());
+
+        let external = Database::load(&path).unwrap();
+        assert_eq!(external.len(), 1);
+        let hash = RomHash { crc32: 0x89ABCDEF, sha1: "FEDCBA9876543210FEDCBA9876543210FEDCBA98".to_string(), md5: [0; 16] };
+        assert_eq!(lookup_in(Some(&external), &DATABASE, &hash).unwrap().title, "External Game");
+        assert_eq!(lookup_in(None, &DATABASE, &hash), None);
+        // 外部のデータベースにないROMは埋め込みのものから探す。
+        let rom = std::fs::read("../snake.nes").unwrap();
+        assert_eq!(lookup_in(Some(&external), &DATABASE, &RomHash::new(&rom[16..], &[])).unwrap().title, "Snake");
+        std::fs::write(&path, "<nes20db></nes20db>").unwrap();
+        assert!(Database::load(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
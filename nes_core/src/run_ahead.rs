@@ -0,0 +1,151 @@
+use crate::savestate::{SaveState, StateReader, StateWriter};
+use crate::{CPU, FRAME_CYCLES};
+
+// 先行実行するフレーム数の上限
+pub const MAX_RUN_AHEAD: u32 = 4;
+
+// 先行実行(ラン・アヘッド)。ホストの1フレームごとに、今の入力のままframesフレーム先まで実行して
+// その画面を表示し、状態を元に戻す。ゲーム自身が持つ入力の遅れの分だけ反応が早くなる。
+// instanceがあれば本体の状態を2つ目のCPUに写してそちらで実行する。なければ本体のミキサーを
+// 控えてから実行し、状態と一緒に戻す。どちらでも先行実行分の音は出力にも録音にも残らない。
+pub struct RunAhead {
+    frames: u32,
+    instance: Option<Box<CPU>>,
+}
+
+impl RunAhead {
+    pub fn new(frames: u32, instance: Option<CPU>) -> Result<Self, String> {
+        if frames > MAX_RUN_AHEAD {
+            return Err(format!("Run-ahead must be 0 to {} frames", MAX_RUN_AHEAD));
+        }
+        Ok(RunAhead { frames, instance: instance.map(Box::new) })
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    // frame_endは実行中のフレームが終わるCPUサイクル。run_frameは渡したサイクルまで1フレームを実行し、
+    // BRKで止まったらfalseを返す(snakeの乱数や速さの調整はここで行う)。
+    // 先行実行した後のCPUでviewを呼び、その結果を返す。BRKで止まったらそこでviewを呼ぶ。
+    pub fn run<R>(
+        &mut self,
+        cpu: &mut CPU,
+        frame_end: f64,
+        run_frame: impl FnMut(&mut CPU, f64) -> bool,
+        view: impl FnOnce(&mut CPU) -> R,
+    ) -> R {
+        if self.frames == 0 {
+            return view(cpu);
+        }
+        // CPU::loadはプログラムの読み込みなので、SaveStateのメソッドは名前を付けて呼ぶ。
+        let mut w = StateWriter::new();
+        SaveState::save(cpu, &mut w);
+        let state = w.into_bytes();
+        let frames = self.frames;
+
+        if let Some(instance) = &mut self.instance {
+            SaveState::load(instance.as_mut(), &mut StateReader::new(&state)).unwrap();
+            run_frames(instance, frame_end, frames, run_frame);
+            instance.bus.apu.samples().clear();
+            return view(instance);
+        }
+
+        let mixer = cpu.bus.apu.suspend_output();
+        run_frames(cpu, frame_end, frames, run_frame);
+        let result = view(cpu);
+        SaveState::load(cpu, &mut StateReader::new(&state)).unwrap();
+        cpu.bus.apu.resume_output(mixer);
+        result
+    }
+}
+
+fn run_frames(cpu: &mut CPU, frame_end: f64, frames: u32, mut run_frame: impl FnMut(&mut CPU, f64) -> bool) {
+    for frame in 0..frames {
+        if !run_frame(cpu, frame_end + frame as f64 * FRAME_CYCLES) {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::Rom;
+    use crate::Mem;
+
+    fn program_cpu(program: &[u8]) -> CPU {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[..program.len()].copy_from_slice(program);
+        prg_rom[0x7FFC] = 0x00;
+        prg_rom[0x7FFD] = 0x80;
+        raw.extend(prg_rom);
+        let mut cpu = CPU::new(Bus::new(Rom::new(&raw).unwrap()).unwrap());
+        cpu.reset();
+        cpu
+    }
+
+    // $10を数え続けるだけのプログラム(INC $10; JMP $8000)
+    fn counter_cpu() -> CPU {
+        program_cpu(&[0xE6, 0x10, 0x4C, 0x00, 0x80])
+    }
+
+    // 矩形波1を鳴らしてから$10を数え続ける。
+    fn tone_cpu() -> CPU {
+        program_cpu(&[
+            0xA9, 0x01, 0x8D, 0x15, 0x40, // LDA #$01; STA $4015
+            0xA9, 0xBF, 0x8D, 0x00, 0x40, // LDA #$BF; STA $4000
+            0xA9, 0xFD, 0x8D, 0x02, 0x40, // LDA #$FD; STA $4002
+            0xA9, 0x00, 0x8D, 0x03, 0x40, // LDA #$00; STA $4003
+            0xE6, 0x10, 0x4C, 0x14, 0x80, // INC $10; JMP $8014
+        ])
+    }
+
+    #[test]
+    fn test_run_ahead_restores_state() {
+        for instance in [None, Some(counter_cpu())] {
+            let mut cpu = counter_cpu();
+            cpu.run_until(100.0, |_| {});
+            let cycles = cpu.bus.cycles();
+            let count = cpu.mem_read(0x10);
+
+            let mut run_ahead = RunAhead::new(2, instance).unwrap();
+            let (ahead_cycles, ahead_count) = run_ahead.run(&mut cpu, FRAME_CYCLES, |cpu, end| cpu.run_until(end, |_| {}), |cpu| (cpu.bus.cycles(), cpu.mem_read(0x10)));
+            assert!(ahead_cycles as f64 >= 2.0 * FRAME_CYCLES);
+            assert_ne!(ahead_count, count);
+            assert_eq!(cpu.bus.cycles(), cycles);
+            assert_eq!(cpu.mem_read(0x10), count);
+        }
+        assert!(RunAhead::new(MAX_RUN_AHEAD + 1, None).is_err());
+    }
+
+    #[test]
+    fn test_run_ahead_keeps_recording() {
+        let dir = std::env::temp_dir().join(format!("nes_run_ahead_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // 先行実行してもしなくても、録音とホストに出す音は同じになる。
+        let record = |frames: u32, name: &str| {
+            let path = dir.join(name);
+            let mut cpu = tone_cpu();
+            cpu.bus.apu.start_recording(&path, false).unwrap();
+            let mut run_ahead = RunAhead::new(frames, None).unwrap();
+            let mut frame_end = FRAME_CYCLES;
+            for _ in 0..10 {
+                cpu.run_until(frame_end, |_| {});
+                frame_end += FRAME_CYCLES;
+                run_ahead.run(&mut cpu, frame_end, |cpu, end| cpu.run_until(end, |_| {}), |_| ());
+            }
+            let samples = cpu.bus.apu.samples();
+            let mut out = vec![0.0; samples.len()];
+            samples.read(&mut out);
+            cpu.bus.apu.stop_recording().unwrap();
+            (std::fs::read(&path).unwrap(), out)
+        };
+        let (wav, samples) = record(0, "plain.wav");
+        assert!(wav[44..].iter().any(|&b| b != 0));
+        assert_eq!(record(2, "ahead.wav"), (wav, samples));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
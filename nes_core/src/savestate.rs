@@ -0,0 +1,343 @@
+use std::cell::Cell;
+
+// セーブステートの形式:
+//   "NESSTATE", バージョン(u32), ROMのMD5(16バイト), ムービーのフレーム(u8のフラグ + u64), 本体
+// 本体は各部品がSaveStateで決めた順に値をリトルエンディアンで並べたもの。
+// 部品の中身を変えたらバージョンを上げて、古い形式は読み込まずにエラーにする。
+// PPUはまだ実装がないので、その分は版付きの区画(write_section)だけを空で置いておく。
+const STATE_MAGIC: &[u8; 8] = b"NESSTATE";
+pub const STATE_VERSION: u32 = 3;
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        StateWriter::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, position: 0 }
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + len)
+            .ok_or_else(|| "Save state is truncated".to_string())?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+}
+
+// 状態を保存する部品。loadはsaveと同じ順に読む。
+pub trait SaveState {
+    fn save(&self, w: &mut StateWriter);
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String>;
+}
+
+macro_rules! impl_save_state_for_int {
+    ($($type:ty),*) => {
+        $(
+            impl SaveState for $type {
+                fn save(&self, w: &mut StateWriter) {
+                    w.write_bytes(&self.to_le_bytes());
+                }
+
+                fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+                    let bytes = r.read_bytes(std::mem::size_of::<$type>())?;
+                    *self = <$type>::from_le_bytes(bytes.try_into().unwrap());
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_save_state_for_int!(u8, u16, u32, u64, i8, i16, i32, f32, f64);
+
+impl SaveState for usize {
+    fn save(&self, w: &mut StateWriter) {
+        (*self as u64).save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut value = 0u64;
+        value.load(r)?;
+        *self = value as usize;
+        Ok(())
+    }
+}
+
+impl SaveState for bool {
+    fn save(&self, w: &mut StateWriter) {
+        (*self as u8).save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut value = 0u8;
+        value.load(r)?;
+        *self = value != 0;
+        Ok(())
+    }
+}
+
+impl<T: SaveState, const N: usize> SaveState for [T; N] {
+    fn save(&self, w: &mut StateWriter) {
+        for value in self {
+            value.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        for value in self.iter_mut() {
+            value.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+// 長さはROMの構成で決まるので、読み込み時に一致しなければエラーにする。
+impl<T: SaveState> SaveState for Vec<T> {
+    fn save(&self, w: &mut StateWriter) {
+        self.len().save(w);
+        for value in self {
+            value.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut len = 0usize;
+        len.load(r)?;
+        if len != self.len() {
+            return Err(format!("Save state has {} elements where {} are expected", len, self.len()));
+        }
+        for value in self.iter_mut() {
+            value.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+// 保存した時にSomeで今がNoneの場合はデフォルト値に読み込む。
+impl<T: SaveState + Default> SaveState for Option<T> {
+    fn save(&self, w: &mut StateWriter) {
+        self.is_some().save(w);
+        if let Some(value) = self {
+            value.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut present = false;
+        present.load(r)?;
+        match (present, self.as_mut()) {
+            (true, Some(value)) => value.load(r),
+            (true, None) => {
+                let mut value = T::default();
+                value.load(r)?;
+                *self = Some(value);
+                Ok(())
+            }
+            (false, _) => {
+                *self = None;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<T: SaveState + Copy> SaveState for Cell<T> {
+    fn save(&self, w: &mut StateWriter) {
+        self.get().save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.get_mut().load(r)
+    }
+}
+
+impl<A: SaveState, B: SaveState> SaveState for (A, B) {
+    fn save(&self, w: &mut StateWriter) {
+        self.0.save(w);
+        self.1.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.0.load(r)?;
+        self.1.load(r)
+    }
+}
+
+// 構造体のフィールドを並べた順に保存する。
+//   impl_save_state!(Envelope { start, looping, volume });
+macro_rules! impl_save_state {
+    ($type:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::savestate::SaveState for $type {
+            fn save(&self, w: &mut $crate::savestate::StateWriter) {
+                $( $crate::savestate::SaveState::save(&self.$field, w); )*
+            }
+
+            fn load(&mut self, r: &mut $crate::savestate::StateReader) -> Result<(), String> {
+                $( $crate::savestate::SaveState::load(&mut self.$field, r)?; )*
+                Ok(())
+            }
+        }
+    };
+}
+
+pub(crate) use impl_save_state;
+
+// 版付きの区画: 名前(4バイト), 版(u32), 長さ(u32), 中身。中身の形式は区画の版で決めるので、
+// 後から中身を足しても全体のバージョンを上げずに済み、読む側は知らない版の中身を使わずに済む。
+pub fn write_section(w: &mut StateWriter, tag: &[u8; 4], version: u32, body: &[u8]) {
+    w.write_bytes(tag);
+    version.save(w);
+    (body.len() as u32).save(w);
+    w.write_bytes(body);
+}
+
+// 戻り値は区画の版と中身。名前が違えばエラーにする。
+pub fn read_section<'a>(r: &mut StateReader<'a>, tag: &[u8; 4]) -> Result<(u32, &'a [u8]), String> {
+    if r.read_bytes(tag.len())? != tag {
+        return Err(format!("Save state section {} is missing", String::from_utf8_lossy(tag)));
+    }
+    let mut version = 0u32;
+    version.load(r)?;
+    let mut len = 0u32;
+    len.load(r)?;
+    Ok((version, r.read_bytes(len as usize)?))
+}
+
+pub fn save_state<T: SaveState>(machine: &T, rom_md5: [u8; 16], movie_frame: Option<usize>) -> Vec<u8> {
+    let mut w = StateWriter::new();
+    w.write_bytes(STATE_MAGIC);
+    STATE_VERSION.save(&mut w);
+    rom_md5.save(&mut w);
+    movie_frame.save(&mut w);
+    machine.save(&mut w);
+    w.into_bytes()
+}
+
+// 別のROMや古い形式のステートは何も変えずにエラーを返す。途中で読み込みに失敗した場合は元の状態に戻す。
+// 戻り値は保存した時のムービーのフレーム
+pub fn load_state<T: SaveState>(machine: &mut T, rom_md5: [u8; 16], data: &[u8]) -> Result<Option<usize>, String> {
+    let mut r = StateReader::new(data);
+    if r.read_bytes(STATE_MAGIC.len()).ok() != Some(&STATE_MAGIC[..]) {
+        return Err("Not a save state".to_string());
+    }
+    let mut version = 0u32;
+    version.load(&mut r)?;
+    if version != STATE_VERSION {
+        return Err(format!("Save state version {} is not supported (expected {})", version, STATE_VERSION));
+    }
+    let mut md5 = [0u8; 16];
+    md5.load(&mut r)?;
+    if md5 != rom_md5 {
+        return Err("Save state was made with a different ROM".to_string());
+    }
+    let mut movie_frame: Option<usize> = None;
+    movie_frame.load(&mut r)?;
+
+    let mut backup = StateWriter::new();
+    machine.save(&mut backup);
+    let result = machine.load(&mut r).and_then(|()| {
+        if r.is_empty() {
+            Ok(())
+        } else {
+            Err("Save state has trailing data".to_string())
+        }
+    });
+    if let Err(e) = result {
+        let backup = backup.into_bytes();
+        machine.load(&mut StateReader::new(&backup)).unwrap();
+        return Err(e);
+    }
+    Ok(movie_frame)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Sample {
+        a: u8,
+        b: u16,
+        flag: bool,
+        ram: Vec<u8>,
+        value: Option<i32>,
+    }
+
+    impl_save_state!(Sample { a, b, flag, ram, value });
+
+    fn sample() -> Sample {
+        Sample { a: 1, b: 0x1234, flag: true, ram: vec![1, 2, 3], value: Some(-5) }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = save_state(&sample(), [7; 16], Some(42));
+        let mut loaded = Sample { ram: vec![0; 3], ..Sample::default() };
+        assert_eq!(load_state(&mut loaded, [7; 16], &data), Ok(Some(42)));
+        assert_eq!(loaded, sample());
+    }
+
+    #[test]
+    fn test_rejects_mismatch() {
+        let data = save_state(&sample(), [7; 16], None);
+        let mut loaded = Sample { ram: vec![0; 3], ..Sample::default() };
+        assert!(load_state(&mut loaded, [8; 16], &data).is_err());
+
+        let mut old = data.clone();
+        old[8] = 0;
+        assert!(load_state(&mut loaded, [7; 16], &old).unwrap_err().contains("version"));
+
+        // 途中で失敗したら元の状態に戻す。
+        let mut loaded = Sample { a: 9, ram: vec![0; 4], ..Sample::default() };
+        assert!(load_state(&mut loaded, [7; 16], &data).is_err());
+        assert_eq!(loaded.a, 9);
+        assert!(load_state(&mut loaded, [7; 16], &data[..data.len() - 1]).is_err());
+    }
+
+    // 後の版で中身が増えた区画も、名前が合えば読み飛ばせる。
+    #[test]
+    fn test_section() {
+        let mut w = StateWriter::new();
+        write_section(&mut w, b"VRAM", 1, &[0xAA; 3]);
+        write_section(&mut w, b"OAM ", 0, &[]);
+        let data = w.into_bytes();
+        let mut r = StateReader::new(&data);
+        assert_eq!(read_section(&mut r, b"VRAM"), Ok((1, &[0xAA; 3][..])));
+        assert_eq!(read_section(&mut r, b"OAM "), Ok((0, &[][..])));
+        assert!(r.is_empty());
+        assert!(read_section(&mut StateReader::new(&data), b"PALT").unwrap_err().contains("PALT"));
+    }
+}
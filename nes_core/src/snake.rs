@@ -0,0 +1,61 @@
+use crate::joypad::JoypadButton;
+use crate::{Mem, CPU};
+use rand::Rng;
+
+// snakeのデモの画面。PPUはまだないので、$0200-$05FFの32x32の色番号を画面として扱う。
+pub const SCREEN_WIDTH: usize = 32;
+pub const SCREEN_HEIGHT: usize = 32;
+pub const SCREEN_BYTES: usize = SCREEN_WIDTH * SCREEN_HEIGHT * 3;
+const SCREEN_START: u16 = 0x0200;
+// 乱数と最後に押されたキーを置くアドレス
+const RANDOM_ADDR: u16 = 0x00fe;
+const KEY_ADDR: u16 = 0x00ff;
+
+pub fn color(byte: u8) -> (u8, u8, u8) {
+    match byte {
+        0 => (0, 0, 0),
+        1 => (255, 255, 255),
+        2 | 9 => (128, 128, 128),
+        3 | 10 => (255, 0, 0),
+        4 | 11 => (0, 255, 0),
+        5 | 12 => (0, 0, 255),
+        6 | 13 => (255, 0, 255),
+        7 | 14 => (255, 255, 0),
+        _ => (0, 255, 255),
+    }
+}
+
+// 画面をRGB24で描く。前回から変わっていればtrueを返す。
+pub fn read_screen_state(cpu: &mut CPU, frame: &mut [u8; SCREEN_BYTES]) -> bool {
+    let mut update = false;
+    for (i, pixel) in frame.chunks_exact_mut(3).enumerate() {
+        let (r, g, b) = color(cpu.mem_read(SCREEN_START + i as u16));
+        if pixel != [r, g, b] {
+            pixel.copy_from_slice(&[r, g, b]);
+            update = true;
+        }
+    }
+    update
+}
+
+// snakeは命令ごとに$FEの乱数(1〜15)を読み替える。
+pub fn write_random(cpu: &mut CPU, rng: &mut impl Rng) {
+    cpu.mem_write(RANDOM_ADDR, rng.gen_range(1, 16));
+}
+
+// snakeのデモは$FFから最後に押された方向のASCIIコード(w/s/a/d)を読む。
+pub fn write_snake_key(cpu: &mut CPU) {
+    let buttons = cpu.bus.ports.joypad_mut(0).map_or(JoypadButton::empty(), |joypad| joypad.buttons());
+    let key = if buttons.contains(JoypadButton::UP) {
+        0x77
+    } else if buttons.contains(JoypadButton::DOWN) {
+        0x73
+    } else if buttons.contains(JoypadButton::LEFT) {
+        0x61
+    } else if buttons.contains(JoypadButton::RIGHT) {
+        0x64
+    } else {
+        return;
+    };
+    cpu.mem_write(KEY_ADDR, key);
+}
@@ -0,0 +1,97 @@
+use crate::ports::InputDevice;
+use crate::savestate::impl_save_state;
+
+// スーパーファミコンのマウス(コントローラー端子)。ストローブを下げた時点の32bitをD0から上位ビットから読み出す。
+//   1バイト目: 0
+//   2バイト目: 右ボタン, 左ボタン, 感度(2bit), 識別子0001
+//   3バイト目: 上向きなら1, 縦の移動量(7bit)
+//   4バイト目: 左向きなら1, 横の移動量(7bit)
+// 読み終わった後は1を返す。
+const SIGNATURE: u32 = 0b0001;
+const MAX_MOTION: i32 = 0x7F;
+
+#[derive(Default)]
+pub struct SnesMouse {
+    // 前回読み出してからの移動量(右と下が正)
+    dx: i32,
+    dy: i32,
+    left: bool,
+    right: bool,
+    strobe: bool,
+    shift: u32,
+}
+
+impl_save_state!(SnesMouse { strobe, shift });
+
+fn motion_bits(delta: i32) -> u32 {
+    ((delta < 0) as u32) << 7 | delta.abs().min(MAX_MOTION) as u32
+}
+
+impl SnesMouse {
+    pub fn new() -> Self {
+        SnesMouse::default()
+    }
+
+    pub fn add_motion(&mut self, dx: i32, dy: i32) {
+        self.dx += dx;
+        self.dy += dy;
+    }
+
+    pub fn set_buttons(&mut self, left: bool, right: bool) {
+        self.left = left;
+        self.right = right;
+    }
+
+    fn latch(&mut self) {
+        let buttons = (self.right as u32) << 7 | (self.left as u32) << 6 | SIGNATURE;
+        self.shift = buttons << 16 | motion_bits(self.dy) << 8 | motion_bits(self.dx);
+        self.dx = 0;
+        self.dy = 0;
+    }
+}
+
+impl InputDevice for SnesMouse {
+    fn write(&mut self, data: u8) {
+        let strobe = data & 1 == 1;
+        if self.strobe && !strobe {
+            self.latch();
+        }
+        self.strobe = strobe;
+    }
+
+    fn read(&mut self, _address: u16, _cycles: u64) -> u8 {
+        let bit = (self.shift >> 31) as u8;
+        if !self.strobe {
+            self.shift = self.shift << 1 | 1;
+        }
+        bit
+    }
+
+    fn as_mouse_mut(&mut self) -> Option<&mut SnesMouse> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ports::JOYPAD1;
+
+    #[test]
+    fn test_report() {
+        let mut mouse = SnesMouse::new();
+        mouse.add_motion(-3, 200);
+        mouse.set_buttons(true, false);
+        mouse.write(1);
+        mouse.write(0);
+        let report = (0..32).fold(0u32, |report, _| report << 1 | mouse.read(JOYPAD1, 0) as u32);
+        assert_eq!(report, 0x00_41_7F_83);
+        assert_eq!(mouse.read(JOYPAD1, 0), 1);
+
+        // 移動量は読み出すたびにリセットされる。
+        mouse.write(1);
+        mouse.write(0);
+        let report = (0..32).fold(0u32, |report, _| report << 1 | mouse.read(JOYPAD1, 0) as u32);
+        assert_eq!(report, 0x00_41_00_00);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::bus::Bus;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const BATTERY_EXTENSION: &str = "sav";
+// FDSのディスクへの書き込みは元イメージとの差分(IPS)として保存する。
+pub const DISK_PATCH_EXTENSION: &str = "fds.ips";
+
+// セーブステートのスロット数。スロットnは<rom>.ssnに保存する。
+pub const STATE_SLOTS: usize = 10;
+
+// バッテリーバックアップRAMを<rom>.savとして保存する。
+pub struct SaveFile {
+    path: PathBuf,
+}
+
+impl SaveFile {
+    pub fn new(rom_path: &Path, save_dir: Option<&Path>) -> Self {
+        Self::with_extension(rom_path, save_dir, BATTERY_EXTENSION)
+    }
+
+    pub fn with_extension(rom_path: &Path, save_dir: Option<&Path>, extension: &str) -> Self {
+        let file_name = rom_path.with_extension(extension);
+        let path = match save_dir {
+            Some(dir) => dir.join(file_name.file_name().unwrap_or_default()),
+            None => file_name,
+        };
+        SaveFile { path }
+    }
+
+    pub fn state_slot(rom_path: &Path, save_dir: Option<&Path>, slot: usize) -> Self {
+        Self::with_extension(rom_path, save_dir, &format!("ss{}", slot))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // ファイルが存在しない場合は何もしない。サイズが違う場合は読める分だけ読む。
+    pub fn load(&self, ram: &mut [u8]) -> io::Result<()> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let len = data.len().min(ram.len());
+        ram[..len].copy_from_slice(&data[..len]);
+        Ok(())
+    }
+
+    pub fn read(&self) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn flush(&self, ram: &[u8]) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+        // 書き込み途中で落ちてもセーブが壊れないよう一時ファイル経由でリネームする。
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, ram)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+// ROM1本分のセーブデータ(バッテリーRAMとFDSのディスク)
+pub struct SaveData {
+    battery: SaveFile,
+    disk: SaveFile,
+}
+
+impl SaveData {
+    pub fn new(rom_path: &Path, save_dir: Option<&Path>) -> Self {
+        SaveData {
+            battery: SaveFile::new(rom_path, save_dir),
+            disk: SaveFile::with_extension(rom_path, save_dir, DISK_PATCH_EXTENSION),
+        }
+    }
+
+    pub fn load(&self, bus: &mut Bus) -> Result<(), String> {
+        if let Some(ram) = bus.battery_ram_mut() {
+            self.battery
+                .load(ram)
+                .map_err(|e| format!("Failed to read {}: {}", self.battery.path().display(), e))?;
+        }
+        if let Some(fds) = bus.mapper_mut().as_fds_mut() {
+            let patch = self
+                .disk
+                .read()
+                .map_err(|e| format!("Failed to read {}: {}", self.disk.path().display(), e))?;
+            if let Some(patch) = patch {
+                fds.apply_disk_patch(&patch)
+                    .map_err(|e| format!("{}: {}", self.disk.path().display(), e))?;
+            }
+        }
+        Ok(())
+    }
+
+    // forceがfalseの場合は前回の書き出し以降に変更があったものだけ書き出す。
+    pub fn flush(&self, bus: &mut Bus, force: bool) -> Result<(), String> {
+        if bus.take_prg_ram_dirty() || force {
+            if let Some(ram) = bus.battery_ram() {
+                self.battery
+                    .flush(ram)
+                    .map_err(|e| format!("Failed to write {}: {}", self.battery.path().display(), e))?;
+            }
+        }
+        if let Some(fds) = bus.mapper_mut().as_fds_mut() {
+            if fds.take_disk_modified() {
+                self.disk
+                    .flush(&fds.disk_patch())
+                    .map_err(|e| format!("Failed to write {}: {}", self.disk.path().display(), e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sav_path() {
+        let save = SaveFile::new(Path::new("roms/zelda.nes"), None);
+        assert_eq!(save.path(), Path::new("roms/zelda.sav"));
+
+        let save = SaveFile::new(Path::new("roms/zelda.nes"), Some(Path::new("saves")));
+        assert_eq!(save.path(), Path::new("saves/zelda.sav"));
+    }
+
+    #[test]
+    fn test_flush_and_load() {
+        let dir = std::env::temp_dir().join("nes_emulator_sram_test");
+        let save = SaveFile::new(Path::new("test.nes"), Some(&dir));
+
+        let ram: Vec<u8> = (0..=255).collect();
+        save.flush(&ram).unwrap();
+
+        let mut loaded = vec![0; 256];
+        save.load(&mut loaded).unwrap();
+        assert_eq!(loaded, ram);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+use crate::cartridge::{Mirroring, Rom, TvSystem};
+use crate::romdb::RomHash;
+
+pub const UNIF_TAG: [u8; 4] = [0x55, 0x4E, 0x49, 0x46];
+const HEADER_SIZE: usize = 32;
+const PRG_RAM_SIZE: usize = 8192;
+
+// UNIFのボード名とiNESマッパー番号の対応表。new_mapperが実装しているマッパーのボードだけを載せる。
+// MMC1やMMC3、それらを使う海賊版・マルチカートのボードはマッパーの実装がないので読み込めない。
+const BOARDS: &[(&str, u8)] = &[
+    ("NROM", 0),
+    ("NROM-128", 0),
+    ("NROM-256", 0),
+    ("ELROM", 5),
+    ("EKROM", 5),
+    ("ETROM", 5),
+    ("EWROM", 5),
+    ("BTR", 69),
+    ("JLROM", 69),
+    ("JSROM", 69),
+    ("BMC-GK-192", 58),
+    ("BMC-NovelDiamond9999999in1", 201),
+];
+// NROMのPRG-ROMは16KBか32KB
+const NROM_PRG_SIZES: [usize; 2] = [0x4000, 0x8000];
+
+// "NES-"や"HVC-"などの任天堂純正ボードのプレフィックスは番号の判定に関係ないので外す。
+pub fn board_to_mapper(board: &str) -> Option<u8> {
+    let name = ["NES-", "HVC-", "UNL-NES-"]
+        .iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(board);
+    BOARDS
+        .iter()
+        .find(|(b, _)| b.eq_ignore_ascii_case(name))
+        .map(|&(_, mapper)| mapper)
+}
+
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+pub fn parse(raw: &[u8]) -> Result<Rom, String> {
+    if raw.len() < HEADER_SIZE || raw[0..4] != UNIF_TAG {
+        return Err("File is not in UNIF file format".to_string());
+    }
+
+    let mut board = None;
+    let mut name = None;
+    // PRG0..PRGF, CHR0..CHRFは番号順に連結する。
+    let mut prg_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut chr_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut mirroring = Mirroring::HORIZONTAL;
+    let mut battery = false;
+    let mut tv_system = TvSystem::NTSC;
+
+    let mut pos = HEADER_SIZE;
+    while pos + 8 <= raw.len() {
+        let id = &raw[pos..pos + 4];
+        let len = u32::from_le_bytes([raw[pos + 4], raw[pos + 5], raw[pos + 6], raw[pos + 7]]) as usize;
+        let data = raw
+            .get(pos + 8..pos + 8 + len)
+            .ok_or_else(|| format!("UNIF chunk {} is truncated", String::from_utf8_lossy(id)))?;
+        pos += 8 + len;
+
+        match id {
+            b"MAPR" => board = Some(read_string(data)),
+            b"NAME" => name = Some(read_string(data)),
+            b"MIRR" => {
+                mirroring = match data.first() {
+                    Some(0) => Mirroring::HORIZONTAL,
+                    Some(1) => Mirroring::VERTICAL,
+                    Some(2) => Mirroring::SINGLE_SCREEN_LOWER,
+                    Some(3) => Mirroring::SINGLE_SCREEN_UPPER,
+                    Some(4) => Mirroring::FOUR_SCREEN,
+                    // 5はマッパーが切り替える
+                    _ => Mirroring::HORIZONTAL,
+                }
+            }
+            b"BATR" => battery = true,
+            b"TVCI" => {
+                tv_system = match data.first() {
+                    Some(1) => TvSystem::PAL,
+                    Some(2) => TvSystem::DUAL,
+                    _ => TvSystem::NTSC,
+                }
+            }
+            _ => {
+                let index = match std::str::from_utf8(&id[3..]).ok().and_then(|n| u8::from_str_radix(n, 16).ok()) {
+                    Some(index) => index as usize,
+                    None => continue,
+                };
+                match &id[0..3] {
+                    b"PRG" => prg_chunks[index] = Some(data),
+                    b"CHR" => chr_chunks[index] = Some(data),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let board = board.ok_or("UNIF file has no MAPR chunk")?;
+    let mapper = board_to_mapper(&board).ok_or_else(|| {
+        let supported: Vec<&str> = BOARDS.iter().map(|&(name, _)| name).collect();
+        format!("UNIF board {} is not supported (supported boards: {})", board, supported.join(", "))
+    })?;
+
+    let prg_rom: Vec<u8> = prg_chunks.iter().flatten().flat_map(|c| c.iter().copied()).collect();
+    let chr_rom: Vec<u8> = chr_chunks.iter().flatten().flat_map(|c| c.iter().copied()).collect();
+    if prg_rom.is_empty() {
+        return Err("UNIF file has no PRG chunk".to_string());
+    }
+    if mapper == 0 && !NROM_PRG_SIZES.contains(&prg_rom.len()) {
+        return Err(format!("UNIF board {} needs 16 KB or 32 KB of PRG-ROM (got {} bytes)", board, prg_rom.len()));
+    }
+    let hash = RomHash::new(&prg_rom, &chr_rom);
+
+    let mut rom = Rom {
+        prg_rom,
+        chr_rom,
+        mapper,
+        screen_mirroring: mirroring,
+        battery,
+        prg_ram_size: PRG_RAM_SIZE,
+        tv_system,
+        title: None,
+        hash,
+        disk_sides: Vec::new(),
+        nsf: None,
+    };
+    rom.apply_database();
+    if rom.title.is_none() {
+        rom.title = name.filter(|n| !n.is_empty());
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut result = id.to_vec();
+        result.extend(&(data.len() as u32).to_le_bytes());
+        result.extend(data);
+        result
+    }
+
+    fn create_unif(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut result = UNIF_TAG.to_vec();
+        result.extend(&7u32.to_le_bytes());
+        result.resize(HEADER_SIZE, 0);
+        for c in chunks {
+            result.extend(c);
+        }
+        result
+    }
+
+    #[test]
+    fn test_parse() {
+        let raw = create_unif(&[
+            chunk(b"MAPR", b"NES-NROM-256\0"),
+            chunk(b"NAME", b"Test Game\0"),
+            chunk(b"PRG1", &[2; 0x4000]),
+            chunk(b"PRG0", &[1; 0x4000]),
+            chunk(b"CHR0", &[3; 0x2000]),
+            chunk(b"MIRR", &[1]),
+            chunk(b"BATR", &[1]),
+            chunk(b"TVCI", &[1]),
+        ]);
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+        assert_eq!(rom.prg_rom[0], 1);
+        assert_eq!(rom.prg_rom[0x4000], 2);
+        assert_eq!(rom.chr_rom, vec![3; 0x2000]);
+        assert_eq!(rom.screen_mirroring, Mirroring::VERTICAL);
+        assert!(rom.battery);
+        assert_eq!(rom.tv_system, TvSystem::PAL);
+        assert_eq!(rom.title, Some("Test Game".to_string()));
+    }
+
+    #[test]
+    fn test_unknown_board() {
+        for board in ["UNL-UNKNOWN", "NES-SNROM", "BMC-FK23C"] {
+            let raw = create_unif(&[chunk(b"MAPR", format!("{}\0", board).as_bytes()), chunk(b"PRG0", &[0; 0x4000])]);
+            assert!(parse(&raw).err().unwrap().contains("supported boards: NROM"));
+        }
+    }
+
+    #[test]
+    fn test_nrom_prg_size() {
+        let raw = create_unif(&[chunk(b"MAPR", b"NES-NROM\0"), chunk(b"PRG0", &[0; 0x2000])]);
+        assert!(parse(&raw).err().unwrap().contains("16 KB or 32 KB"));
+    }
+
+    #[test]
+    fn test_board_to_mapper() {
+        assert_eq!(board_to_mapper("NES-NROM-128"), Some(0));
+        assert_eq!(board_to_mapper("HVC-EKROM"), Some(5));
+        assert_eq!(board_to_mapper("NES-JLROM"), Some(69));
+        assert_eq!(board_to_mapper("BMC-NovelDiamond9999999in1"), Some(201));
+        assert_eq!(board_to_mapper("FOO"), None);
+    }
+
+    // 表に載せたボードはすべてマッパーまで読み込める。
+    #[test]
+    fn test_boards_load() {
+        for &(board, _) in BOARDS {
+            let raw = create_unif(&[
+                chunk(b"MAPR", format!("NES-{}\0", board).as_bytes()),
+                chunk(b"PRG0", &[0xEA; 0x8000]),
+                chunk(b"CHR0", &[0; 0x2000]),
+            ]);
+            let rom = Rom::new(&raw).unwrap();
+            assert!(Bus::new(rom).is_ok(), "{} does not load", board);
+        }
+    }
+}
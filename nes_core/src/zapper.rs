@@ -0,0 +1,215 @@
+// 光線銃(ザッパー)。2コンの端子につなぎ、$4017のbit3が受光(0で光を検出)、bit4がトリガー。
+// 受光はビームが照準の位置を描いてから数走査線の間だけ反応するので、
+// 完成したフレームではなく読み込んだ時点のビームの位置で判定する。
+// PPUができるまで保留: 受光はフロントエンドが渡すsnakeの32x32の画面と、CPUのサイクル数から
+// 求めたビームの位置で判定している。実際の光線銃のゲームはこの画面では当たりを検出できないので、
+// DeviceKind::parseでは選べず、フロントエンドの--experimental-zapperの時だけつなぐ。
+
+use crate::ports::InputDevice;
+use crate::savestate::{SaveState, StateReader, StateWriter};
+
+// NES画面の大きさ(照準の座標はこの範囲)
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+// NTSC: 1走査線341ドット、1フレーム262走査線、CPU 1サイクルで3ドット
+const SCANLINE_DOTS: u64 = 341;
+const FRAME_SCANLINES: u64 = 262;
+// 明るい画素を描いてからフォトダイオードが反応し続ける走査線数
+const LIGHT_SCANLINES: usize = 25;
+// 照準の周りで明るさを調べる範囲(NES画面のドット)
+const LIGHT_RADIUS: usize = 2;
+// R+G+Bがこれ以上なら明るいとみなす。
+const BRIGHTNESS_THRESHOLD: u32 = 0x180;
+
+// 光線銃をつないだ時に表示する注意
+pub const EXPERIMENTAL_NOTICE: &str =
+    "Zapper support is blocked until there is a PPU: this experimental version senses light from the 32x32 debug screen, so real light gun games cannot detect hits";
+
+const LIGHT_NOT_SENSED: u8 = 0b0000_1000;
+const TRIGGER_PULLED: u8 = 0b0001_0000;
+
+// CPUサイクル数からビームの位置(走査線, ドット)を求める。PPUがないのでフレームの先頭を0走査線とする。
+pub fn beam_position(cycles: u64) -> (usize, usize) {
+    let dot = (cycles * 3) % (SCANLINE_DOTS * FRAME_SCANLINES);
+    ((dot / SCANLINE_DOTS) as usize, (dot % SCANLINE_DOTS) as usize)
+}
+
+#[derive(Default)]
+pub struct Zapper {
+    trigger: bool,
+    // NES画面上の照準。画面の外を向けている時はNone
+    aim: Option<(usize, usize)>,
+    // 最後に描画したフレーム(RGB24)。解像度はNES画面と違ってもよい。
+    frame: Vec<u8>,
+    frame_width: usize,
+    frame_height: usize,
+}
+
+// 照準とトリガーはホストの入力、フレームはフロントエンドの描画なので保存しない。
+impl SaveState for Zapper {
+    fn save(&self, _w: &mut StateWriter) {}
+
+    fn load(&mut self, _r: &mut StateReader) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper::default()
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    pub fn set_aim(&mut self, aim: Option<(usize, usize)>) {
+        self.aim = aim.filter(|&(x, y)| x < SCREEN_WIDTH && y < SCREEN_HEIGHT);
+    }
+
+    pub fn set_frame(&mut self, frame: &[u8], width: usize, height: usize) {
+        self.frame.clear();
+        self.frame.extend_from_slice(frame);
+        self.frame_width = width;
+        self.frame_height = height;
+    }
+
+    pub fn read(&self, cycles: u64) -> u8 {
+        let (scanline, dot) = beam_position(cycles);
+        let mut data = 0;
+        if !self.light_sensed(scanline, dot) {
+            data |= LIGHT_NOT_SENSED;
+        }
+        if self.trigger {
+            data |= TRIGGER_PULLED;
+        }
+        data
+    }
+
+    fn light_sensed(&self, scanline: usize, dot: usize) -> bool {
+        let (x, y) = match self.aim {
+            Some(aim) => aim,
+            None => return false,
+        };
+        // ビームがまだ照準の位置を描いていないか、描いてから時間がたちすぎている。
+        if scanline < y || (scanline == y && dot < x) || scanline - y >= LIGHT_SCANLINES {
+            return false;
+        }
+        let xs = x.saturating_sub(LIGHT_RADIUS)..=(x + LIGHT_RADIUS).min(SCREEN_WIDTH - 1);
+        xs.flat_map(|x| {
+            let ys = y.saturating_sub(LIGHT_RADIUS)..=(y + LIGHT_RADIUS).min(SCREEN_HEIGHT - 1);
+            ys.map(move |y| (x, y))
+        })
+        .any(|(x, y)| self.brightness(x, y) >= BRIGHTNESS_THRESHOLD)
+    }
+
+    // NES画面の座標にあたるフレームの画素の明るさ
+    fn brightness(&self, x: usize, y: usize) -> u32 {
+        let fx = x * self.frame_width / SCREEN_WIDTH;
+        let fy = y * self.frame_height / SCREEN_HEIGHT;
+        let index = (fy * self.frame_width + fx) * 3;
+        self.frame.get(index..index + 3).map_or(0, |rgb| rgb.iter().map(|&c| c as u32).sum())
+    }
+}
+
+impl InputDevice for Zapper {
+    fn write(&mut self, _data: u8) {}
+
+    fn read(&mut self, _address: u16, cycles: u64) -> u8 {
+        Zapper::read(self, cycles)
+    }
+
+    fn as_zapper_mut(&mut self) -> Option<&mut Zapper> {
+        Some(self)
+    }
+}
+
+// ヘッドレス実行用のスクリプト。1行に1つ、指定したフレームからの照準とトリガーを書く。
+//   # コメント
+//   120 128 100 fire   (120フレーム目から(128, 100)を狙って引き金を引く)
+//   122 128 100        (引き金を離す)
+//   200 off fire       (画面の外に向けて撃つ)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZapperStep {
+    pub frame: u32,
+    pub aim: Option<(usize, usize)>,
+    pub trigger: bool,
+}
+
+pub fn parse_script(text: &str) -> Result<Vec<ZapperStep>, String> {
+    let mut steps = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = || format!("Zapper script line {}: expected <frame> <x> <y>|off [fire]", number + 1);
+        let mut fields = line.split_whitespace();
+        let frame = fields.next().and_then(|f| f.parse().ok()).ok_or_else(error)?;
+        let aim = match fields.next() {
+            Some("off") => None,
+            Some(x) => {
+                let x = x.parse().ok().filter(|&x| x < SCREEN_WIDTH).ok_or_else(error)?;
+                let y = fields.next().and_then(|y| y.parse().ok()).filter(|&y| y < SCREEN_HEIGHT).ok_or_else(error)?;
+                Some((x, y))
+            }
+            None => return Err(error()),
+        };
+        let trigger = match fields.next() {
+            Some("fire") => true,
+            None => false,
+            Some(_) => return Err(error()),
+        };
+        if fields.next().is_some() {
+            return Err(error());
+        }
+        steps.push(ZapperStep { frame, aim, trigger });
+    }
+    steps.sort_by_key(|step| step.frame);
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // (scanline, dot)にビームがある時のCPUサイクル数
+    fn cycles_at(scanline: u64, dot: u64) -> u64 {
+        (scanline * SCANLINE_DOTS + dot) / 3
+    }
+
+    #[test]
+    fn test_light_follows_beam() {
+        let mut zapper = Zapper::new();
+        // 上半分が白、下半分が黒の2x2のフレーム
+        zapper.set_frame(&[255, 255, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0], 2, 2);
+        zapper.set_aim(Some((100, 50)));
+
+        assert_eq!(zapper.read(cycles_at(40, 0)) & LIGHT_NOT_SENSED, LIGHT_NOT_SENSED);
+        assert_eq!(zapper.read(cycles_at(51, 0)) & LIGHT_NOT_SENSED, 0);
+        assert_eq!(zapper.read(cycles_at(80, 0)) & LIGHT_NOT_SENSED, LIGHT_NOT_SENSED);
+
+        // 暗い所を狙うとビームが通っても反応しない。
+        zapper.set_aim(Some((100, 180)));
+        assert_eq!(zapper.read(cycles_at(181, 0)) & LIGHT_NOT_SENSED, LIGHT_NOT_SENSED);
+
+        zapper.set_aim(None);
+        zapper.set_trigger(true);
+        assert_eq!(zapper.read(cycles_at(51, 0)), LIGHT_NOT_SENSED | TRIGGER_PULLED);
+    }
+
+    #[test]
+    fn test_parse_script() {
+        let steps = parse_script("# test\n200 off fire\n120 128 100 fire\n").unwrap();
+        assert_eq!(
+            steps,
+            [
+                ZapperStep { frame: 120, aim: Some((128, 100)), trigger: true },
+                ZapperStep { frame: 200, aim: None, trigger: true },
+            ]
+        );
+        assert!(parse_script("10 300 10").is_err());
+        assert!(parse_script("10 1 2 shoot").is_err());
+        assert!(parse_script("10").is_err());
+    }
+}
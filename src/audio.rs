@@ -0,0 +1,143 @@
+use nes_core::apu::Apu;
+use nes_core::NTSC_FRAME_RATE;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+use std::time::{Duration, Instant};
+
+// オーディオキューに溜めておく目標の長さ(秒)
+const TARGET_LATENCY: f64 = 0.06;
+// 動的レート制御で速度を変える最大の割合。0.5%程度なら音程の変化は聞き取れない。
+const MAX_RATE_DELTA: f64 = 0.005;
+// 大きく遅れた時(ウィンドウのドラッグ等)は追いつこうとせずに基準時刻を取り直す。
+const MAX_FRAME_LAG: Duration = Duration::from_millis(100);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SyncMode {
+    // オーディオキューの残量に合わせてエミュレーション速度を微調整する。
+    Audio,
+    // ディスプレイの垂直同期に合わせ、リサンプリングのレートを微調整する。
+    Video,
+}
+
+impl SyncMode {
+    pub fn parse(name: &str) -> Result<SyncMode, String> {
+        match name {
+            "audio" => Ok(SyncMode::Audio),
+            "video" => Ok(SyncMode::Video),
+            _ => Err(format!("Unknown sync mode: {} (audio or video)", name)),
+        }
+    }
+}
+
+// キューの残量が目標より多ければ1より大きく、少なければ1より小さい値を返す。
+pub fn rate_control(queued: usize, sample_rate: u32) -> f64 {
+    let target = sample_rate as f64 * TARGET_LATENCY;
+    let error = ((queued as f64 - target) / target).clamp(-1.0, 1.0);
+    1.0 + error * MAX_RATE_DELTA
+}
+
+pub struct AudioOutput {
+    queue: AudioQueue<f32>,
+    sample_rate: u32,
+    buffer: Vec<f32>,
+}
+
+impl AudioOutput {
+    pub fn new(audio: &AudioSubsystem, sample_rate: u32) -> Result<Self, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(sample_rate as i32),
+            channels: Some(1),
+            samples: Some(1024),
+        };
+        let queue = audio.open_queue::<f32, _>(None, &desired)?;
+        let sample_rate = queue.spec().freq as u32;
+        queue.resume();
+        Ok(AudioOutput {
+            queue,
+            sample_rate,
+            buffer: Vec::new(),
+        })
+    }
+
+    // デバイス側で決まった実際のサンプルレート
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // キューに残っているサンプル数
+    pub fn queued(&self) -> usize {
+        self.queue.size() as usize / std::mem::size_of::<f32>()
+    }
+
+    // APUのリングバッファに溜まったサンプルを全てキューに送る。
+    pub fn push(&mut self, apu: &mut Apu) {
+        self.push_samples(apu, false);
+    }
+
+    // 巻き戻し中は溜まったサンプルを逆順に送る。
+    pub fn push_reversed(&mut self, apu: &mut Apu) {
+        self.push_samples(apu, true);
+    }
+
+    fn push_samples(&mut self, apu: &mut Apu, reversed: bool) {
+        let ring = apu.samples();
+        self.buffer.resize(ring.len(), 0.0);
+        let count = ring.read(&mut self.buffer);
+        if reversed {
+            self.buffer[..count].reverse();
+        }
+        if !self.queue.queue(&self.buffer[..count]) {
+            eprintln!("Failed to queue audio: {}", sdl2::get_error());
+        }
+    }
+
+    pub fn rate_control(&self) -> f64 {
+        rate_control(self.queued(), self.sample_rate)
+    }
+}
+
+// 1フレームごとに呼び出し、次のフレームの時刻まで待つ。
+pub struct FrameTimer {
+    next_frame: Instant,
+}
+
+impl FrameTimer {
+    pub fn new() -> Self {
+        FrameTimer {
+            next_frame: Instant::now(),
+        }
+    }
+
+    // rateが1より大きいとフレームの間隔が伸びる。(エミュレーションが遅くなる)
+    pub fn wait(&mut self, rate: f64) {
+        let now = Instant::now();
+        if self.next_frame > now {
+            std::thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > MAX_FRAME_LAG {
+            self.next_frame = now;
+        }
+        self.next_frame += Duration::from_secs_f64(rate / NTSC_FRAME_RATE);
+    }
+}
+
+impl Default for FrameTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_control() {
+        // 44100 * 0.06 = 2646
+        assert_eq!(rate_control(2646, 44_100), 1.0);
+        assert!(rate_control(3000, 44_100) > 1.0);
+        assert!(rate_control(1000, 44_100) < 1.0);
+        // 変化は最大0.5%に抑える。
+        assert_eq!(rate_control(100_000, 44_100), 1.005);
+        assert_eq!(rate_control(0, 44_100), 0.995);
+    }
+}
@@ -0,0 +1,617 @@
+use nes_core::autofire::{Turbo, DEFAULT_TURBO_RATE};
+use nes_core::bus::Bus;
+use nes_core::family_keyboard::KEY_MATRIX;
+use nes_core::input_macro::{self, InputMacro};
+use nes_core::joypad::JoypadButton;
+use nes_core::ports::FourPlayerMode;
+use nes_core::power_pad::POWER_PAD_BUTTONS;
+use nes_core::zapper;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::{KeyboardState, Keycode, Scancode};
+use sdl2::{EventPump, GameControllerSubsystem};
+use std::path::Path;
+
+pub const PLAYERS: usize = 4;
+const DEFAULT_DEADZONE: i16 = 8000;
+// フロントエンドが固定で使うキー(handle_user_inputと巻き戻し)。コントローラーには割り当てられない。
+pub const HOTKEYS: [&str; 13] =
+    ["Escape", "F1", "F2", "F3", "F4", "F5", "F6", "F9", "F10", "F11", "PageUp", "PageDown", "Backspace"];
+// Power Pad / ファミリートレーナーのボタン1から12に割り当てるキー(マットと同じ並び)。
+// デフォルトのコントローラーの割り当てとは重ならない。
+const POWER_PAD_KEYS: [&str; POWER_PAD_BUTTONS] = ["1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F"];
+
+// 設定ファイルがない時の割り当て。ゲームコントローラーは接続順に1P, 2Pになる。
+const DEFAULT_CONFIG: &str = "
+p1.up = key:Up
+p1.down = key:Down
+p1.left = key:Left
+p1.right = key:Right
+p1.a = key:X
+p1.b = key:Z
+p1.select = key:Right Shift
+p1.start = key:Return
+p1.turbo_a = key:C
+p1.turbo_b = key:V
+p1.macro_record = key:F7
+p1.macro_play = key:F8
+p2.up = key:I
+p2.down = key:K
+p2.left = key:J
+p2.right = key:L
+p2.a = key:M
+p2.b = key:N
+p2.select = key:Y
+p2.start = key:U
+p3.up = key:Keypad 8
+p3.down = key:Keypad 2
+p3.left = key:Keypad 4
+p3.right = key:Keypad 6
+p3.a = key:Keypad 9
+p3.b = key:Keypad 7
+p3.select = key:Keypad 1
+p3.start = key:Keypad 3
+";
+
+const DEFAULT_PAD_CONFIG: &str = "
+up = button:dpup
+up = axis:lefty-
+down = button:dpdown
+down = axis:lefty+
+left = button:dpleft
+left = axis:leftx-
+right = button:dpright
+right = axis:leftx+
+a = button:a
+b = button:x
+select = button:back
+start = button:start
+turbo_a = button:b
+turbo_b = button:y
+";
+
+// 設定ファイルでの入力の指定。名前はSDLのキー名、ボタン名、軸名。
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Key(String),
+    PadButton(String),
+    // 軸と向き(trueなら正の方向)
+    PadAxis(String, bool),
+}
+
+// 入力に割り当てる操作
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Button(JoypadButton),
+    Turbo(JoypadButton),
+    MacroRecord,
+    MacroPlay,
+}
+
+impl Action {
+    fn from_name(name: &str) -> Option<Action> {
+        match name {
+            "macro_record" => Some(Action::MacroRecord),
+            "macro_play" => Some(Action::MacroPlay),
+            _ => match name.strip_prefix("turbo_") {
+                Some(button) => JoypadButton::from_name(button).map(Action::Turbo),
+                None => JoypadButton::from_name(name).map(Action::Button),
+            },
+        }
+    }
+
+    // 設定ファイルでの名前(エラーメッセージ用)
+    fn name(&self) -> String {
+        let button_name = |button: JoypadButton| {
+            ["a", "b", "select", "start", "up", "down", "left", "right"]
+                .into_iter()
+                .find(|&name| JoypadButton::from_name(name) == Some(button))
+                .unwrap_or("?")
+        };
+        match self {
+            Action::Button(button) => button_name(*button).to_string(),
+            Action::Turbo(button) => format!("turbo_{}", button_name(*button)),
+            Action::MacroRecord => "macro_record".to_string(),
+            Action::MacroPlay => "macro_play".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub player: usize,
+    pub action: Action,
+    pub source: Source,
+}
+
+// 入力設定ファイルの書式:
+//   # コメント
+//   deadzone = 8000
+//   p1.a = key:X
+//   p1.a = button:a       (1つのボタンに複数の入力を割り当てられる)
+//   p1.up = axis:lefty-
+//   p2.select = none      (割り当てを外す)
+//   turbo_rate = 2        (連射の間隔。rateフレーム押してrateフレーム離す)
+//   p1.turbo_a = key:C
+//   p1.macro_play = key:F8
+//   p1.macro = right:10 right+a:5 none:3   (あらかじめ用意するマクロ)
+//   four_player = fourscore   (3P, 4Pのつなぎ方。off, fourscore(NES), famicom(拡張端子))
+// ファイルに書かれたボタンはデフォルトの割り当てを置き換える。ホットキー(HOTKEYS)や、
+// 別のボタンにも割り当たっているキーを割り当てるとエラーになる。
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputConfig {
+    pub bindings: Vec<Binding>,
+    pub deadzone: i16,
+    pub turbo_rate: u32,
+    pub macros: [Vec<JoypadButton>; PLAYERS],
+    pub four_player: FourPlayerMode,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        let mut text = DEFAULT_CONFIG.to_string();
+        for player in 1..=PLAYERS {
+            for line in DEFAULT_PAD_CONFIG.lines().filter(|l| !l.is_empty()) {
+                text += &format!("p{}.{}\n", player, line);
+            }
+        }
+        let mut config = InputConfig {
+            bindings: Vec::new(),
+            deadzone: DEFAULT_DEADZONE,
+            turbo_rate: DEFAULT_TURBO_RATE,
+            macros: Default::default(),
+            four_player: FourPlayerMode::Off,
+        };
+        config.apply(&text).unwrap();
+        config
+    }
+}
+
+impl InputConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read input config {}: {}", path.display(), e))?;
+        InputConfig::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = InputConfig::default();
+        config.apply(text)?;
+        Ok(config)
+    }
+
+    fn apply(&mut self, text: &str) -> Result<(), String> {
+        // ファイル内で最初に出てきたボタンの割り当てだけを消す。
+        let mut replaced = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: &str| format!("Input config line {}: {}", number + 1, message);
+            let (name, value) = line.split_once('=').ok_or_else(|| error("expected <name> = <value>"))?;
+            let (name, value) = (name.trim(), value.trim());
+
+            if name == "deadzone" {
+                self.deadzone = value.parse().map_err(|_| error("invalid deadzone"))?;
+                continue;
+            }
+            if name == "turbo_rate" {
+                self.turbo_rate = value.parse().ok().filter(|&r| r > 0).ok_or_else(|| error("invalid turbo rate"))?;
+                continue;
+            }
+            if name == "four_player" {
+                self.four_player = FourPlayerMode::parse(value).map_err(|e| error(&e))?;
+                continue;
+            }
+
+            let (player, action) = name.split_once('.').ok_or_else(|| error("expected p<n>.<button>"))?;
+            let player = player
+                .strip_prefix('p')
+                .and_then(|n| n.parse::<usize>().ok())
+                .filter(|n| (1..=PLAYERS).contains(n))
+                .ok_or_else(|| error("unknown player"))?
+                - 1;
+            if action == "macro" {
+                self.macros[player] = input_macro::parse_sequence(value).map_err(|e| error(&e))?;
+                continue;
+            }
+            let action = Action::from_name(action).ok_or_else(|| error("unknown button"))?;
+            if !replaced.contains(&(player, action)) {
+                replaced.push((player, action));
+                self.bindings.retain(|b| b.player != player || b.action != action);
+            }
+            if value == "none" {
+                continue;
+            }
+
+            let source = match value.split_once(':') {
+                Some(("key", key)) => Source::Key(key.to_string()),
+                Some(("button", pad_button)) => Source::PadButton(pad_button.to_string()),
+                Some(("axis", axis)) if axis.ends_with('+') => Source::PadAxis(axis[..axis.len() - 1].to_string(), true),
+                Some(("axis", axis)) if axis.ends_with('-') => Source::PadAxis(axis[..axis.len() - 1].to_string(), false),
+                _ => return Err(error("expected key:<name>, button:<name> or axis:<name>+/-")),
+            };
+            self.bindings.push(Binding { player, action, source });
+        }
+        self.check_keys()
+    }
+
+    // キーの名前はSDLと同じく大文字小文字を区別しない。
+    fn check_keys(&self) -> Result<(), String> {
+        let keys: Vec<(&Binding, &str)> = self
+            .bindings
+            .iter()
+            .filter_map(|b| match &b.source {
+                Source::Key(key) => Some((b, key.as_str())),
+                _ => None,
+            })
+            .collect();
+        for (i, &(binding, key)) in keys.iter().enumerate() {
+            if HOTKEYS.iter().any(|hotkey| hotkey.eq_ignore_ascii_case(key)) {
+                return Err(format!(
+                    "Input config: key {} is a hotkey and can't be bound to p{}.{}",
+                    key,
+                    binding.player + 1,
+                    binding.action.name()
+                ));
+            }
+            let other = keys[..i]
+                .iter()
+                .find(|(other, other_key)| other_key.eq_ignore_ascii_case(key) && (other.player, other.action) != (binding.player, binding.action));
+            if let Some((other, _)) = other {
+                return Err(format!(
+                    "Input config: key {} is bound to both p{}.{} and p{}.{}",
+                    key,
+                    other.player + 1,
+                    other.action.name(),
+                    binding.player + 1,
+                    binding.action.name()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn key_pressed(keyboard: &KeyboardState, name: &str) -> bool {
+    Keycode::from_name(name)
+        .and_then(Scancode::from_keycode)
+        .is_some_and(|scancode| keyboard.is_scancode_pressed(scancode))
+}
+
+// アナログスティックの値がデッドゾーンを超えてその向きに倒れているか
+pub fn axis_pressed(value: i16, positive: bool, deadzone: i16) -> bool {
+    if positive {
+        value > deadzone
+    } else {
+        (value as i32) < -(deadzone as i32)
+    }
+}
+
+// 1フレームの間に押されている入力
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HeldInput {
+    pub buttons: JoypadButton,
+    pub turbo: JoypadButton,
+    pub macro_record: bool,
+    pub macro_play: bool,
+}
+
+impl HeldInput {
+    fn press(&mut self, action: Action) {
+        match action {
+            Action::Button(button) => self.buttons |= button,
+            Action::Turbo(button) => self.turbo |= button,
+            Action::MacroRecord => self.macro_record = true,
+            Action::MacroPlay => self.macro_play = true,
+        }
+    }
+}
+
+// 押されている入力に連射とマクロを適用して、そのフレームのコントローラーの状態を決める。
+// ムービーにはここで決まった状態を記録するので、連射やマクロも通常の入力と同じように再現される。
+pub struct PlayerInput {
+    turbo: Turbo,
+    input_macro: InputMacro,
+    last: HeldInput,
+}
+
+impl PlayerInput {
+    pub fn new(turbo_rate: u32, frames: Vec<JoypadButton>) -> Self {
+        PlayerInput {
+            turbo: Turbo::new(turbo_rate),
+            input_macro: InputMacro::new(frames),
+            last: HeldInput::default(),
+        }
+    }
+
+    // 1フレームに1回呼ぶ。マクロのホットキーは押した瞬間だけ反応する。
+    pub fn next_frame(&mut self, held: HeldInput) -> JoypadButton {
+        if held.macro_record && !self.last.macro_record {
+            self.input_macro.toggle_recording();
+            if self.input_macro.is_recording() {
+                println!("Recording macro");
+            } else {
+                println!("Recorded macro ({} frames)", self.input_macro.len());
+            }
+        }
+        if held.macro_play && !self.last.macro_play {
+            self.input_macro.play();
+        }
+        self.last = held;
+
+        let buttons = held.buttons | self.turbo.next_frame(held.turbo);
+        self.input_macro.next_frame(buttons)
+    }
+}
+
+// SDLのキーボードとゲームコントローラーの状態からコントローラーのボタンを決める。
+pub struct Input {
+    subsystem: Option<GameControllerSubsystem>,
+    keys: Vec<(usize, Action, Scancode)>,
+    buttons: Vec<(usize, Action, Button)>,
+    axes: Vec<(usize, Action, Axis, bool)>,
+    deadzone: i16,
+    players: Vec<PlayerInput>,
+    // プレイヤーごとのゲームコントローラー。接続された順に空いているプレイヤーへ割り当てる。
+    controllers: [Option<GameController>; PLAYERS],
+}
+
+impl Input {
+    pub fn new(config: &InputConfig, subsystem: Option<GameControllerSubsystem>) -> Result<Self, String> {
+        let mut input = Input {
+            subsystem,
+            keys: Vec::new(),
+            buttons: Vec::new(),
+            axes: Vec::new(),
+            deadzone: config.deadzone,
+            players: config.macros.iter().map(|frames| PlayerInput::new(config.turbo_rate, frames.clone())).collect(),
+            controllers: Default::default(),
+        };
+        for binding in &config.bindings {
+            let (player, action) = (binding.player, binding.action);
+            match &binding.source {
+                Source::Key(name) => {
+                    let scancode = Keycode::from_name(name)
+                        .and_then(Scancode::from_keycode)
+                        .ok_or_else(|| format!("Unknown key: {}", name))?;
+                    input.keys.push((player, action, scancode));
+                }
+                Source::PadButton(name) => {
+                    let pad_button = Button::from_string(name).ok_or_else(|| format!("Unknown controller button: {}", name))?;
+                    input.buttons.push((player, action, pad_button));
+                }
+                Source::PadAxis(name, positive) => {
+                    let axis = Axis::from_string(name).ok_or_else(|| format!("Unknown controller axis: {}", name))?;
+                    input.axes.push((player, action, axis, *positive));
+                }
+            }
+        }
+        Ok(input)
+    }
+
+    // ゲームコントローラーの抜き差し。起動時に接続済みのものもAddedイベントで届く。
+    pub fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                let subsystem = match &self.subsystem {
+                    Some(subsystem) => subsystem,
+                    None => return,
+                };
+                let slot = match self.controllers.iter().position(|c| c.is_none()) {
+                    Some(slot) => slot,
+                    None => return,
+                };
+                match subsystem.open(*which) {
+                    Ok(controller) => {
+                        println!("Controller {} connected as player {}", controller.name(), slot + 1);
+                        self.controllers[slot] = Some(controller);
+                    }
+                    Err(e) => eprintln!("Failed to open controller: {}", e),
+                }
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                for (slot, controller) in self.controllers.iter_mut().enumerate() {
+                    if controller.as_ref().is_some_and(|c| c.instance_id() == *which) {
+                        println!("Player {} controller disconnected", slot + 1);
+                        *controller = None;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn held(&self, event_pump: &EventPump, player: usize) -> HeldInput {
+        let keyboard = event_pump.keyboard_state();
+        let mut held = HeldInput::default();
+        for &(_, action, scancode) in self.keys.iter().filter(|k| k.0 == player) {
+            if keyboard.is_scancode_pressed(scancode) {
+                held.press(action);
+            }
+        }
+        if let Some(controller) = &self.controllers[player] {
+            for &(_, action, pad_button) in self.buttons.iter().filter(|b| b.0 == player) {
+                if controller.button(pad_button) {
+                    held.press(action);
+                }
+            }
+            for &(_, action, axis, positive) in self.axes.iter().filter(|a| a.0 == player) {
+                if axis_pressed(controller.axis(axis), positive, self.deadzone) {
+                    held.press(action);
+                }
+            }
+        }
+        held
+    }
+
+    // 1フレームに1回呼ぶ。
+    pub fn update(&mut self, event_pump: &EventPump, bus: &mut Bus) {
+        let buttons: Vec<JoypadButton> = (0..PLAYERS)
+            .map(|player| {
+                let held = self.held(event_pump, player);
+                self.players[player].next_frame(held)
+            })
+            .collect();
+        for (player, buttons) in buttons.into_iter().enumerate() {
+            if let Some(joypad) = bus.ports.joypad_mut(player) {
+                joypad.set_buttons(buttons);
+            }
+        }
+    }
+
+    // コントローラー以外の機器をマウスとキーボードで操作する。1フレームに1回呼ぶ。
+    //   光線銃: 左クリックで撃ち、右クリックで画面の外に向けて撃つ。
+    //   パドル: マウスの横位置がボリューム、左クリックがボタン
+    //   マウス: 移動量とボタンをそのまま伝える。
+    //   Power Pad: POWER_PAD_KEYSのキー
+    //   キーボード: family_keyboard::KEY_MATRIXのキー
+    pub fn update_devices(&self, event_pump: &EventPump, bus: &mut Bus, window_size: (u32, u32)) {
+        let mouse = event_pump.mouse_state();
+        let motion = event_pump.relative_mouse_state();
+        let keyboard = event_pump.keyboard_state();
+        let (width, height) = (window_size.0.max(1) as i32, window_size.1.max(1) as i32);
+        let inside = (0..width).contains(&mouse.x()) && (0..height).contains(&mouse.y());
+        for device in bus.ports.devices_mut() {
+            if let Some(zapper) = device.as_zapper_mut() {
+                let aim = Some((
+                    (mouse.x() * zapper::SCREEN_WIDTH as i32 / width) as usize,
+                    (mouse.y() * zapper::SCREEN_HEIGHT as i32 / height) as usize,
+                ))
+                .filter(|_| inside && !mouse.right());
+                zapper.set_aim(aim);
+                zapper.set_trigger(mouse.left() || mouse.right());
+            }
+            if let Some(paddle) = device.as_paddle_mut() {
+                paddle.set_position((mouse.x().clamp(0, width - 1) * 256 / width) as u8);
+                paddle.set_button(mouse.left());
+            }
+            if let Some(snes_mouse) = device.as_mouse_mut() {
+                snes_mouse.add_motion(motion.x(), motion.y());
+                snes_mouse.set_buttons(motion.left(), motion.right());
+            }
+            if let Some(pad) = device.as_power_pad_mut() {
+                for (i, name) in POWER_PAD_KEYS.iter().enumerate() {
+                    pad.set_button(i + 1, key_pressed(&keyboard, name));
+                }
+            }
+            if let Some(family_keyboard) = device.as_keyboard_mut() {
+                for name in KEY_MATRIX.iter().flatten() {
+                    family_keyboard.set_key(name, key_pressed(&keyboard, name));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = InputConfig::parse(
+            "# コメント
+            deadzone = 12000
+            p1.a = key:Space
+            p1.a = button:y
+            p2.up = axis:righty-
+            p2.b = none
+            ",
+        )
+        .unwrap();
+        assert_eq!(config.deadzone, 12000);
+
+        let sources = |player, button| -> Vec<Source> {
+            config
+                .bindings
+                .iter()
+                .filter(|b| b.player == player && b.action == Action::Button(button))
+                .map(|b| b.source.clone())
+                .collect()
+        };
+        assert_eq!(
+            sources(0, JoypadButton::A),
+            [Source::Key("Space".to_string()), Source::PadButton("y".to_string())]
+        );
+        assert_eq!(sources(1, JoypadButton::UP), [Source::PadAxis("righty".to_string(), false)]);
+        assert!(sources(1, JoypadButton::B).is_empty());
+        // ファイルに書かれていないボタンはデフォルトのまま
+        assert!(sources(0, JoypadButton::START).contains(&Source::Key("Return".to_string())));
+
+        assert!(InputConfig::parse("p5.a = key:A").is_err());
+        assert!(InputConfig::parse("p1.turbo = key:A").is_err());
+        assert!(InputConfig::parse("p1.a = X").is_err());
+    }
+
+    #[test]
+    fn test_key_conflicts() {
+        // デフォルトの割り当てはホットキーともPower Padとも重ならない。
+        let config = InputConfig::default();
+        for binding in &config.bindings {
+            if let Source::Key(key) = &binding.source {
+                assert!(!POWER_PAD_KEYS.contains(&key.as_str()), "{}", key);
+            }
+        }
+        let error = InputConfig::parse("p1.a = key:f1").unwrap_err();
+        assert!(error.contains("hotkey") && error.contains("p1.a"), "{}", error);
+        let error = InputConfig::parse("p2.start = key:x").unwrap_err();
+        assert!(error.contains("p1.a and p2.start"), "{}", error);
+        // 元の割り当てを書き換えていれば重ならない。
+        assert!(InputConfig::parse("p2.start = key:X\np1.a = key:Space").is_ok());
+        // 同じボタンに同じキーを2回書くのは構わない。
+        assert!(InputConfig::parse("p1.a = key:Space\np1.a = key:space").is_ok());
+    }
+
+    #[test]
+    fn test_parse_turbo_and_macro() {
+        let config = InputConfig::parse("turbo_rate = 3\np2.turbo_b = key:B\np2.macro = a:2 none").unwrap();
+        assert_eq!(config.turbo_rate, 3);
+        assert!(config.bindings.contains(&Binding {
+            player: 1,
+            action: Action::Turbo(JoypadButton::B),
+            source: Source::Key("B".to_string()),
+        }));
+        assert_eq!(config.macros[1], [JoypadButton::A, JoypadButton::A, JoypadButton::empty()]);
+        assert!(config.macros[0].is_empty());
+        assert!(InputConfig::parse("turbo_rate = 0").is_err());
+        assert!(InputConfig::parse("p1.macro = c:1").is_err());
+    }
+
+    #[test]
+    fn test_parse_four_player() {
+        let config = InputConfig::parse("four_player = famicom\np4.a = key:Q").unwrap();
+        assert_eq!(config.four_player, FourPlayerMode::Famicom);
+        assert!(config.bindings.contains(&Binding {
+            player: 3,
+            action: Action::Button(JoypadButton::A),
+            source: Source::Key("Q".to_string()),
+        }));
+        assert_eq!(InputConfig::default().four_player, FourPlayerMode::Off);
+        assert!(InputConfig::parse("four_player = yes").is_err());
+    }
+
+    #[test]
+    fn test_player_input() {
+        let mut player = PlayerInput::new(1, vec![JoypadButton::START; 2]);
+        let turbo = HeldInput { turbo: JoypadButton::A, buttons: JoypadButton::LEFT, ..HeldInput::default() };
+        assert_eq!(player.next_frame(turbo), JoypadButton::A | JoypadButton::LEFT);
+        assert_eq!(player.next_frame(turbo), JoypadButton::LEFT);
+
+        // ホットキーは押し続けても1回だけ再生する。
+        let play = HeldInput { macro_play: true, ..HeldInput::default() };
+        assert_eq!(player.next_frame(play), JoypadButton::START);
+        assert_eq!(player.next_frame(play), JoypadButton::START);
+        assert_eq!(player.next_frame(play), JoypadButton::empty());
+    }
+
+    #[test]
+    fn test_axis_deadzone() {
+        assert!(!axis_pressed(7999, true, 8000));
+        assert!(axis_pressed(8001, true, 8000));
+        assert!(axis_pressed(-32768, false, 8000));
+        assert!(!axis_pressed(-8000, false, 8000));
+        assert!(!axis_pressed(20000, false, 8000));
+    }
+}
@@ -1,2302 +1,791 @@
-pub mod bus;
-pub mod cartridge;
-use cartridge::Rom;
-use bus::Bus;
-
-
-pub const CARRY_FLAG:u8 = 0b0000_0001;
-pub const INTERRUPT_FLAG:u8 = 0b0000_0100;
-pub const DECIMAL_FLAG:u8 = 0b0000_1000;
-pub const BREAK_FLAG:u8 = 0b0001_0000;
-pub const INVALID_FLAG:u8 = 0b0010_0000;
-pub const NEGATIVE_FLAG:u8 = 0b1000_0000;
-pub const ZERO_FLAG:u8 = 0b0000_0010;
-pub const OVERFLOW_FLAG:u8 = 0b0100_0000;
-
-
-
-
-
-pub fn is_flag_set(flag:u8,x:u8) -> bool {
-      x & flag > 0
-}
-
-
-
-pub struct CPU {
-    pub register_a:u8,
-    pub register_x:u8,
-    pub register_y:u8,
-    pub status:u8,
-    pub program_counter:u16,
-    pub stackpointer:u8,
-    //memory: [u8;0xFFFF],
-    pub bus: Bus,
-}
-
-pub trait Mem {
-    fn mem_read(&self, addr:u16) -> u8;
-
-    fn mem_write(&mut self, addr:u16, data:u8);
-
-    fn mem_read_u16(&self, pos:u16) -> u16 {
-        let hi = self.mem_read(pos + 1) as u16;
-        let lo = self.mem_read(pos) as u16;
-
-        (hi << 8) | (lo as u16)
-    }
-
-    fn mem_write_u16(&mut self, pos:u16, data:u16) {
-        let hi = (data >> 8) as u8;
-        let lo = (data & 0xff) as u8;
-
-        self.mem_write(pos, lo);
-        self.mem_write(pos + 1, hi);
-    }
+mod audio;
+mod input;
+
+use audio::{AudioOutput, FrameTimer, SyncMode};
+use input::{Input, InputConfig};
+use nes_core::bus::Bus;
+use nes_core::cartridge::Rom;
+use nes_core::expansion::ExpansionLevels;
+use nes_core::movie::{apply_movie_frame, movie_rng, power_cycle, Movie, MovieFrame, MovieSession, COMMAND_POWER, COMMAND_RESET, MOVIE_PLAYERS};
+use nes_core::ports::{DeviceKind, FourPlayerMode};
+use nes_core::rewind::{Rewind, RewindAudio, DEFAULT_REWIND_INTERVAL, DEFAULT_REWIND_MEMORY};
+use nes_core::run_ahead::RunAhead;
+use nes_core::snake::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use nes_core::sram::{SaveData, SaveFile, STATE_SLOTS};
+use nes_core::{fds, loader, nsf, romdb, zapper};
+use nes_core::{Nes, CPU, NTSC_FRAME_RATE};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use sdl2::event::Event;
+use sdl2::EventPump;
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::pixels::PixelFormatEnum;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// 起動引数: nes_emulator [rom] [--save-dir <dir>] [--patch <ips/bps/ups>] [--entry <zip内のファイル名>]
+//                        [--fds-bios <disksys.rom>] [--sync audio|video] [--stems]
+//                        [--expansion-level <vrc6|vrc7|n163|5b|mmc5|fds>=<音量>]... [--track <n>]
+//                        [--input-config <file>] [--port1 <機器>] [--port2 <機器>] [--expansion <機器>]
+//                        [--experimental-zapper]
+//                        (光線銃はPPUができるまで保留。実際のゲームでは当たりを検出できない)
+//           nes_emulator rom-info <rom>
+//           どのモードでも[--rom-db <nes20db.xml>]でNES 2.0 DBを読み込み、ヘッダーの補正に使う。
+//           nes_emulator record <rom> [--frames <n> | --seconds <t>] [--output <wav>] [--stems] [--track <n>]
+//                        [--experimental-zapper --zapper-script <file>]
+//           nes_emulator [rom] --movie <fm2> [--movie-read-write] | --record-movie <fm2>
+//           nes_emulator [rom] [--rewind-interval <n>] [--rewind-memory <MB>] [--rewind-audio mute|reverse]
+//                        [--run-ahead <n>] [--run-ahead-instance]
+//           nes_emulator [rom] --snake   (ROMを指定しなければsnake.nesを--snake付きで起動する)
+struct Options {
+    rom_path: PathBuf,
+    save_dir: Option<PathBuf>,
+    fds_bios: PathBuf,
+    rom_db: Option<PathBuf>,
+    patch_path: Option<PathBuf>,
+    zip_entry: Option<String>,
+    rom_info: bool,
+    sync_mode: SyncMode,
+    // WAV録音: recordはウィンドウを出さずにframesフレーム分だけ録音して終了する。
+    record: bool,
+    record_frames: u32,
+    record_path: Option<PathBuf>,
+    stems: bool,
+    expansion_levels: ExpansionLevels,
+    // NSFの最初に再生する曲(1始まり)。指定がなければファイルの指定に従う。
+    track: Option<u8>,
+    // キー/ゲームコントローラーの割り当て。指定がなければinput.cfgがあれば読む。
+    input_config: Option<PathBuf>,
+    // 端子につなぐ機器(none, joypad, paddle, powerpad, mouse, keyboard)。指定がなければ標準コントローラー
+    // 光線銃は受光にPPUが要るので--port2 zapperではつながない。--experimental-zapperの時だけ2コンの端子につなぎ、
+    // snakeの32x32の画面で受光を判定する。ウィンドウではマウスで狙い、ヘッドレスではスクリプトに従う。
+    port_devices: [Option<DeviceKind>; 2],
+    expansion_device: Option<DeviceKind>,
+    zapper_script: Option<PathBuf>,
+    // FM2ムービーの再生(読み込み専用、--movie-read-writeなら続きを記録できる)と記録
+    movie_path: Option<PathBuf>,
+    movie_read_write: bool,
+    record_movie: Option<PathBuf>,
+    // 巻き戻し(Backspaceを押している間)。rewind_interval フレームごとに保存し、メモリは rewind_memory バイトまで使う。
+    // 0 MBを指定すると巻き戻しを無効にする。
+    rewind_interval: u32,
+    rewind_memory: usize,
+    rewind_audio: RewindAudio,
+    // 先行実行するフレーム数(0〜4)。run_ahead_instanceなら2つ目のCPUで実行して音の乱れを避ける。
+    run_ahead: u32,
+    run_ahead_instance: bool,
+    // snakeのデモ用に乱数($FE)とキー($FF)を書き込む。他のROMのRAMを壊すので指定した時だけ。
+    snake: bool,
 }
 
-impl Mem for CPU {
-    fn mem_read(&self, addr:u16) -> u8 {
-        self.bus.mem_read(addr)
-    }
-    fn mem_write(&mut self, addr:u16, data:u8) {
-        self.bus.mem_write(addr, data)
-    }
-
-    fn mem_read_u16(&self , pos:u16) -> u16 {
-        self.bus.mem_read_u16(pos)
-    }
-    fn mem_write_u16(&mut self, pos:u16, data:u16) {
-        self.bus.mem_write_u16(pos, data)
-    }
-
+fn next_arg(args: &mut impl Iterator<Item = String>, message: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| message.to_string())
 }
 
-#[derive(Debug, PartialEq)]
-#[allow(non_camel_case_types)]
-pub enum AddressingMode {
-    Accumulator,
-    Immediate,
-    Zeropage,
-    Zeropage_X,
-    Zeropage_Y,
-    Absolute,
-    Absolute_X,
-    Absolute_Y,
-    Indirect,
-    Indirect_X,
-    Indirect_Y,
-    Relative,
-    NoneAddressinng,
+fn parse_arg<T: std::str::FromStr>(value: &str, message: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{}: {}", message, value))
 }
 
-
-impl CPU {
-
-    fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
-
-        match mode {
-            AddressingMode::Accumulator => self.register_a as u16 ,
-            AddressingMode::Immediate => self.program_counter,
-            AddressingMode::Zeropage => self.mem_read(self.program_counter) as u16,
-            AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
-            AddressingMode::Zeropage_Y =>{
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y) as u16;
-                addr
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: PathBuf::from("snake.nes"),
+        save_dir: None,
+        fds_bios: PathBuf::from("disksys.rom"),
+        rom_db: None,
+        patch_path: None,
+        zip_entry: None,
+        rom_info: false,
+        sync_mode: SyncMode::Audio,
+        record: false,
+        record_frames: 600,
+        record_path: None,
+        stems: false,
+        expansion_levels: ExpansionLevels::default(),
+        track: None,
+        input_config: None,
+        port_devices: [None; 2],
+        expansion_device: None,
+        zapper_script: None,
+        movie_path: None,
+        movie_read_write: false,
+        record_movie: None,
+        rewind_interval: DEFAULT_REWIND_INTERVAL,
+        rewind_memory: DEFAULT_REWIND_MEMORY,
+        rewind_audio: RewindAudio::Mute,
+        run_ahead: 0,
+        run_ahead_instance: false,
+        snake: false,
+    };
+    let mut rom_given = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "rom-info" => options.rom_info = true,
+            "record" => options.record = true,
+            "--frames" => {
+                let frames = next_arg(&mut args, "--frames requires a number")?;
+                options.record_frames = parse_arg(&frames, "--frames requires a number")?;
             }
-            AddressingMode::Zeropage_X =>{
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x) as u16;
-                addr
+            "--seconds" => {
+                let seconds = next_arg(&mut args, "--seconds requires a number")?;
+                let seconds: f64 = parse_arg(&seconds, "--seconds requires a number")?;
+                options.record_frames = (seconds * NTSC_FRAME_RATE).round() as u32;
             }
-            AddressingMode::Absolute_X => {
-                let pos = self.mem_read_u16(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x as u16);
-                addr
+            "--input-config" => {
+                let config = next_arg(&mut args, "--input-config requires a file name")?;
+                options.input_config = Some(PathBuf::from(config));
             }
-            AddressingMode::Absolute_Y => {
-                let pos = self.mem_read_u16(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y as u16);
-                addr
+            "--port1" | "--port2" | "--expansion" => {
+                let device = next_arg(&mut args, &format!("{} requires a device name", arg))?;
+                let device = Some(DeviceKind::parse(&device)?);
+                match arg.as_str() {
+                    "--port1" => options.port_devices[0] = device,
+                    "--port2" => options.port_devices[1] = device,
+                    _ => options.expansion_device = device,
+                }
             }
-
-            AddressingMode::Indirect => {
-                let addr = self.mem_read_u16(self.program_counter);
-                let value = self.mem_read_u16(addr);
-                value
+            "--experimental-zapper" => options.port_devices[1] = Some(DeviceKind::Zapper),
+            "--zapper-script" => {
+                let script = next_arg(&mut args, "--zapper-script requires a file name")?;
+                options.zapper_script = Some(PathBuf::from(script));
             }
-
-            AddressingMode::Indirect_X => {
-                let pos = self.mem_read(self.program_counter);
-
-                let base = (pos as u8).wrapping_add(self.register_x);
-                let lo = self.mem_read(base as u16);
-                let hi = self.mem_read(base.wrapping_add(1) as u16);
-                (hi as u16) << 8 | (lo as u16)
-
+            "--movie" => {
+                let movie = next_arg(&mut args, "--movie requires a file name")?;
+                options.movie_path = Some(PathBuf::from(movie));
             }
-
-            AddressingMode::Indirect_Y => {
-                let pos = self.mem_read(self.program_counter);
-
-                let lo = self.mem_read(pos as u16);
-                let hi = self.mem_read((pos as u8).wrapping_add(1) as u16);
-                let ptr = (hi as u16) << 8 | (lo as u16);
-                let addr = ptr.wrapping_add(self.register_y as u16);
-                addr
+            "--movie-read-write" => options.movie_read_write = true,
+            "--record-movie" => {
+                let movie = next_arg(&mut args, "--record-movie requires a file name")?;
+                options.record_movie = Some(PathBuf::from(movie));
             }
-
-            AddressingMode::Relative => {
-                let addr = self.mem_read(self.program_counter);
-                let tmp = (addr as i8) as i32 + self.program_counter as i32;
-                tmp as u16
+            "--rewind-interval" => {
+                let frames = next_arg(&mut args, "--rewind-interval requires a number of frames")?;
+                options.rewind_interval = parse_arg(&frames, "--rewind-interval requires a number of frames")?;
             }
-
-            AddressingMode::NoneAddressinng => {
-                panic!("mode {:?} is not supported" , mode);
+            "--rewind-memory" => {
+                let megabytes = next_arg(&mut args, "--rewind-memory requires a size in MB")?;
+                let megabytes: usize = parse_arg(&megabytes, "--rewind-memory requires a size in MB")?;
+                options.rewind_memory = megabytes << 20;
+            }
+            "--rewind-audio" => {
+                let mode = next_arg(&mut args, "--rewind-audio requires mute or reverse")?;
+                options.rewind_audio = RewindAudio::parse(&mode)?;
+            }
+            "--run-ahead" => {
+                let frames = next_arg(&mut args, "--run-ahead requires a number of frames")?;
+                options.run_ahead = parse_arg(&frames, "--run-ahead requires a number of frames")?;
+            }
+            "--run-ahead-instance" => options.run_ahead_instance = true,
+            "--snake" => options.snake = true,
+            "--track" => {
+                let track = next_arg(&mut args, "--track requires a number")?;
+                options.track = Some(parse_arg(&track, "--track requires a number")?);
+            }
+            "--output" => {
+                let output = next_arg(&mut args, "--output requires a file name")?;
+                options.record_path = Some(PathBuf::from(output));
+            }
+            "--stems" => options.stems = true,
+            "--save-dir" => {
+                let dir = next_arg(&mut args, "--save-dir requires a directory")?;
+                options.save_dir = Some(PathBuf::from(dir));
+            }
+            "--patch" => {
+                let patch = next_arg(&mut args, "--patch requires a patch file")?;
+                options.patch_path = Some(PathBuf::from(patch));
+            }
+            "--fds-bios" => {
+                let bios = next_arg(&mut args, "--fds-bios requires a BIOS file")?;
+                options.fds_bios = PathBuf::from(bios);
+            }
+            "--rom-db" => {
+                let db = next_arg(&mut args, "--rom-db requires an NES 2.0 XML file")?;
+                options.rom_db = Some(PathBuf::from(db));
+            }
+            "--entry" => {
+                options.zip_entry = Some(next_arg(&mut args, "--entry requires a file name")?);
+            }
+            "--sync" => {
+                let mode = next_arg(&mut args, "--sync requires audio or video")?;
+                options.sync_mode = SyncMode::parse(&mode)?;
+            }
+            "--expansion-level" => {
+                let setting = next_arg(&mut args, "--expansion-level requires <chip>=<level>")?;
+                options.expansion_levels.parse_setting(&setting)?;
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ => {
+                options.rom_path = PathBuf::from(arg);
+                rom_given = true;
             }
-
-
-        }
-    }
-
-
-
-
-
-    pub fn load_and_run(&mut self, program:Vec<u8>) {
-        self.load(program);
-        self.reset();
-        self.run()
-    }
-
-    pub fn load(&mut self , program:Vec<u8>) {
-     for i in 0.. (program.len() as u16) {
-        self.mem_write(0x0600 + i, program[i as usize]);
-     }
-        self.mem_write_u16(0xFFFC, 0x0600);
-    }
-
-
-    pub fn reset(&mut self) {
-        self.register_a = 0;
-        self.register_x = 0;
-        self.register_y = 0;
-        self.stackpointer = 0xff;
-        self.status = 0;
-
-        self.program_counter = self.mem_read_u16(0xFFFC);
-    }
-
-
-    pub fn new(bus: Bus) -> Self {
-        CPU {
-            register_a:0,
-            register_x:0,
-            register_y:0,
-            status:0,
-            program_counter:0,
-            stackpointer:0xff,
-            //memory:[0u8; 0xFFFF],
-            bus:bus,
         }
     }
-
-    fn sec(&mut self){
-        self.status = self.status | 0b0000_0001;
-    }
-    
-    fn clc(&mut self){
-        self.status = self.status & 0b1111_1110;
-    }
-
-    fn lda(&mut self, mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
-
-        self.register_a =  value;
-        self.update_zero_and_negative_flags(self.register_a);
+    if !rom_given {
+        options.snake = true;
     }
-
-    fn ldx(&mut self , mode:&AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
-
-        self.register_x = value;
-        self.update_zero_and_negative_flags(self.register_x);
+    if options.zapper_script.is_some() && options.port_devices[1] != Some(DeviceKind::Zapper) {
+        return Err("--zapper-script needs --experimental-zapper".to_string());
     }
-
-    fn ldy(&mut self , mode: &AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
-
-        self.register_y = value;
-        self.update_zero_and_negative_flags(self.register_y);
+    if options.port_devices.contains(&Some(DeviceKind::Zapper)) {
+        eprintln!("{}", zapper::EXPERIMENTAL_NOTICE);
     }
+    Ok(options)
+}
 
-    fn sta(&mut self, mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        self.mem_write(addr, self.register_a);
-    }
+// 引数やROMの誤りはパニックせず、メッセージを出して終了する。
+fn exit_with_error(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1)
+}
 
-    fn stx(&mut self , mode: &AddressingMode){
-        let addr = self.get_operand_address(mode);
-        self.mem_write(addr, self.register_x);
-    }
+// セーブデータを定期的に書き出す間隔
+const SAVE_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
 
-    fn sty(&mut self , mode: &AddressingMode){
-        let addr = self.get_operand_address(mode);
-        self.mem_write(addr, self.register_y);
+fn flush_save(cpu: &mut CPU, save_data: &SaveData, force: bool) {
+    if let Err(e) = save_data.flush(&mut cpu.bus, force) {
+        eprintln!("{}", e);
     }
+}
 
+// ホットキーで録音、記録する時のファイル名: <ROM名>-<UNIX時刻>.<拡張子>
+fn recording_path(rom_path: &Path, extension: &str) -> PathBuf {
+    let stem = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    PathBuf::from(format!("{}-{}.{}", stem, secs, extension))
+}
 
-
-    fn adc(&mut self, mode: &AddressingMode) {
-        // メモリ値とキャリーフラグを取得
-        let addr = self.get_operand_address(mode);
-        let pos = self.mem_read(addr);
-        let carry = if self.status & CARRY_FLAG == CARRY_FLAG { 1 } else { 0 };
-
-        // 加算処理
-        let tmp = self.register_a; // 元のAレジスタの値を保存
-        let (result1, carry1) = tmp.overflowing_add(pos); // A + メモリ値
-        let (result2, carry2) = result1.overflowing_add(carry); // A + メモリ値 + キャリーフラグ
-        self.register_a = result2; // 計算結果をAレジスタに格納
-
-        // ZフラグとNフラグを更新
-        self.update_zero_and_negative_flags(self.register_a);
-
-        // キャリーフラグを更新
-        if carry1 || carry2 {
-            self.status |= CARRY_FLAG; // キャリーフラグをセット
-        } else {
-            self.status &= !CARRY_FLAG; // キャリーフラグをクリア
+fn toggle_recording(cpu: &mut CPU, rom_path: &Path, stems: bool) {
+    let apu = &mut cpu.bus.apu;
+    if apu.is_recording() {
+        match apu.stop_recording() {
+            Ok(()) => println!("Recording stopped"),
+            Err(e) => eprintln!("{}", e),
         }
-
-        // オーバーフローフラグを更新
-        if ((self.register_a ^ tmp) & (self.register_a ^ pos) & 0x80) != 0 {
-            self.status |= OVERFLOW_FLAG; // オーバーフローフラグをセット
-        } else {
-            self.status &= !OVERFLOW_FLAG; // オーバーフローフラグをクリア
+    } else {
+        let path = recording_path(rom_path, "wav");
+        match apu.start_recording(&path, stems) {
+            Ok(()) => println!("Recording to {}", path.display()),
+            Err(e) => eprintln!("{}", e),
         }
     }
+}
 
-    fn sbc(&mut self , mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let pos = self.mem_read(addr);
-        let tmp = self.register_a;
-        let base = (!(pos) as u8).wrapping_add((self.status & CARRY_FLAG) as u8);
-
-        self.register_a =  self.register_a.wrapping_add(base as u8);
-
-        /* bit operation starts from here */
-        self.update_zero_and_negative_flags(self.register_a);
-
-
-        if tmp as u16 >= pos as u16 + (1 - (self.status & CARRY_FLAG) as u16) {
-            self.status = self.status | CARRY_FLAG; // 借りが発生しなかった場合
-        } else {
-            self.status = self.status & !CARRY_FLAG; // 借りが発生した場合
+// ウィンドウを出さずに指定フレーム数だけ実行し、音声をWAVに書き出す。
+fn record_headless(nes: &mut Nes, options: &Options) -> Result<(), String> {
+    let path = options.record_path.clone().unwrap_or_else(|| options.rom_path.with_extension("wav"));
+    nes.cpu_mut().bus.apu.start_recording(&path, options.stems)?;
+    let script = match &options.zapper_script {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            zapper::parse_script(&text)?
         }
-        
-        
-
-        if ((self.register_a ^ tmp) & (self.register_a ^ !(pos)) & 0x80) != 0 {
-            self.status = self.status | OVERFLOW_FLAG;
-        } else {
-            self.status = self.status & !OVERFLOW_FLAG;
+        None => Vec::new(),
+    };
+    for frame in 0..options.record_frames {
+        if let Some(zapper) = nes.cpu_mut().bus.ports.zapper_mut() {
+            for step in script.iter().filter(|step| step.frame == frame) {
+                zapper.set_aim(step.aim);
+                zapper.set_trigger(step.trigger);
+            }
+        }
+        if !nes.step_frame() {
+            break;
         }
-
-        /* bit operation endsuu from here */
-
-        
-    }
-
-    fn inc(&mut self, mode: &AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
-
-        let new_value = (value as u8).overflowing_add(1).0;
-
-        self.mem_write(addr, new_value);
-        self.update_zero_and_negative_flags(new_value);
-    }
-
-    fn dec(&mut self, mode: &AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
-
-        let new_value = (value as u8).overflowing_sub(1).0;
-
-        self.mem_write(addr, new_value);
-        self.update_zero_and_negative_flags(new_value);
     }
+    nes.cpu_mut().bus.apu.stop_recording()?;
+    println!("Recorded {} frames to {}", options.record_frames, path.display());
+    Ok(())
+}
 
-    fn inx(&mut self) {
-       self.register_x = (self.register_x).overflowing_add(1).0;
-       self.update_zero_and_negative_flags(self.register_x);
+// NSFは本体が最初の曲から始めているので、--trackの指定があればその曲に切り替え、曲名を出す。
+fn start_nsf(nes: &mut Nes, options: &Options) {
+    if !nes.is_nsf() {
+        return;
     }
-
-    fn dex(&mut self) {
-       self.register_x = (self.register_x).overflowing_sub(1).0;
-       self.update_zero_and_negative_flags(self.register_x);
+    let cpu = nes.cpu_mut();
+    if let Some(track) = options.track {
+        nsf::start_track(cpu, track.saturating_sub(1));
     }
+    println!("{}", nsf_track_title(cpu).unwrap_or_default());
+}
 
-    fn iny(&mut self) {
-       self.register_y = (self.register_y).overflowing_add(1).0;
-       self.update_zero_and_negative_flags(self.register_y);
-    }
+// NSF: 前後の曲に切り替える。
+fn change_track(cpu: &mut CPU, forward: bool) {
+    let track = match cpu.bus.mapper_mut().as_nsf_mut() {
+        Some(nsf) => {
+            let total = nsf.info().total_songs;
+            if forward {
+                (nsf.track() + 1) % total
+            } else {
+                (nsf.track() + total - 1) % total
+            }
+        }
+        None => return,
+    };
+    nsf::start_track(cpu, track);
+    println!("{}", nsf_track_title(cpu).unwrap_or_default());
+}
 
-    fn dey(&mut self) {
-       self.register_y = (self.register_y).overflowing_sub(1).0;
-       self.update_zero_and_negative_flags(self.register_y);
-    }
+// "3/12 曲名 - タイトル"
+fn nsf_track_title(cpu: &mut CPU) -> Option<String> {
+    let nsf = cpu.bus.mapper_mut().as_nsf_mut()?;
+    let info = nsf.info();
+    Some(format!("{}/{} {} - {}", nsf.track() as u32 + 1, info.total_songs, info.track_name(nsf.track()), info.title))
+}
 
-    fn tax(&mut self){
-        self.register_x = self.register_a;
-        self.update_zero_and_negative_flags(self.register_x);
+// 入力設定の3P, 4Pのつなぎ方の後に、起動引数で指定した機器をつなぐ。
+fn connect_devices(bus: &mut Bus, options: &Options, four_player: FourPlayerMode) -> Result<(), String> {
+    bus.ports.set_four_player(four_player);
+    for (port, device) in options.port_devices.iter().enumerate() {
+        if let Some(device) = device {
+            bus.ports.set_device(port, *device)?;
+        }
     }
-    
-    fn txa(&mut self){
-        self.register_a = self.register_x;
-        self.update_zero_and_negative_flags(self.register_a);
+    if let Some(device) = options.expansion_device {
+        bus.ports.set_expansion_device(device)?;
     }
+    Ok(())
+}
 
-    fn tay(&mut self){
-        self.register_y = self.register_a;
-        self.update_zero_and_negative_flags(self.register_y);
-    }
+// フレームの先頭で処理するホットキーの要求
+#[derive(Default)]
+struct Requests {
+    // ムービーに記録するコマンド(リセット、電源)
+    commands: u8,
+    toggle_movie: bool,
+    toggle_read_only: bool,
+    // セーブステートのスロットの選択、保存、読み込み
+    select_slot: Option<usize>,
+    save_state: bool,
+    load_state: bool,
+}
 
-    fn tya(&mut self){
-        self.register_a = self.register_y;
-        self.update_zero_and_negative_flags(self.register_a);
+// 起動引数で指定したムービーを記録または再生する。どちらも電源を入れた状態から始める。
+fn start_movie(cpu: &mut CPU, options: &Options, rom_md5: [u8; 16], four_player: FourPlayerMode) -> Result<Option<MovieSession>, String> {
+    if let Some(path) = &options.record_movie {
+        return Ok(Some(new_movie_recording(cpu, options, rom_md5, four_player, path.clone())));
+    }
+    let Some(path) = &options.movie_path else {
+        return Ok(None);
+    };
+    let movie = Movie::load(path)?;
+    if movie.rom_checksum != rom_md5 {
+        eprintln!("Warning: movie was recorded with a different ROM ({})", movie.rom_filename);
+    }
+    if movie.four_score {
+        cpu.bus.ports.set_four_player(FourPlayerMode::FourScore);
+    }
+    println!("Playing movie {} ({} frames, {})", path.display(), movie.frames.len(), if options.movie_read_write { "read-write" } else { "read-only" });
+    // セーブステートから始まるムービーは電源を入れる代わりにステートを読み込む。
+    match &movie.savestate {
+        Some(state) => {
+            cpu.load_state(rom_md5, state).map_err(|e| format!("Failed to load the movie's save state: {}", e))?;
+        }
+        None => power_cycle(cpu),
     }
+    Ok(Some(MovieSession::play(movie, path.clone(), !options.movie_read_write)))
+}
 
-    /*shift instruction starts from here */
-    
-
-    fn asl(&mut self,mode: &AddressingMode){
-
-        match mode {
-            AddressingMode::Accumulator => {
-                let value = self.register_a;
-                let bit7_tmp = value & 0b1000_0000;
-                let new_value = value.wrapping_mul(2);
-
-                self.register_a = new_value;
-
-                self.status = self.status & 0b1111_1110;
-
-                self.status = self.status | (bit7_tmp >> 7); //このやり方だと事前にキャリーがセットされているときにうまくいかない。
-                self.update_zero_and_negative_flags(self.register_a);
-            }
-            _ => {
-                let addr = self.get_operand_address(mode);
-                let value = self.mem_read(addr);
-                let bit7_tmp = value & 0b1000_0000;
-                let new_value = value.wrapping_mul(2);
-
-                self.mem_write(addr, new_value);
-
-                self.status = self.status & 0b1111_1110;
+fn new_movie_recording(cpu: &mut CPU, options: &Options, rom_md5: [u8; 16], four_player: FourPlayerMode, path: PathBuf) -> MovieSession {
+    let rom_filename = options.rom_path.file_stem().unwrap_or_default().to_string_lossy();
+    let movie = Movie::new(&rom_filename, rom_md5, four_player == FourPlayerMode::FourScore);
+    println!("Recording movie to {}", path.display());
+    power_cycle(cpu);
+    MovieSession::record(movie, path)
+}
 
-                self.status = self.status | (bit7_tmp >> 7);
-                self.update_zero_and_negative_flags(new_value);
-            }
-        };
-      
+fn save_movie(session: &mut MovieSession) {
+    if !session.is_dirty() {
+        return;
     }
-
-    fn lsr(&mut self,mode: &AddressingMode){
-
-        match mode {
-            AddressingMode::Accumulator => {
-                let value = self.register_a;
-                let bit0_tmp = value & 0b0000_0001;
-                let new_value = value.wrapping_div(2);
-
-                self.register_a = new_value;
-
-                self.status = self.status & 0b1111_1110;
-
-                self.status = self.status | (bit0_tmp); 
-                self.update_zero_and_negative_flags(self.register_a);
-            }
-            _ => {
-                let addr = self.get_operand_address(mode);
-                let value = self.mem_read(addr);
-                let bit0_tmp = value & 0b0000_0001;
-                let new_value = value.wrapping_div(2);
-
-                self.mem_write(addr, new_value);
-
-                self.status = self.status & 0b1111_1110;
-
-                self.status = self.status | (bit0_tmp);
-                self.update_zero_and_negative_flags(new_value);
-            }
-        };
-      
+    match session.save() {
+        Ok(()) => println!("Movie saved to {} ({} frames)", session.path.display(), session.movie.frames.len()),
+        Err(e) => eprintln!("{}", e),
     }
+}
 
-    fn rol(&mut self,mode: &AddressingMode){
-
-        match mode {
-            AddressingMode::Accumulator => {
-                let value = self.register_a;
-                let bit7_tmp = value & 0b1000_0000;
-                let carry_tmp = self.status & 0b0000_0001;
-                let tmp_value = value.wrapping_mul(2);
-
-                let tmp_value_without_carry = tmp_value & 0b1111_1110;
-
-                let modified_value = tmp_value_without_carry | carry_tmp;
-
-                self.register_a = modified_value;
-
-                self.status = self.status & 0b1111_1110;
-
-                self.status = self.status | (bit7_tmp >> 7); 
-                self.update_zero_and_negative_flags(self.register_a);
-            }
-            _ => {
-                let addr = self.get_operand_address(mode);
-                let value = self.mem_read(addr);
-                let bit7_tmp = value & 0b1000_0000;
-                let carry_tmp = self.status & 0b0000_0001;
-                let tmp_value = value.wrapping_mul(2);
-
-                let tmp_value_without_carry = tmp_value & 0b1111_1110;
-
-                let modified_value = tmp_value_without_carry | carry_tmp;
-
-                self.mem_write(addr, modified_value);
-
-                self.status = self.status & 0b1111_1110;
-
-                self.status = self.status | (bit7_tmp >> 7);
-                self.update_zero_and_negative_flags(modified_value);
-            }
-        };
-      
+fn save_state_slot(cpu: &CPU, options: &Options, rom_md5: [u8; 16], slot: usize, movie: Option<&MovieSession>) {
+    let file = SaveFile::state_slot(&options.rom_path, options.save_dir.as_deref(), slot);
+    let state = cpu.save_state(rom_md5, movie.map(|session| session.frame()));
+    match file.flush(&state) {
+        Ok(()) => println!("Saved state to slot {}", slot),
+        Err(e) => eprintln!("Failed to save state to {}: {}", file.path().display(), e),
     }
+}
 
-    fn ror(&mut self , mode:&AddressingMode){
-
-        let (value,borrow) = match mode {
-            AddressingMode::Accumulator => {
-
-                let mut  value = self.register_a;
-                let borrow = value % 2;
-                value = value.wrapping_div(2);
-                value = value | ((self.status & 0b0000_0001) << 7);
-                self.register_a = value;
-                (value , borrow)
-            }
-            _ => { 
-                let addr = self.get_operand_address(mode);
-                let mut  value = self.mem_read(addr);
+fn load_state_slot(cpu: &mut CPU, options: &Options, rom_md5: [u8; 16], slot: usize, movie: Option<&mut MovieSession>) -> Result<(), String> {
+    let file = SaveFile::state_slot(&options.rom_path, options.save_dir.as_deref(), slot);
+    let state = file
+        .read()
+        .map_err(|e| format!("Failed to read {}: {}", file.path().display(), e))?
+        .ok_or_else(|| format!("Slot {} is empty", slot))?;
+    load_state(cpu, rom_md5, &state, movie)
+}
 
-                let borrow = value % 2;
-                value = value.wrapping_div(2);
-                value = value | ((self.status & 0b0000_0001) <<  7);
-                self.mem_write(addr, value);
-                (value , borrow)
-            }
-        };
-        self.status = if borrow == 1 {
-            self.status | 0b0000_0001
-        } else {
-            self.status & 0b1111_1110
-        };
-        self.update_zero_and_negative_flags(value);
+// ムービー中は、読み書き可能ならステートのフレームから記録し直し、読み込み専用ならそのフレームから再生する。
+// 読み込めなかった時はエラーを返し、状態は変わらない。
+fn load_state(cpu: &mut CPU, rom_md5: [u8; 16], state: &[u8], movie: Option<&mut MovieSession>) -> Result<(), String> {
+    let backup = cpu.save_state(rom_md5, None);
+    let frame = cpu.load_state(rom_md5, state)?;
+    let Some(session) = movie else {
+        return Ok(());
+    };
+    let result = match frame {
+        Some(frame) if session.is_read_only() => session.seek(frame),
+        Some(frame) => session.rerecord(frame),
+        None => Err("Save state was not made during a movie".to_string()),
+    };
+    if result.is_err() {
+        cpu.load_state(rom_md5, &backup).unwrap();
+    }
+    result
+}
 
+// コントローラーの実際の入力(連射やマクロを含む)
+fn live_frame(cpu: &mut CPU, commands: u8) -> MovieFrame {
+    let mut frame = MovieFrame { commands, ..MovieFrame::default() };
+    for (player, buttons) in frame.joypads.iter_mut().enumerate().take(MOVIE_PLAYERS) {
+        if let Some(joypad) = cpu.bus.ports.joypad_mut(player) {
+            *buttons = joypad.buttons();
+        }
     }
+    frame
+}
 
-    /*shift instruction ends here */
-
-    /*arithmetic instruction starts here */
-    fn and(&mut self , mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let  value = self.mem_read(addr);
+// 入力設定の読み込み先
+const DEFAULT_INPUT_CONFIG: &str = "input.cfg";
 
-        self.register_a = self.register_a & value;
-        self.update_zero_and_negative_flags(self.register_a);
+fn load_input_config(options: &Options) -> Result<InputConfig, String> {
+    match &options.input_config {
+        Some(path) => InputConfig::load(path),
+        None if Path::new(DEFAULT_INPUT_CONFIG).exists() => InputConfig::load(Path::new(DEFAULT_INPUT_CONFIG)),
+        None => Ok(InputConfig::default()),
     }
+}
 
-    fn ora(&mut self , mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let  value = self.mem_read(addr);
-
-        self.register_a = self.register_a | value;
-        self.update_zero_and_negative_flags(self.register_a);
+fn print_rom_info(rom: &Rom) {
+    println!("Title:       {}", rom.title.as_deref().unwrap_or("(not in database)"));
+    println!("CRC32:       {:08X}", rom.hash.crc32);
+    println!("SHA1:        {}", rom.hash.sha1);
+    println!("Mapper:      {}", rom.mapper);
+    println!("Mirroring:   {:?}", rom.screen_mirroring);
+    println!("Battery:     {}", rom.battery);
+    println!("TV system:   {:?}", rom.tv_system);
+    println!("PRG-ROM:     {} KB", rom.prg_rom.len() / 1024);
+    println!("CHR-ROM:     {} KB", rom.chr_rom.len() / 1024);
+    println!("PRG-RAM:     {} KB", rom.prg_ram_size / 1024);
+    if let Some(info) = &rom.nsf {
+        println!("Artist:      {}", info.artist);
+        println!("Copyright:   {}", info.copyright);
+        println!("Songs:       {} (start {})", info.total_songs, info.starting_song as u32 + 1);
+        let chips: Vec<&str> = info.chips.iter().map(|chip| chip.name()).collect();
+        println!("Expansion:   {}", chips.join(", "));
     }
+}
 
-    fn eor(&mut self , mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let  value = self.mem_read(addr);
+fn main() {
+   let options = parse_args().unwrap_or_else(|e| exit_with_error(e));
 
-        self.register_a = self.register_a ^ value;
-        self.update_zero_and_negative_flags(self.register_a);
+    if let Some(path) = &options.rom_db {
+        let count = romdb::load_database(path).unwrap_or_else(|e| exit_with_error(e));
+        println!("Loaded {} games from {}", count, path.display());
     }
 
-    fn bit(&mut self , mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
-
-        let bit6_tmp = value & 0b0100_0000;
-        let bit7_tmp = value & 0b1000_0000;
-
-        let result = self.register_a & value;
-
-        self.status = if result == 0 {
-            self.status | 0b0000_0010
-        } else {
-            self.status & 0b1111_1101
-        };
-
-        self.status = if bit6_tmp == 0b0100_0000 {
-            self.status | ((bit6_tmp))
-        } else {
-            self.status & 0b1011_1111
-        };
-
-        self.status = if bit7_tmp == 0b1000_0000 {
-            self.status |  ((bit7_tmp))
-        } else {
-            self.status & 0b0111_1111
-        };
-
-
+    let bytes = loader::load_rom(&options.rom_path, options.patch_path.as_deref(), options.zip_entry.as_deref())
+        .unwrap_or_else(|e| exit_with_error(e));
+    let mut rom = Rom::new(&bytes).unwrap_or_else(|e| exit_with_error(e));
+    if options.rom_info {
+        print_rom_info(&rom);
+        return;
     }
-    /*arithmetic instruction ends here */
-
-    /*compare instruction starts here */
-    fn cmp(&mut self, mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
 
-        let result = self.register_a.wrapping_sub(value);
+   let title = match &rom.title {
+       Some(title) => title.clone(),
+       None => options.rom_path.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+   };
 
-        self.status = if result == 0 {
-            self.status | 0b0000_0010
-        } else {
-            self.status & !(0b0000_0010)
-        };
+    let rom_md5 = rom.hash.md5;
 
-        self.status = if !(result & 0b1000_0000 == 0b1000_0000) {
-            self.status | 0b0000_0001
-        } else {
-            self.status & !(0b0000_0001)
-        };
+    if rom.mapper == fds::FDS_MAPPER {
+        let bios = std::fs::read(&options.fds_bios)
+            .unwrap_or_else(|e| exit_with_error(format!("Failed to read FDS BIOS {}: {}", options.fds_bios.display(), e)));
+        fds::attach_bios(&mut rom, bios).unwrap_or_else(|e| exit_with_error(e));
+    }
 
-        self.status = if result & 0b1000_0000 == 0b1000_0000 {
-            self.status | 0b1000_0000
-        } else {
-            self.status & !(0b1000_0000)
-        };
+    if options.record {
+        let mut bus = Bus::new(rom).unwrap_or_else(|e| exit_with_error(e));
+        bus.set_expansion_levels(&options.expansion_levels);
+        connect_devices(&mut bus, &options, FourPlayerMode::Off).unwrap_or_else(|e| exit_with_error(e));
+        let mut nes = Nes::from_bus(bus);
+        nes.set_snake(options.snake);
+        start_nsf(&mut nes, &options);
+        record_headless(&mut nes, &options).unwrap_or_else(|e| exit_with_error(e));
+        return;
     }
-    fn cpx(&mut self, mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
 
-        let result = self.register_x.wrapping_sub(value);
+   let sdl_context = sdl2::init().unwrap();
+   let video_subsystem = sdl_context.video().unwrap();
+   let window = video_subsystem
+       .window(&title, (32.0 * 10.0) as u32, (32.0 * 10.0) as u32)
+       .position_centered()
+       .build().unwrap();
 
-        self.status = if result == 0 {
-            self.status | 0b0000_0010
-        } else {
-            self.status & !(0b0000_0010)
-        };
+    // ビデオ同期の時だけpresentが垂直同期を待つ。
+    let sync_mode = options.sync_mode;
+    let mut canvas = match sync_mode {
+        SyncMode::Video => window.into_canvas().present_vsync().build().unwrap(),
+        SyncMode::Audio => window.into_canvas().build().unwrap(),
+    };
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    // ゲームコントローラーが使えなくてもキーボードで遊べるようにする。
+    let controllers = sdl_context.game_controller().map_err(|e| eprintln!("Game controllers disabled: {}", e)).ok();
+    let input_config = load_input_config(&options).unwrap_or_else(|e| exit_with_error(e));
+    let mut input = Input::new(&input_config, controllers).unwrap_or_else(|e| exit_with_error(e));
+    canvas.set_scale(10.0, 10.0).unwrap();
 
-        self.status = if !(result & 0b1000_0000 == 0b1000_0000) {
-            self.status | 0b0000_0001
-        } else {
-            self.status & !(0b0000_0001)
-        };
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(PixelFormatEnum::RGB24, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32).unwrap();
+
+    let run_ahead_rom = (options.run_ahead > 0 && options.run_ahead_instance).then(|| rom.clone());
+    let mut bus = Bus::new(rom).unwrap_or_else(|e| exit_with_error(e));
+    bus.set_expansion_levels(&options.expansion_levels);
+    connect_devices(&mut bus, &options, input_config.four_player).unwrap_or_else(|e| exit_with_error(e));
+    // マウスはウィンドウの端で止まらないよう相対モードで動かす。
+    if bus.ports.devices_mut().any(|device| device.as_mouse_mut().is_some()) {
+        sdl_context.mouse().set_relative_mouse_mode(true);
+    }
+
+    // 音が出せなくてもゲームは続けられるようにする。
+    let mut audio = match sdl_context.audio().and_then(|a| AudioOutput::new(&a, bus.apu.sample_rate())) {
+        Ok(audio) => {
+            bus.apu.set_sample_rate(audio.sample_rate());
+            Some(audio)
+        }
+        Err(e) => {
+            eprintln!("Audio disabled: {}", e);
+            None
+        }
+    };
+     
+    let mut nes = Nes::from_bus(bus);
+    nes.set_snake(options.snake);
+    nes.set_rng(StdRng::from_entropy());
 
-        self.status = if result & 0b1000_0000 == 0b1000_0000 {
-            self.status | 0b1000_0000
-        } else {
-            self.status & !(0b1000_0000)
-        };
+    let save_data = SaveData::new(&options.rom_path, options.save_dir.as_deref());
+    if let Err(e) = save_data.load(&mut nes.cpu_mut().bus) {
+        eprintln!("{}", e);
     }
-    fn cpy(&mut self, mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+    let mut last_flush = Instant::now();
 
-        let result = self.register_y.wrapping_sub(value);
+    start_nsf(&mut nes, &options);
+    let mut shown_title = title;
 
-        self.status = if result == 0 {
-            self.status | 0b0000_0010
-        } else {
-            self.status & !(0b0000_0010)
-        };
+    let mut frame_timer = FrameTimer::new();
 
-        self.status = if !(result & 0b1000_0000 == 0b1000_0000) {
-            self.status | 0b0000_0001
-        } else {
-            self.status & !(0b0000_0001)
-        };
+    let mut state_slot = 0;
+    let mut rewind = Rewind::new(options.rewind_interval, options.rewind_memory);
 
-        self.status = if result & 0b1000_0000 == 0b1000_0000 {
-            self.status | 0b1000_0000
-        } else {
-            self.status & !(0b1000_0000)
-        };
+    let mut movie = start_movie(nes.cpu_mut(), &options, rom_md5, input_config.four_player).unwrap_or_else(|e| exit_with_error(e));
+    if movie.is_some() {
+        apply_movie_frame(nes.cpu_mut(), &mut movie, MovieFrame::default());
+        nes.resync_frame();
     }
-    /*compare instruction ends here */
-
-    /*branch instruction starts from here */
-    fn bcc(&mut self , mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-
-        if is_flag_set(CARRY_FLAG, self.status) == false {
-               self.program_counter = addr;
-        }
+    if let Some(session) = &movie {
+        nes.set_rng(movie_rng(session));
     }
-    fn bcs(&mut self , mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
 
-        if is_flag_set(CARRY_FLAG, self.status) == true {
-            self.program_counter = addr;
+    // 先行実行用のCPUは本体と同じ機器をつなぐ。
+    let run_ahead_instance = run_ahead_rom.map(|rom| {
+        let four_player = match &movie {
+            Some(session) if session.movie.four_score => FourPlayerMode::FourScore,
+            _ => input_config.four_player,
+        };
+        let mut bus = Bus::new(rom).unwrap_or_else(|e| exit_with_error(e));
+        connect_devices(&mut bus, &options, four_player).unwrap_or_else(|e| exit_with_error(e));
+        CPU::new(bus)
+    });
+    let mut run_ahead = RunAhead::new(options.run_ahead, run_ahead_instance).unwrap_or_else(|e| exit_with_error(e));
+    if run_ahead.frames() > 0 {
+        println!("Run-ahead: {} frames{}", run_ahead.frames(), if options.run_ahead_instance { " (second instance)" } else { "" });
+    }
+
+    // 1フレーム(約29781 CPUサイクル)ごとに入力やホットキーを処理する。BRKで止まったら終わる。
+    while nes.step_frame() {
+        let mut requests = Requests::default();
+        if handle_user_input(nes.cpu_mut(), &mut event_pump, &mut input, &options, &mut requests) {
+            flush_save(nes.cpu_mut(), &save_data, true);
+            if let Some(session) = &mut movie {
+                save_movie(session);
+            }
+            if let Err(e) = nes.cpu_mut().bus.apu.stop_recording() {
+                eprintln!("{}", e);
+            }
+            std::process::exit(0)
         }
-    }
-    fn beq(&mut self , mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
 
-        if is_flag_set(ZERO_FLAG, self.status) == true {
-            self.program_counter = addr;
-        }
-    }
-    fn bne(&mut self , mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        if is_flag_set(ZERO_FLAG, self.status) == false {
-            self.program_counter = addr;
+        // Ctrl+数字でスロットを選び、F1で保存、F4で読み込む。
+        if let Some(slot) = requests.select_slot {
+            state_slot = slot;
+            println!("State slot {}", slot);
         }
-    }
-    fn bpl(&mut self , mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        if is_flag_set(NEGATIVE_FLAG, self.status) == false {
-            self.program_counter = addr;
-        }
-    }
-    fn bmi(&mut self , mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        if is_flag_set(NEGATIVE_FLAG, self.status) == true {
-            self.program_counter = addr;
+        if requests.save_state {
+            save_state_slot(nes.cpu(), &options, rom_md5, state_slot, movie.as_ref());
         }
-    }
-    fn bvc(&mut self , mode:&AddressingMode){
-        let addr = self.get_operand_address(mode);
-        if is_flag_set(OVERFLOW_FLAG, self.status) == false {
-            self.program_counter = addr;
+        if requests.load_state {
+            match load_state_slot(nes.cpu_mut(), &options, rom_md5, state_slot, movie.as_mut()) {
+                Ok(()) => {
+                    println!("Loaded state from slot {}", state_slot);
+                    nes.resync_frame();
+                }
+                Err(e) => eprintln!("{}", e),
+            }
         }
-    }
-
-    fn bvs(&mut self , mode:&AddressingMode){
-       let addr = self.get_operand_address(mode);
 
-        if is_flag_set(OVERFLOW_FLAG, self.status) == true {
-            self.program_counter = addr;
+        // Backspaceを押している間は1フレームごとにスナップショットを1つずつ戻る。
+        let rewinding = options.rewind_memory > 0 && event_pump.keyboard_state().is_scancode_pressed(Scancode::Backspace);
+        if rewinding {
+            if let Some(state) = rewind.pop() {
+                match load_state(nes.cpu_mut(), rom_md5, &state, movie.as_mut()) {
+                    Ok(()) => nes.resync_frame(),
+                    Err(e) => {
+                        eprintln!("Rewind stopped: {}", e);
+                        rewind.clear();
+                    }
+                }
+            }
+        } else if options.rewind_memory > 0 && rewind.tick() {
+            rewind.push(nes.cpu().save_state(rom_md5, movie.as_ref().map(|session| session.frame())));
         }
-    }
-    /*branch instruction ends from here */
-
-    /*jump instruction starts from here */
-    fn push(&mut self,data:u16) {
-        let hi = (data >> 8) as u8;
-        let lo = (data & 0xff) as u8;
 
-        self.mem_write(0x0100 + (self.stackpointer as u16), hi);
-        self.mem_write(0x0100 + ((self.stackpointer -1) as u16), lo);
-        self.stackpointer = self.stackpointer.wrapping_sub(2);
-    }
-    //サブルーチン後はjsrの次の命令から開始する必要がある。
-    fn push_pc(&mut self){
-        self.push(self.program_counter.wrapping_add(2));
-    }
-    //ただスタックからpcをとり出すだけ。
-    fn pop_pc(&mut self) -> u16 {
-        self.stackpointer = self.stackpointer.wrapping_add(2);
+        let cpu = nes.cpu_mut();
+        input.update(&event_pump, &mut cpu.bus);
+        input.update_devices(&event_pump, &mut cpu.bus, canvas.window().size());
 
-        let hi = self.mem_read(0x0100 + (self.stackpointer as u16));
-        let lo = self.mem_read(0x0100 + ((self.stackpointer - 1 ) as u16));
+        // F10でムービーの記録を開始/停止し、F11で読み込み専用を切り替える。
+        if requests.toggle_read_only {
+            if let Some(session) = &mut movie {
+                session.toggle_read_only();
+                println!("Movie is {}", if session.is_read_only() { "read-only" } else { "read-write" });
+            }
+        }
+        if requests.toggle_movie {
+            match movie.take() {
+                Some(mut session) => {
+                    save_movie(&mut session);
+                    rewind.clear();
+                }
+                None => {
+                    let path = recording_path(&options.rom_path, "fm2");
+                    movie = Some(new_movie_recording(cpu, &options, rom_md5, input_config.four_player, path));
+                    rewind.clear();
+                }
+            }
+        }
+        let live = live_frame(cpu, requests.commands);
+        apply_movie_frame(cpu, &mut movie, live);
 
+        // NSFは曲名をウィンドウのタイトルに出す。
+        if let Some(track_title) = nsf_track_title(cpu).filter(|t| *t != shown_title) {
+            canvas.window_mut().set_title(&track_title).unwrap();
+            shown_title = track_title;
+        }
 
-        let pc = (((hi as u16) << 8)) | (lo as u16);
-        pc
-    }
+        if last_flush.elapsed() >= SAVE_FLUSH_INTERVAL {
+            flush_save(cpu, &save_data, false);
+            last_flush = Instant::now();
+        }
 
-    fn pop_flag(&mut self) -> u8 {
-        self.stackpointer += 1;
-        let flag = self.mem_read(0x0100 + (self.stackpointer as u16));
-        flag
-    }
+        let rate = match &mut audio {
+            Some(audio) => {
+                match (rewinding, options.rewind_audio) {
+                    (false, _) => audio.push(&mut cpu.bus.apu),
+                    (true, RewindAudio::Mute) => cpu.bus.apu.samples().clear(),
+                    (true, RewindAudio::Reverse) => audio.push_reversed(&mut cpu.bus.apu),
+                }
+                audio.rate_control()
+            }
+            None => 1.0,
+        };
 
-   
+        if let Some(session) = &movie {
+            nes.set_rng(movie_rng(session));
+        }
+        if movie.as_ref().is_some_and(|session| session.is_finished()) {
+            println!("Movie finished");
+            movie = None;
+        }
 
-    fn jmp(&mut self , mode: &AddressingMode){
-        let value = match mode {
-            &AddressingMode::Absolute => {
-                let value = self.mem_read_u16(self.program_counter);
-                value
-            }
-            &AddressingMode::Indirect => {
-                let value = self.get_operand_address(&AddressingMode::Indirect);
-                value
+        let updated = nes.run_ahead(&mut run_ahead);
+        match sync_mode {
+            SyncMode::Audio => {
+                if updated {
+                    texture.update(None, nes.snake_screen(), SCREEN_WIDTH * 3).unwrap();
+                    canvas.copy(&texture, None, None).unwrap();
+                    canvas.present();
+                }
+                frame_timer.wait(rate);
             }
-            _ => {
-                panic!("mode {:?} is not supported" , mode);
-            }
-        };
-            
-        self.program_counter = value;
-    }
-
-    fn jsr(&mut self, mode:&AddressingMode){
-        let _value = match mode  {
-            &AddressingMode::Absolute => {
-                let value = self.get_operand_address(mode);
-                value
-            }
-
-            _ => {
-                panic!("mode {:?} is not supported in jsr" , mode);
-            }
-        };
-  
-        self.push_pc();
-        self.program_counter = _value;
-    }
-    /*jump instruction ends from here */
-
-    fn rts(&mut self) {
-        self.program_counter = self.pop_pc();
-        //self.program_counter += 1;
-    }
-
-    fn rti(&mut self){
-        //pop status flags
-        self.status = self.pop_flag() & !BREAK_FLAG;
-        //bit 5 is always 1
-        self.status = self.status | INVALID_FLAG;
-
-        self.program_counter = self.pop_pc();
-    }
-
-    fn brk(&mut self) {
-      
-      self.program_counter  = self.mem_read_u16(0xFFFE);
-      self.status = self.status | BREAK_FLAG;
-
-    }
-    
-    fn pha(&mut self){
-        self.mem_write(0x0100 + (self.stackpointer as u16), self.register_a);
-        self.stackpointer = self.stackpointer - 1;
-    }
-
-    fn pla(&mut self){
-        self.stackpointer += 1;
-        self.register_a = self.mem_read(0x0100 + (self.stackpointer as u16));
-        self.update_zero_and_negative_flags(self.register_a);
-    }
-
-    fn php(&mut self){
-        self.mem_write(0x0100 + (self.stackpointer as u16), self.status | BREAK_FLAG | INVALID_FLAG );
-        self.stackpointer = self.stackpointer - 1;
-    }
-
-    fn plp(&mut self){
-        self.stackpointer += 1;
-        self.status = self.mem_read(0x0100 + (self.stackpointer as u16)) ;
-    }
-
-    fn txs(&mut self){
-        self.stackpointer = self.register_x;
-    }
-
-    fn tsx(&mut self){
-        self.register_x = self.stackpointer;
-        self.update_zero_and_negative_flags(self.register_x);
-    }
-
-    fn cli(&mut self){
-        //CLI命令による割り込み禁止フラグの更新は1命令文遅れる。次の命令が行われるのと同タイミングでフラグを更新する。
-        //self.program_counter += 1;
-        self.status = self.status & !INTERRUPT_FLAG;
-
-    }
-
-    fn sei(&mut self){
-        //CLI命令と同様に１命令文更新が遅れる。
-        //self.program_counter += 1;
-        self.status  = self.status | INTERRUPT_FLAG;
-    }
-
-    fn cld(&mut self){
-        self.status = self.status & !DECIMAL_FLAG;
-    }
-
-    fn sed(&mut self){
-        self.status = self.status | DECIMAL_FLAG;
-    }
-
-    fn clv(&mut self){
-        self.status = self.status & !OVERFLOW_FLAG;
-    }
-
-    fn nop(&mut self){
-        
-    }
-
-
-
-
-
-    fn update_zero_and_negative_flags(&mut self, result:u8) {
-        if result == 0 {
-            self.status = self.status | 0b0000_0010;
-        } else {
-            self.status = self.status & 0b1111_1101;
-        }
-
-        if result & 0b1000_0000 != 0 {
-            self.status = self.status | 0b1000_0000;
-        } else {
-            self.status  = self.status & 0b0111_1111;
-        }
-    }
-
-    pub fn run(&mut self){
-        self.run_with_callback(|_| {});
-    }
-
-    pub fn run_with_callback<F>(&mut self, mut callback:F)
-    where 
-        F: FnMut(&mut CPU),
-     {
-        loop {
-            callback(self);
-            let code = self.mem_read(self.program_counter);
-            println!("code at {:#x} is {:#x}" , self.program_counter  , code);
-            self.program_counter += 1;
-            match code {
-                /* ----------SEC is stats here --------------- */
-                0x38 => {
-                    self.sec();
-                }
-                /* ----------SEC is ends here --------------- */
-
-                /* ----------CLC is stats here --------------- */
-                0x18 => {
-                    self.clc();
-                }
-                /* ----------CLC is stats here --------------- */
-
-                /* ----------LDA is stats here --------------- */
-                0xA9 => {
-                    self.lda(&AddressingMode::Immediate);
-                    self.program_counter += 1;
-                }
-                0xA5 => {
-                    self.lda(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0xB5 => {
-                    self.lda(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0xAD => {
-                    self.lda(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                0xBD => {
-                    self.lda(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                0xB9 => {
-                    self.lda(&AddressingMode::Absolute_Y);
-                    self.program_counter += 2;
-                }
-                0xA1 => {
-                    self.lda(&AddressingMode::Indirect_X);
-                    self.program_counter += 1;
-                }
-                0xB1 => {
-                    self.lda(&AddressingMode::Indirect_Y);
-                    self.program_counter += 1;
-                }
-                /* --------- LDA is over -------------- */
-
-                /* --------- LDX starts here -------------- */
-                0xA2 => {
-                    self.ldx(&AddressingMode::Immediate);
-                    self.program_counter += 1;
-                }
-                0xA6 => {
-                    self.ldx(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0xB6 => {
-                    self.ldx(&AddressingMode::Zeropage_Y);
-                    self.program_counter += 1;
-                }
-                0xAE => {
-                    self.ldx(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                0xBE => {
-                    self.ldx(&AddressingMode::Absolute_Y);
-                    self.program_counter += 2;
-                }
-                /* --------- LDX ends over -------------- */
-
-                /* --------- LDY starts here -------------- */
-                0xA0 => {
-                    self.ldy(&AddressingMode::Immediate);
-                    self.program_counter += 1;
-                }
-                0xA4 => {
-                    self.ldy(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0xB4 => {
-                    self.ldy(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0xAC => {
-                    self.ldy(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                0xBC => {
-                    self.ldy(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                /* --------- LDY ends over -------------- */
-
-                
-
-                /* --------- STA start from here -------------- */
-
-                0x85 => {
-                    self.sta(&AddressingMode::Zeropage);
-                    self.program_counter += 1
-                }
-                0x95 => {
-                    self.sta(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1
-                }
-                0x8D => {
-                    self.sta(&AddressingMode::Absolute);
-                    self.program_counter += 2
-                }
-                0x9D => {
-                    self.sta(&AddressingMode::Absolute_X);
-                    self.program_counter += 2
-                }
-                0x99 => {
-                    self.sta(&AddressingMode::Absolute_Y);
-                    self.program_counter += 2
-                }
-                0x81 => {
-                    self.sta(&AddressingMode::Indirect_X);
-                    self.program_counter += 1
-                }
-                0x91 => {
-                    self.sta(&AddressingMode::Indirect_Y);
-                    self.program_counter += 1
-                }
-                /* --------- STA ends over -------------- */
-
-                /* --------- STX starts from here -------------- */
-
-                0x86 => {
-                    self.stx(&AddressingMode::Zeropage);
-                    self.program_counter += 1
-                }
-                0x96 => {
-                    self.stx(&AddressingMode::Zeropage_Y);
-                    self.program_counter += 1
-                }
-                0x8E => {
-                    self.stx(&AddressingMode::Absolute);
-                    self.program_counter += 2
-                }
-                
-                /* --------- STX ends here -------------- */
-
-                /* --------- STY starts from here -------------- */
-
-                0x84 => {
-                    self.sty(&AddressingMode::Zeropage);
-                    self.program_counter += 1
-                }
-                0x94 => {
-                    self.sty(&AddressingMode::Zeropage_Y);
-                    self.program_counter += 1
-                }
-                0x8C => {
-                    self.sty(&AddressingMode::Absolute);
-                    self.program_counter += 2
-                }
-                
-                /* --------- STY ends here -------------- */
-
-                /* --------- ADC starts here -------------- */
-                0x69 => {
-                    self.adc(&AddressingMode::Immediate);
-                    self.program_counter += 1;
-                }
-                0x65=> {
-                    self.adc(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0x75=> {
-                    self.adc(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0x6D=> {
-                    self.adc(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                0x7D=> {
-                    self.adc(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                0x79=> {
-                    self.adc(&AddressingMode::Absolute_Y);
-                    self.program_counter += 2;
-                }
-                0x61=> {
-                    self.adc(&AddressingMode::Indirect_X);
-                    self.program_counter += 1;
-                }
-                0x71=> {
-                    self.adc(&AddressingMode::Indirect_Y);
-                    self.program_counter += 1;
-                }
-                /* --------- ADC ends here -------------- */
-
-                /* --------- SBC starts here -------------- */
-                0xE9 => {
-                    self.sbc(&AddressingMode::Immediate);
-                    self.program_counter += 1;
-                }
-                0xE5=> {
-                    self.sbc(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0xF5=> {
-                    self.sbc(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0xED=> {
-                    self.sbc(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                0xFD=> {
-                    self.sbc(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                0xF9=> {
-                    self.sbc(&AddressingMode::Absolute_Y);
-                    self.program_counter += 2;
-                }
-                0xE1=> {
-                    self.sbc(&AddressingMode::Indirect_X);
-                    self.program_counter += 1;
-                }
-                0xF1=> {
-                    self.sbc(&AddressingMode::Indirect_Y);
-                    self.program_counter += 1;
-                }
-                /* --------- SBC ends here -------------- */
-
-                /* --------- INC starts here -------------- */
-                0xE6 => {
-                    self.inc(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0xF6 => {
-                    self.inc(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0xEE => {
-                    self.inc(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                0xFE => {
-                    self.inc(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                /* --------- INC ends here -------------- */
-
-                /* --------- DEC starts here -------------- */
-                0xC6 => {
-                    self.dec(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0xD6 => {
-                    self.dec(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0xCE => {
-                    self.dec(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                0xDE => {
-                    self.dec(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                /* --------- DEC ends here -------------- */
-
-                /* --------- tax ends here -------------- */
-                0xAA => {
-                    self.tax();
-                }
-                /* --------- tax ends here -------------- */
-
-                /* --------- txa ends here -------------- */
-                0x8A => {
-                    self.txa();
-                }
-                /* --------- txa ends here -------------- */
-
-                /* --------- tay ends here -------------- */
-                0xA8 => {
-                    self.tay();
-                }
-                /* --------- tay ends here -------------- */
-
-                /* --------- tya ends here -------------- */
-                0x98 => {
-                    self.tya();
-                }
-                /* --------- tya ends here -------------- */
-
-                /* --------- inx ends here -------------- */
-                0xE8 => {
-                    self.inx();
-                }
-                /* --------- inx ends here -------------- */
-
-                /* --------- dex ends here -------------- */
-                0xCA => {
-                    self.dex();
-                }
-                /* --------- dex ends here -------------- */
-
-                /* --------- iny ends here -------------- */
-                0xC8 => {
-                    self.iny();
-                }
-                /* --------- iny ends here -------------- */
-
-                /* --------- dey starts here -------------- */
-                0x88 => {
-                    self.dey();
-                }
-                /* --------- dey ends here -------------- */
-
-                /* --------- asl starts here -------------- */
-                0x0A => {
-                    self.asl(&AddressingMode::Accumulator);
-                }
-                0x06 => {
-                    self.asl(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0x16 => {
-                    self.asl(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0x0E => {
-                    self.asl(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                0x1E => {
-                    self.asl(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                /* --------- asl ends here -------------- */
-
-                /* --------- lsr starts here -------------- */
-                0x4A => {
-                    self.lsr(&AddressingMode::Accumulator);
-                }
-                0x46 => {
-                    self.lsr(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0x56 => {
-                    self.lsr(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0x4E => {
-                    self.lsr(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                0x5E => {
-                    self.lsr(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                /* --------- lsr ends here -------------- */
-
-                /* --------- rol starts here -------------- */
-                0x2A => {
-                    self.rol(&AddressingMode::Accumulator);
-                }
-                0x26 => {
-                    self.rol(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0x36 => {
-                    self.rol(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0x2E => {
-                    self.rol(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                0x3E => {
-                    self.rol(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                /* --------- rol ends here -------------- */
-
-                /* --------- ror starts here -------------- */
-                0x6A => {
-                    self.ror(&AddressingMode::Accumulator);
-                }
-                0x66 => {
-                    self.ror(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0x76 => {
-                    self.ror(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0x6E => {
-                    self.ror(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                0x7E => {
-                    self.ror(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                /* --------- ror ends here -------------- */
-
-                /* --------- and starts here -------------- */
-                0x29 => {
-                    self.and(&AddressingMode::Immediate);
-                    self.program_counter += 1;
-                }
-                0x25 => {
-                    self.and(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                
-                0x35 => {
-                    self.and(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0x2D => {
-                    self.and(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                
-                0x3D => {
-                    self.and(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                0x39 => {
-                    self.and(&AddressingMode::Absolute_Y);
-                    self.program_counter += 2;
-                }
-                
-                0x21 => {
-                    self.and(&AddressingMode::Indirect_X);
-                    self.program_counter += 1;
-                }
-                
-                0x31 => {
-                    self.and(&AddressingMode::Indirect_Y);
-                    self.program_counter += 1;
-                }
-                    
-                /* --------- and ends here -------------- */
-
-                /* --------- ora starts here -------------- */
-                0x09 => {
-                    self.ora(&AddressingMode::Immediate);
-                    self.program_counter += 1;
-                }
-                0x05 => {
-                    self.ora(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                
-                0x15 => {
-                    self.ora(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0x0D => {
-                    self.ora(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                
-                0x1D => {
-                    self.ora(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                0x19 => {
-                    self.ora(&AddressingMode::Absolute_Y);
-                    self.program_counter += 2;
-                }
-                
-                0x01 => {
-                    self.ora(&AddressingMode::Indirect_X);
-                    self.program_counter += 1;
-                }
-                
-                0x11 => {
-                    self.ora(&AddressingMode::Indirect_Y);
-                    self.program_counter += 1;
-                }
-                    
-                /* --------- ora ends here -------------- */
-
-                /* --------- eor starts here -------------- */
-                0x49 => {
-                    self.eor(&AddressingMode::Immediate);
-                    self.program_counter += 1;
-                }
-                0x45 => {
-                    self.eor(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                
-                0x55 => {
-                    self.eor(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0x4D => {
-                    self.eor(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                
-                0x5D => {
-                    self.eor(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                0x59 => {
-                    self.eor(&AddressingMode::Absolute_Y);
-                    self.program_counter += 2;
-                }
-                
-                0x41 => {
-                    self.eor(&AddressingMode::Indirect_X);
-                    self.program_counter += 1;
-                }
-                
-                0x51 => {
-                    self.eor(&AddressingMode::Indirect_Y);
-                    self.program_counter += 1;
-                }
-                    
-                /* --------- ora ends here -------------- */
-
-                /* --------- bit starts here -------------- */
-                0x24 => {
-                    self.bit(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0x2C => {
-                    self.bit(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-
-                /* --------- bit ends here -------------- */
-
-                /* --------- cmp starts here -------------- */
-                0xC9 => {
-                    self.cmp(&AddressingMode::Immediate);
-                    self.program_counter += 1;
-                }
-                0xC5 => {
-                    self.cmp(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0xD5 => {
-                    self.cmp(&AddressingMode::Zeropage_X);
-                    self.program_counter += 1;
-                }
-                0xCD => {
-                    self.cmp(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                0xDD => {
-                    self.cmp(&AddressingMode::Absolute_X);
-                    self.program_counter += 2;
-                }
-                0xD9 => {
-                    self.cmp(&AddressingMode::Absolute_Y);
-                    self.program_counter += 2;
-                }
-                0xC1 => {
-                    self.cmp(&AddressingMode::Indirect_X);
-                    self.program_counter += 1;
-                }
-                0xD1 => {
-                    self.cmp(&AddressingMode::Indirect_Y);
-                    self.program_counter += 1;
-                }
-                /* --------- cmp ends here -------------- */
-                /* --------- cpx starts here -------------- */
-                0xE0 => {
-                    self.cpx(&AddressingMode::Immediate);
-                    self.program_counter += 1;
-                }
-                0xE4 => {
-                    self.cpx(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0xEC => {
-                    self.cpx(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                /* --------- cpx ends here -------------- */
-                /* --------- cpy starts here -------------- */
-                0xC0 => {
-                    self.cpy(&AddressingMode::Immediate);
-                    self.program_counter += 1;
-                }
-                0xC4 => {
-                    self.cpy(&AddressingMode::Zeropage);
-                    self.program_counter += 1;
-                }
-                0xCC => {
-                    self.cpy(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                /* --------- cpy ends here -------------- */
-
-                /* --------- bcc starts here -------------- */
-                0x90 => {
-                    self.bcc(&AddressingMode::Relative);
-                    self.program_counter += 1;
-                }
-                /* --------- bcc ends here -------------- */
-
-                /* --------- bcs starts here -------------- */
-                0xB0 => {
-                    self.bcs(&AddressingMode::Relative);
-                    self.program_counter += 1;
-                }
-                /* --------- bcc ends here -------------- */
-                /* --------- beq starts here -------------- */
-                0xF0 => {
-                    self.beq(&AddressingMode::Relative);
-                    self.program_counter += 1;
-                }
-                /* --------- beq ends here -------------- */
-
-                /* --------- bne starts here -------------- */
-                0xD0 => {
-                    self.bne(&AddressingMode::Relative);
-                    self.program_counter += 1;
-                }
-                /* --------- bne ends here -------------- */
-
-                /* --------- bpl starts here -------------- */
-                0x10 => {
-                    self.bpl(&AddressingMode::Relative);
-                    self.program_counter += 1;
-                }
-                /* --------- bpl ends here -------------- */
-
-                /* --------- bmi starts here -------------- */
-                0x30 => {
-                    self.bmi(&AddressingMode::Relative);
-                    self.program_counter += 1;
-                }
-                /* --------- bmi ends here -------------- */
-
-                /* --------- bvc starts here -------------- */
-                0x50 => {
-                    self.bvc(&AddressingMode::Relative);
-                    self.program_counter += 1;
-                }
-                /* --------- bvc ends here -------------- */
-
-                /* --------- bvs starts here -------------- */
-                0x70 => {
-                    self.bvs(&AddressingMode::Relative);
-                    self.program_counter += 1;
-                }
-                /* --------- bvs ends here -------------- */
-
-                /* --------- jmp starts here -------------- */
-                0x4C => {
-                    self.jmp(&AddressingMode::Absolute);
-                    //self.program_counter += 2;
-                }
-                0x6C => {
-                    self.jmp(&AddressingMode::Absolute);
-                    //self.program_counter += 2;
-                }
-                /* --------- jmp ends here -------------- */
-
-                0x20 => {
-                    self.jsr(&AddressingMode::Absolute);
-                }
-
-                0x60 => {
-                    self.rts();
-                }
-
-                0x40 => {
-                    self.rti();
-                }
-
-                0x00 => {
-                    return;
-                }
-
-
-                0x48 => {
-                    self.pha();
-                }
-
-                0x68 => {
-                    self.pla();
-                }
-
-                0x08 => {
-                    self.php();
-                }
-
-                0x28 => {
-                    self.plp();
-                }
-
-                0x9A => {
-                    self.txs();
-                }
-
-                0xBA => {
-                    self.tsx();
-                }
-
-                0x58 => {
-                    self.cli();
-                }
-
-                0x78 => {
-                    self.sei();
-                }
-
-                0xD8 => {
-                    self.cld();
-                }
-
-                0xF8 => {
-                    self.sed();
-                }
-                
-                0xB8 => {
-                    self.clv();
-                }
-
-                0xEA => {
-                    self.nop();
-                }
-
-               
-                _ => {
-                    panic!("not yet implemented")
-                }
+            SyncMode::Video => {
+                // 毎フレームpresentして垂直同期を待ち、キューの残量は音の生成量で調整する。
+                texture.update(None, nes.snake_screen(), SCREEN_WIDTH * 3).unwrap();
+                canvas.copy(&texture, None, None).unwrap();
+                canvas.present();
+                nes.cpu_mut().bus.apu.adjust_sample_rate(1.0 / rate);
             }
         }
     }
 }
 
-
-use rand::Rng;
-use sdl2::event::Event;
-use sdl2::EventPump;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::pixels::PixelFormatEnum;
-
-
-
-fn main() {
-   let sdl_context = sdl2::init().unwrap();
-   let video_subsystem = sdl_context.video().unwrap();
-   let window = video_subsystem
-       .window("Snake game", (32.0 * 10.0) as u32, (32.0 * 10.0) as u32)
-       .position_centered()
-       .build().unwrap();
-
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(10.0, 10.0).unwrap();
-
-    let creator = canvas.texture_creator();
-    let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, 32, 32).unwrap();
-
-    let bytes:Vec<u8> = std::fs::read("snake.nes").unwrap(); 
-    let rom = Rom::new(&bytes).unwrap();
-    let bus = Bus::new(rom);
-     
-    let mut cpu = CPU::new(bus);
-    cpu.reset();
-
-    let mut screen_state = [0 as u8; 32 * 3 * 32];
-    let mut rng  = rand::thread_rng();
-
-    cpu.run_with_callback(move |cpu| {
-        handle_user_input(cpu, &mut event_pump);
-        cpu.mem_write(0xfe, rng.gen_range(1, 16));
-
-        if read_screnn_state(cpu, &mut screen_state) {
-            texture.update(None, &screen_state, 32 * 3).unwrap();
-            canvas.copy(&texture, None, None).unwrap();
-            canvas.present();
-        }
-        ::std::thread::sleep(std::time::Duration::new(0, 70_000));
-    });
-
-    
-
-    
-}
-
-fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump){
+// 終了要求があった場合はtrueを返す。
+fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump, input: &mut Input, options: &Options, requests: &mut Requests) -> bool {
     for event in event_pump.poll_iter() {
+        input.handle_event(&event);
         match event {
             Event::Quit { .. } | Event::KeyDown{keycode: Some(Keycode::Escape), ..} => {
-                std::process::exit(0)
-            },
-            Event::KeyDown {keycode :Some(Keycode::W) , ..} => {
-                cpu.mem_write(0xff, 0x77);
+                return true;
             },
-            Event::KeyDown {keycode :Some(Keycode::S) , ..} => {
-                cpu.mem_write(0xff, 0x73);
-            },
-            Event::KeyDown {keycode: Some(Keycode::A), .. } => {
-                cpu.mem_write(0xff, 0x61);
-            },
-            Event::KeyDown {keycode: Some(Keycode::D) , ..} => {
-                cpu.mem_write(0xff, 0x64);
+            //FDS: F5で次の面に入れ替え、F6でディスクを取り出す
+            Event::KeyDown {keycode: Some(Keycode::F5) , ..} => {
+                if let Some(fds) = cpu.bus.mapper_mut().as_fds_mut() {
+                    fds.switch_side();
+                }
+            }
+            Event::KeyDown {keycode: Some(Keycode::F6) , ..} => {
+                if let Some(fds) = cpu.bus.mapper_mut().as_fds_mut() {
+                    fds.eject();
+                }
+            }
+            //NSF: PageDown/PageUpで前後の曲に切り替える(←→はコントローラーの十字キー)
+            Event::KeyDown {keycode: Some(Keycode::PageDown) , ..} => {
+                change_track(cpu, true);
+            }
+            Event::KeyDown {keycode: Some(Keycode::PageUp) , ..} => {
+                change_track(cpu, false);
+            }
+            //F9で録音の開始/停止(--stemsでチャンネル別のファイルも書く)
+            Event::KeyDown {keycode: Some(Keycode::F9) , ..} => {
+                toggle_recording(cpu, &options.rom_path, options.stems);
+            }
+            //F2でリセット、F3で電源の入れ直し(ムービーに記録される)
+            Event::KeyDown {keycode: Some(Keycode::F2) , ..} => requests.commands |= COMMAND_RESET,
+            Event::KeyDown {keycode: Some(Keycode::F3) , ..} => requests.commands |= COMMAND_POWER,
+            Event::KeyDown {keycode: Some(Keycode::F10) , ..} => requests.toggle_movie = true,
+            Event::KeyDown {keycode: Some(Keycode::F11) , ..} => requests.toggle_read_only = true,
+            Event::KeyDown {keycode: Some(Keycode::F1) , ..} => requests.save_state = true,
+            Event::KeyDown {keycode: Some(Keycode::F4) , ..} => requests.load_state = true,
+            Event::KeyDown {keycode: Some(keycode), keymod, ..} if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                requests.select_slot = state_slot_key(keycode);
             }
             _ => {  /*Do nothing */}
         }
     }
+    false
 }
 
-fn read_screnn_state(cpu: &CPU, frame:&mut [u8;32 * 3 * 32]) -> bool {
-    let mut frame_idx = 0;
-    let mut update = false;
-    for i in 0x0200 .. 0x600 {
-        let color_idx = cpu.mem_read(i as u16);
-        let (b1 , b2 , b3) = color(color_idx).rgb();
-        if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
-            frame[frame_idx] = b1;
-            frame[frame_idx + 1] = b2;
-            frame[frame_idx + 2] = b3;
-            update = true;
-        }
-        frame_idx += 3;
-    }
-    update
-}
-
-fn color(byte: u8) -> Color {
-
-    match byte {
-        0 => sdl2::pixels::Color::BLACK,
-        1 => sdl2::pixels::Color::WHITE,
-        2 | 9 => sdl2::pixels::Color::GRAY,
-        3 | 10 => sdl2::pixels::Color::RED,
-        4 | 11 => sdl2::pixels::Color::GREEN,
-        5 | 12 => sdl2::pixels::Color::BLUE,
-        6 | 13 => sdl2::pixels::Color::MAGENTA,
-        7 | 14 => sdl2::pixels::Color::YELLOW,
-        _ => sdl2::pixels::Color::CYAN,
-    }
-
-}
-
-/* 
-
-#[cfg(test)]
-mod test {
-    use super::*;//superは親モジュールを指す。ここでの親モジュールとはファイル全体？
-
-    #[test]
-    fn test_0xa9_lda_immediate_load_data() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x05,0x00]);
-        assert_eq!(cpu.register_a , 0x05);
-        assert!(cpu.status & 0b0000_0010 == 0);
-        assert!(cpu.status & 0b1000_0000 == 0); //assert!マクロは中身がTRUEなら問題ナシ
-    }
-
-    #[test]
-    fn test_0xa5_lda_zeropage_load_data() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x10,0x85,0x01,0xa5,0x01,0x00]);
-        assert_eq!(cpu.register_a , 0x10);
-        assert!(cpu.status & 0b0000_0010 == 0);
-        assert!(cpu.status & 0b1000_0000 == 0); //assert!マクロは中身がTRUEなら問題ナシ
-    }
-
-
-
-
-    #[test]
-    fn test_0x09_lda_zero_flag() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x00,0x00]);
-        assert_eq!(cpu.register_a , 0x00);
-        assert!(cpu.status & 0b0000_0010 == 0b10 );
-    }
-
-    #[test]
-    fn test_0xaa_tax_move_a_to_x() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x05,0xAA,0x00]);
-        assert_eq!(cpu.register_x , 0x05);
-    }
-
-    #[test]
-    fn test_5_ops_woriking_together() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0xc0,0xaa,0xe8,0x00]);
-        assert_eq!(cpu.register_x, 0xc1);
-    }
-
-    #[test]
-    fn inx_overflow_check() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0xff,0xaa,0xe8,0xe8,0x00]);
-        println!(" register_x is {:?}" , cpu.register_x);
-        assert_eq!(cpu.register_x, 0x01);
-    }
-
-    #[test]
-    fn test_0x69_adc_immediate() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x02,0x69,0x50,0x85,0x01,0x00]);
-        println!(" accumulator is {:?}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x52);
-    }
-
-    #[test]
-    fn test_0x69_adc_immediate_overflow() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x50,0x69,0x50,0x85,0x01,0x00]);
-        println!(" accumulator is {:?}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0xa0);
-        assert!(cpu.status  & 0b01000000  == 0b0100_0000);
-    }
-
-    #[test]
-    fn test_0x69_adc_immediate_overflow_ver2() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0xd0,0x69,0x90,0x85,0x01,0x00]);
-        println!(" accumulator is {:?}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x60);
-        assert!(cpu.status  & 0b01000000  == 0b0100_0000);
-    }
-
-    #[test]
-    fn test_0x69_adc_immediate_overflow_ver3() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x38,0xa9,0x50,0x69,0x50,0x00]);
-        println!(" accumulator is {:?}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0xa1);
-        println!(" status is {:b}" , cpu.status );
-        assert!(cpu.status  & 0b11000001  == 0b1100_0000);
-
-    }
-
-    #[test]
-    fn test_0x69_adc_immediate_carry() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x38,0xa9,0x50,0x69,0xd0,0x00]);
-        println!(" accumulator is {:?}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x21);
-        println!(" status is {:b}" , cpu.status );
-        assert!(cpu.status  & 0b11000001  == 0b0000_0001);
-
-    }
-
-    #[test]
-    fn test_0xe9_adc_immediate_notoverflow() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x38,0xa9,0x50,0xe9,0x10,0x00]);
-        println!(" accumulator is {:?}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x40);
-    }
-    #[test]
-    fn test_0xe9_adc_immediate_overflow() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x38,0xa9,0x50,0xe9,0xb0,0x00]);
-        println!(" accumulator is {:?}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0xa0);
-        println!(" status is {:b}" , cpu.status as u8);
-        assert!(cpu.status & 0b1100_0001 == 0b1100_0000)
-    }
-
-    #[test]
-    fn test_0xe9_adc_immediate_overflow_ver4() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x38,0xa9,0x50,0xe9,0xb0,0x00]);
-        println!(" register_x is {:?}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0xa0);
-        println!(" status is {:b}" , cpu.status as u8);
-        assert!(cpu.status & 0b1100_0001 == 0b1100_0000)
-    }
-
-    #[test]
-    fn test_0x8e_stx_absolute() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa2,0x50,0x8e,0x00,0xff,0x00]);
-        println!(" register_x is {:0x}" , cpu.register_x as u8);
-        assert_eq!(cpu.register_x, 0x50);
-        assert_eq!(cpu.mem_read(0xff00), 0x50);
-    }
-
-    #[test]
-    fn test_0xa0_ldy_and_0x8c_sty_absolute() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa0,0x60,0x8c,0x00,0xff,0x00]);
-        println!(" register_y is {:0x}" , cpu.register_y as u8);
-        assert_eq!(cpu.register_y, 0x60);
-        assert_eq!(cpu.mem_read(0xff00), 0x60)
-    }
-
-    #[test]
-    fn test_0xaa_tax() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x60,0xaa,0x00]);
-        println!(" register_x is {:0x}" , cpu.register_x as u8);
-        assert_eq!(cpu.register_x, 0x60);
-    }
-
-    #[test]
-    fn test_0x8a_txa() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa2,0x70,0x8a,0x00]);
-        println!(" register_a is {:0x}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x70);
-    }
-
-    #[test]
-    fn test_0xa8_tay() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x80,0xa8,0x00]);
-        println!(" register_y is {:0x}" , cpu.register_y as u8);
-        assert_eq!(cpu.register_y, 0x80);
-    }
-
-    #[test]
-    fn test_0xa0_ldy_0x98_tya() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa0,0x90,0x98,0x00]);
-        println!(" register_a is {:0x}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x90);
-    }
-
-    #[test]
-    fn test_0xee_inc_absolute() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa2,0xff,0x8e,0x00,0x10,0xee,0x00,0x10]);
-        println!(" register_x is {:0x}" , cpu.register_x as u8);
-        println!(" memory[0x1000] is {:0x}" , cpu.mem_read(0x1000) as u8);
-        assert_eq!(cpu.mem_read(0x1000), 0x00);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b0000_0010, 0b0000_0010);
-    }
-
-    #[test]
-    fn test_0xce_decc_absolute() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa2,0xff,0x8e,0x00,0x10,0xce,0x00,0x10]);
-        println!(" register_x is {:0x}" , cpu.register_x as u8);
-        println!(" memory[0x1000] is {:0x}" , cpu.mem_read(0x1000) as u8);
-        assert_eq!(cpu.mem_read(0x1000), 0xfe);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0010, 0b1000_0000);
-    }
-
-    #[test]
-    fn test_0xce_dec_absolute_ver2() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa2,0x00,0x8e,0x00,0x10,0xce,0x00,0x10]);
-        println!(" register_x is {:0x}" , cpu.register_x as u8);
-        println!(" memory[0x1000] is {:0x}" , cpu.mem_read(0x1000) as u8);
-        assert_eq!(cpu.mem_read(0x1000), 0xff);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0010, 0b1000_0000);
-    }
-
-    #[test]
-    fn test_0xe8_inx_0xca_dex() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa2,0x00,0xe8,0xca,0x00]);
-        println!(" register_x is {:0x}" , cpu.register_x as u8);
-        assert_eq!(cpu.register_x,0x00);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0010, 0b0000_0010);
-    }
-
-    #[test]
-    fn test_0xc8_iny_0x88_dey() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa0,0x00,0xc8,0x88,0x00]);
-        println!(" register_y is {:0x}" , cpu.register_y as u8);
-        assert_eq!(cpu.register_y,0x00);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0010, 0b0000_0010);
-    }
-
-    #[test]
-    fn test_0x0a_accumulator() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x38,0xa9,0x2a,0x0a,0x00]);
-        println!(" register_a is {:0x}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x54);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b0000_0000);
-    }
-
-    #[test]
-    fn test_0x0e_asl_absolute() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x38,0xa9,0x2a,0x8d,0x10,0x00,0x0e,0x10,0x00,0x00]);
-        println!(" memory[0x0010] is {:0x}" , cpu.mem_read(0x0010) as u8);
-        assert_eq!(cpu.mem_read(0x0010), 0x54);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b0000_0000);
-    }
-
-    #[test]
-    fn test_0x0a_asl_accumulator_carry() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x18,0xa9,0x80,0x0a,0x00]);
-        println!(" register_a is {:0x}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x00);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b0000_0011);
-    }
-
-    #[test]
-    fn test_0x4e_lsr_accumulator() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x38,0xa9,0x2a,0x8d,0x10,0x00,0x4e,0x10,0x00,0x00]);
-        println!(" memory[0x0010] is {:0x}" , cpu.mem_read(0x0010) as u8);
-        assert_eq!(cpu.mem_read(0x0010), 0x15);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b0000_0000);
-    }
-
-    #[test]
-    fn test_0x4a_lsr_accumulator_with_carry() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x18,0xa9,0x01,0x4a,0x00]);
-        println!(" register_a is {:0x}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x00);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b0000_0011);
-    }
-
-    #[test]
-    fn test_0x2a_rol_accumulator() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x38,0xa9,0x2a,0x2a,0x00]);
-        println!(" register_a is {:0x}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x55);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b0000_0000);
-    }
-
-    #[test] //overflow_multiple ?
-    fn test_0x2a_rol_accumulator_with_no_carry() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x18,0xa9,0x80,0x2a,0x00]);
-        println!(" register_a is {:0x}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x00);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b0000_0011);
-    }
-
-    #[test] //overflow_multiple ?
-    fn test_0x2a_rol_accumulator_with_carry() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x38,0xa9,0x00,0x2a,0x00]);
-        println!(" register_a is {:0x}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x01);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b0000_0000);
-    }
-
-    #[test] //overflow_multiple ?
-    fn test_0x6a_ror_accumulator_with_zero() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x18,0xa9,0x01,0x6a,0x00]);
-        println!(" register_a is {:0x}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x00);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b0000_0011);
-    }
-    
-
-    #[test] 
-    fn test_0x29_and_absolute() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0xaa,0x8d,0x10,0x00,0xa9,0x5d,0x2d,0x10,0x00,0x00]);
-        println!(" register_a is {:0x}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x08);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b0000_0000);
-    }
-
-    #[test] 
-    fn test_0x29_and_absolute_negative_flag() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0xaa,0x8d,0x10,0x00,0xa9,0xd5,0x2d,0x10,0x00,0x00]);
-        println!(" register_a is {:0x}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x80);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b1000_0000);
-    }
-    #[test] 
-    fn test_0x0d_ora_absolute() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0xaa,0x8d,0x10,0x00,0xa9,0x55,0x0d,0x10,0x00,0x00]);
-        println!(" register_a is {:0x}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0xff);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b1000_0000);
-    }
-
-    #[test] 
-    fn test_0x4d_eor_absolute_negative_flag() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x80,0x8d,0x10,0x00,0xa9,0x01,0x4d,0x10,0x00,0x00]);
-        println!(" register_a is {:0x}" , cpu.register_a as u8);
-        assert_eq!(cpu.register_a, 0x81);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b1000_0000);
-    }
-
-    #[test] 
-    fn test_0x2c_bit_zero_flag() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x80,0x2c,0x00]);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1000_0011, 0b0000_0010);
-    }
-
-    #[test] 
-    fn test_0x2c_bit_absolute_clear_zero_flag() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x40,0x8d,0x10,0x00,0xa9,0x00,0x2c,0x10,0x00,0x00]);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1100_0011, 0b0100_0010);
-    }
-
-    #[test] 
-    fn test_0xcd_cmp_absolute_with_carry_and_zero_flag() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x40,0x8d,0x10,0x00,0xcd,0x10,0x00,0x00]);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1100_0011, 0b0000_0011);
-    }
-
-    #[test] 
-    fn test_0xec_cpx_absolute_with_carry_and_zero_flag() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa2,0x40,0x8e,0x10,0x00,0xec,0x10,0x00,0x00]);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1100_0011, 0b0000_0011);
-    }
-    #[test] 
-    fn test_0xcc_cpy_absolute_with_carry_and_zero_flag() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa0,0x40,0x8c,0x10,0x00,0xcc,0x10,0x00,0x00]);
-        println!(" status  is {:0b}" , cpu.status);
-        assert_eq!(cpu.status & 0b1100_0011, 0b0000_0011);
-    }
-
-    #[test]
-    fn test_0x90_bcc_not_carry() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x90,0x02,0xe8,0xe8,0xe8,0x00]);
-        assert_eq!(cpu.register_x, 1);
-    }
-
-    #[test]
-    fn test_0x90_bcc_wiht_carry() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x38,0x90,0x02,0xe8,0xe8,0xe8,0x00]);
-        assert_eq!(cpu.register_x, 3);
-    }
-
-    #[test]
-    fn test_0x90_bcc_wiht_no_carry_minus() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x00,0x8d,0xff,0x7f,0xa9,0xe8,0x8d,0xfe,0x7f,0x90,0xf2,0x00]);
-
-        println!("register_x is {}" , cpu.register_x);
-        assert_eq!(cpu.register_x,1)
-    }
-
-    #[test]
-    fn test_0xf0_beq_relative() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9 ,0x00 ,0xf0 ,0x03 ,0xa9 ,0x6,0x00 ,0xa9 ,0x50 ,0x00]);
-        assert_eq!(cpu.register_a,0x50);
-    }
-
-    #[test]
-    fn test_0x4c_jmp_absolute() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9,0x00,0x8d,0xff,0x7f,0xa9,0xe8,0x8d,0xfe,0x7f,0x4c,0xfe,0x7f,0x00]);
-
-        println!("register_x is {}" , cpu.register_x);
-        assert_eq!(cpu.register_x,1)
-    }
-
-    
-    #[test]
-    fn test_0x20_jsr_absolute() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x20 ,0x06 ,0x06 ,0xa9 ,0x50 ,0x00 ,0xa2 ,0x50 ,0x00]);
-        println!("register_x is {}" , cpu.register_x as u8);
-        println!("register_a is {}" , cpu.register_a );
-        println!("program counter is {}" , cpu.program_counter);
-        assert_eq!(cpu.register_x,0x50);
-        assert_eq!(cpu.register_a, 0);
-    }
-
-    #[test]
-    fn test_0x20_jsr_absolute_and_0x60_rts() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x20 ,0x06 ,0x06 ,0xa9 ,0x50 ,0x00 ,0xa2 ,0x50 ,0x60]);
-        println!("register_x is {}" , cpu.register_x as u8);
-        println!("register_a is {}" , cpu.register_a );
-        println!("program counter is {}" , cpu.program_counter);
-        println!("(0x01ff) is {}" , cpu.mem_read(0x01ff));
-        println!("(0x01fe) is {}" , cpu.mem_read(0x01fe));
-        assert_eq!(cpu.register_x,0x50);
-        assert_eq!(cpu.register_a, 0x50);
-        assert_eq!(cpu.mem_read(0x1ff),0x06);
-        assert_eq!(cpu.mem_read(0x1fe),0x03);
-    }
-
-    #[test]
-    fn test_0x48_pha() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9 ,0x50 ,0x48 ,0xa9 ,0x05 ,0x48,0x00]);
-        println!("stack pointer is {}", cpu.stackpointer);
-        assert_eq!(cpu.mem_read(0x01ff), 0x50);
-        assert_eq!(cpu.mem_read(0x1fe), 0x05);
-        assert_eq!(cpu.stackpointer,0xfd);
-    }
-
-    #[test]
-    fn test_0x68_pla() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9 ,0x50 ,0x48 ,0xa9 ,0x05 ,0x48,0x68,0x68,0x00]);
-        println!("stack pointer is {}", cpu.stackpointer);
-        assert_eq!(cpu.register_a, 0x50);
-        assert_eq!(cpu.stackpointer,0xff);
-    }
-
-    #[test]
-    fn test_0x08_php() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x38 ,0x08 ,0x00]);
-        println!("stack pointer is {}", cpu.stackpointer);
-        assert_eq!(cpu.mem_read(0x1ff), 0x31);
-        assert_eq!(cpu.stackpointer,0xfe);
-    }
-
-    #[test]
-    fn test_0x28_plp() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x38 ,0x08,0x18,0x28 ,0x00]);
-        println!("stack pointer is {}", cpu.stackpointer);
-        println!("cpu status is {}", cpu.status);
-        assert_eq!(cpu.mem_read(0x1ff), 0x31);
-        assert_eq!(cpu.status , 0b00110001);
-        assert_eq!(cpu.stackpointer,0xff);
-    }
-
-    #[test]
-    fn test_0x9a_txs() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa2 ,0x50 ,0x9a ,0x00]);
-        assert_eq!(cpu.stackpointer,0x50);
-    }
-    #[test]
-    fn test_0xba_tsx() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa2 ,0x50 ,0x9a,0xa2,0x40,0xba ,0x00]);
-        assert_eq!(cpu.register_x,0x50);
-    }
-
-    #[test]
-    fn test_0x78_sei() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x78 ,0x00]);
-        assert_eq!(is_flag_set(INTERRUPT_FLAG, cpu.status),true);
-    }
-
-    #[test]
-    fn test_0x58_cli() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x78,0x58 ,0x00]);
-        assert_eq!(is_flag_set(INTERRUPT_FLAG, cpu.status),false);
-    }
-
-    #[test]
-    fn test_0xf8_sed() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xf8,0x00]);
-        assert_eq!(is_flag_set(DECIMAL_FLAG, cpu.status),true);
-    }
-
-    #[test]
-    fn test_0xd8_cld() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xf8,0xd8,0x00]);
-        assert_eq!(is_flag_set(DECIMAL_FLAG, cpu.status),false);
-    }
-
-    #[test]
-    fn test_0xb8_clv() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9 ,0x7f ,0x18, 0x69, 0x01 ,0x8d ,0x00 ,0x02,0xb8,0x00]);
-        assert_eq!(is_flag_set(OVERFLOW_FLAG, cpu.status),false);
-    }
-
-    #[test]
-    fn test_push_pc() {
-        let mut cpu = CPU::new();
-        cpu.program_counter = 0x0606;
-        cpu.push_pc();
-        assert_eq!(cpu.pop_pc(),0x0608);
-    }
-
-    
+// Ctrl+0〜9のスロット番号
+fn state_slot_key(keycode: Keycode) -> Option<usize> {
+    let name = keycode.name();
+    let slot = name.parse::<usize>().ok()?;
+    (name.len() == 1 && slot < STATE_SLOTS).then_some(slot)
 }
-    */
\ No newline at end of file